@@ -0,0 +1,138 @@
+// Compares the dense client table of the processor against the HashMap it replaced
+//
+// Run with `cargo bench --bench clients`. Zero dependencies: every case is timed with std::time::Instant
+// over enough rounds to take a few hundred milliseconds, and the mean time per round is printed.
+// The first cases replicate both layouts on their own (the table being private to the processor),
+// the last ones time the processor itself
+
+use lib::{CsvReport, PaymentsProcessor, PaymentsTransaction, ReportWriter};
+use std::collections::{BTreeSet, HashMap};
+use std::hint::black_box;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+const BUDGET: Duration = Duration::from_millis(300);
+
+//stands in for the state of a client, boxed in both layouts as the processor does
+#[derive(Default)]
+struct State {
+	available: i128,
+	held: i128,
+	locked: bool,
+}
+
+//the slots along with the index of the IDs in use
+struct Dense(Vec<Option<Box<State>>>, BTreeSet<u16>);
+
+impl Dense {
+	fn new() -> Self {
+		Dense((0..=u16::MAX).map(|_| None).collect(), BTreeSet::new())
+	}
+
+	fn insert(&mut self, client: u16) {
+		self.0[client as usize] = Some(Box::default());
+		self.1.insert(client);
+	}
+
+	fn get(&self, client: u16) -> Option<&State> {
+		self.0[client as usize].as_deref()
+	}
+
+	fn iter(&self) -> impl Iterator<Item = (u16, &State)> {
+		self
+			.1
+			.iter()
+			.filter_map(|&client| Some((client, self.0[client as usize].as_deref()?)))
+	}
+}
+
+//client IDs spread over the whole range, in no particular order
+fn clients(n: usize) -> Vec<u16> {
+	let step = 0x10000 / n;
+	(0..n)
+		.map(|i| ((i * step) as u16).wrapping_mul(40503))
+		.collect()
+}
+
+//times f until the budget is spent, printing the mean time of a round
+fn bench(name: &str, mut f: impl FnMut()) {
+	f();
+	let started = Instant::now();
+	let mut rounds = 0u32;
+	while started.elapsed() < BUDGET {
+		f();
+		rounds += 1;
+	}
+	println!("{:<48} {:>12.3?}", name, started.elapsed() / rounds);
+}
+
+fn lookups(n: usize) {
+	let ids = clients(n);
+	let mut dense = Dense::new();
+	let mut hashed = HashMap::new();
+	for &id in &ids {
+		dense.insert(id);
+		hashed.insert(id, Box::<State>::default());
+	}
+	bench(&format!("lookup {} clients, dense", n), || {
+		for &id in &ids {
+			black_box(dense.get(black_box(id)));
+		}
+	});
+	bench(&format!("lookup {} clients, HashMap", n), || {
+		for &id in &ids {
+			black_box(hashed.get(&black_box(id)));
+		}
+	});
+	//what reporting costs before rendering anything: visiting every client in ID order
+	bench(&format!("ordered walk {} clients, dense", n), || {
+		for (id, state) in dense.iter() {
+			black_box((id, state.available, state.held, state.locked));
+		}
+	});
+	bench(&format!("ordered walk {} clients, HashMap", n), || {
+		let mut sorted: Vec<_> = hashed.iter().collect();
+		sorted.sort_unstable_by_key(|(id, _)| **id);
+		for (id, state) in sorted {
+			black_box((id, state.available, state.held, state.locked));
+		}
+	});
+}
+
+fn processor(n: usize) {
+	let deposits: Vec<_> = clients(n)
+		.into_iter()
+		.enumerate()
+		.map(|(i, id)| PaymentsTransaction::from_str(&format!("deposit, {}, {}, 1.5", id, i)).unwrap())
+		.collect();
+	bench(&format!("process {} deposits", n), || {
+		let mut pr = PaymentsProcessor::default();
+		for tx in &deposits {
+			let _ = pr.process_transaction(black_box(tx.clone()));
+		}
+		black_box(pr);
+	});
+	let mut pr = PaymentsProcessor::default();
+	for tx in &deposits {
+		pr.process_transaction(tx.clone()).unwrap();
+	}
+	//the buffer is kept so that only the report itself is timed
+	let mut out = String::new();
+	bench(&format!("report {} clients, streamed", n), || {
+		out.clear();
+		pr.write_report(&CsvReport, &mut out).unwrap();
+		black_box(&out);
+	});
+	bench(&format!("report {} clients, collected", n), || {
+		out.clear();
+		CsvReport.write_report(&mut out, &pr.statements()).unwrap();
+		black_box(&out);
+	});
+}
+
+fn main() {
+	for n in [100, 10_000, 65_536] {
+		lookups(n);
+		processor(n);
+	}
+}
@@ -0,0 +1,289 @@
+// The control interface of the servers, over a Unix domain socket so that it is never exposed on the network
+//
+// One command per line, answered by one reply each, in order:
+//   `pause`, `resume`                   stop and restart ingestion, transactions sent meanwhile wait
+//   `snapshot <name>`                   sets a savepoint the books can be restored to (16 at most, the
+//                                       oldest being released once they keep 1000000 transactions undoable)
+//   `restore <name>`                    undoes every transaction applied since the savepoint (`ok <n>` undone)
+//   `release <name>`                    forgets the savepoint
+//   `lock <client>`, `unlock <client>`  locks a client on behalf of the operator, or lifts any lock
+//   `metrics`                           `key=value` lines, followed by an empty line (`pinned` being the
+//                                       number of transactions kept undoable)
+// Failures are answered with `err <kind>`, a line longer than the tolerance closes the connection
+
+use crate::input::kind;
+use crate::status::Failure;
+use crate::summary::Histogram;
+use lib::{PaymentsProcessor, SpamReaderError, SpamTolerantReader};
+use std::io::{BufWriter, Write};
+use std::num::NonZeroUsize;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Instant;
+
+//longest command accepted, savepoint names included
+#[doc(hidden)]
+const TOLERANCE: usize = 256;
+
+//snapshots the operators may hold at once, each one keeping every transaction applied since undoable
+#[doc(hidden)]
+const MAX_SNAPSHOTS: usize = 16;
+
+//transactions the snapshots may keep undoable, beyond it the oldest snapshot is released so that
+//a forgotten one does not grow the memory of a long running server without bound
+#[doc(hidden)]
+const MAX_PINNED: usize = 1_000_000;
+
+//a thread panicking with a lock held may have left the books half updated
+#[doc(hidden)]
+pub(crate) const POISONED: &str = "ProcessorPoisoned";
+
+/// What the ingestion paths of the servers share with the control interface: whether ingestion
+/// is paused and how the transactions received went
+pub(crate) struct Control {
+	paused: Mutex<bool>,
+	resumed: Condvar,
+	applied: AtomicU64,
+	//the transactions that could not be read or applied, by kind of error
+	rejected: Mutex<Histogram>,
+	started: Instant,
+}
+
+impl Control {
+	pub(crate) fn new() -> Self {
+		Control {
+			paused: Mutex::new(false),
+			resumed: Condvar::new(),
+			applied: AtomicU64::new(0),
+			rejected: Mutex::new(Histogram::new()),
+			started: Instant::now(),
+		}
+	}
+
+	fn is_paused(&self) -> bool {
+		self.paused.lock().is_ok_and(|x| *x)
+	}
+
+	//the flag is set with the books locked, so that no transaction is applied once `pause` is answered
+	fn set_paused(&self, pr: &Mutex<PaymentsProcessor>, paused: bool) -> Result<(), String> {
+		let _pr = pr.lock().map_err(|_| POISONED.to_owned())?;
+		*self.paused.lock().map_err(|_| POISONED.to_owned())? = paused;
+		self.resumed.notify_all();
+		Ok(())
+	}
+
+	/// Runs **f** on the books once ingestion is not paused, None when the books are poisoned
+	pub(crate) fn ingest<T>(
+		&self,
+		pr: &Mutex<PaymentsProcessor>,
+		f: impl FnOnce(&mut PaymentsProcessor) -> T,
+	) -> Option<T> {
+		loop {
+			let paused = self.paused.lock().ok()?;
+			drop(self.resumed.wait_while(paused, |x| *x).ok()?);
+			//paused again before the books could be locked
+			let mut pr = pr.lock().ok()?;
+			if !self.is_paused() {
+				let res = f(&mut pr);
+				let released = expire(&mut pr);
+				//logged once the books are unlocked, a failing stderr has no business poisoning them
+				drop(pr);
+				for name in released {
+					let _ = writeln!(
+						std::io::stderr(),
+						"Snapshot `{}` released, it kept more than {} transactions undoable",
+						name,
+						MAX_PINNED
+					);
+				}
+				return Some(res);
+			}
+		}
+	}
+
+	/// Counts a transaction received, applied or rejected with the kind of its error
+	pub(crate) fn record(&self, res: &Result<(), String>) {
+		match res {
+			Ok(()) => {
+				self.applied.fetch_add(1, Ordering::Relaxed);
+			}
+			Err(kind) => {
+				if let Ok(mut rejected) = self.rejected.lock() {
+					*rejected.entry(kind.clone()).or_default() += 1;
+				}
+			}
+		}
+	}
+
+	fn metrics(&self, pr: &PaymentsProcessor) -> String {
+		let rejected = self.rejected.lock().map(|x| x.clone()).unwrap_or_default();
+		let statements = pr.statements();
+		let mut clients: Vec<_> = statements.iter().map(|x| x.client).collect();
+		clients.dedup();
+		let fields = [
+			("paused", self.is_paused().to_string()),
+			("uptime_ms", self.started.elapsed().as_millis().to_string()),
+			("applied", self.applied.load(Ordering::Relaxed).to_string()),
+			("rejected", rejected.values().sum::<usize>().to_string()),
+			("clients", clients.len().to_string()),
+			(
+				"locked_clients",
+				statements.iter().filter(|x| x.locked).count().to_string(),
+			),
+			("postings", pr.postings().to_string()),
+			("snapshots", pr.savepoints().to_string()),
+			("pinned", pr.undoable().to_string()),
+			("balanced", pr.is_balanced().to_string()),
+			("fault", pr.fault().map_or("none".to_owned(), kind)),
+		];
+		fields
+			.iter()
+			.map(|(key, value)| format!("{}={}\n", key, value))
+			.chain(
+				rejected
+					.iter()
+					.map(|(kind, n)| format!("rejected.{}={}\n", kind, n)),
+			)
+			.collect()
+	}
+}
+
+//releases the oldest snapshots while they keep too many transactions undoable, returning their names
+#[doc(hidden)]
+fn expire(pr: &mut PaymentsProcessor) -> Vec<String> {
+	let mut released = Vec::new();
+	while pr.undoable() > MAX_PINNED {
+		match pr.expire_oldest_savepoint() {
+			Some(name) => released.push(name),
+			None => break,
+		}
+	}
+	released
+}
+
+/// Listens on the Unix domain socket, serving the operators on their own threads
+#[cfg(unix)]
+pub(crate) fn listen(
+	path: &str,
+	pr: Arc<Mutex<PaymentsProcessor>>,
+	control: Arc<Control>,
+) -> Result<(), Failure> {
+	use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+	use std::os::unix::net::{UnixListener, UnixStream};
+
+	let failure = |e: std::io::Error| {
+		Failure::io(format!(
+			"Could not listen on the admin socket `{}`: [{}]",
+			path, e
+		))
+	};
+	//a socket left behind by a server that was killed refuses connections, and can be replaced.
+	//Anything else at the path is left alone, connecting to a regular file is refused as well
+	match std::fs::symlink_metadata(path).map(|x| x.file_type().is_socket()) {
+		Ok(false) => {
+			return Err(Failure::io(format!(
+				"Could not listen on the admin socket `{}`: [the path exists and is not a socket]",
+				path
+			)))
+		}
+		Ok(true)
+			if UnixStream::connect(path)
+				.is_err_and(|e| e.kind() == std::io::ErrorKind::ConnectionRefused) =>
+		{
+			std::fs::remove_file(path).map_err(failure)?;
+		}
+		_ => (),
+	}
+	let listener = UnixListener::bind(path).map_err(failure)?;
+	//the operators only, whatever the umask
+	std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)).map_err(failure)?;
+	std::thread::spawn(move || {
+		for stream in listener.incoming() {
+			match stream {
+				Ok(stream) => {
+					let (pr, control) = (pr.clone(), control.clone());
+					std::thread::spawn(move || {
+						if let Err(e) = connection(stream, &pr, &control) {
+							eprintln!("Admin connection dropped: [{}]", e);
+						}
+					});
+				}
+				Err(e) => eprintln!("Could not accept an admin connection: [{}]", e),
+			}
+		}
+	});
+	Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn listen(
+	_path: &str,
+	_pr: Arc<Mutex<PaymentsProcessor>>,
+	_control: Arc<Control>,
+) -> Result<(), Failure> {
+	Err(Failure::new(
+		crate::status::Status::Usage,
+		"Admin sockets are only supported on Unix",
+	))
+}
+
+#[cfg(unix)]
+#[doc(hidden)]
+fn connection(
+	stream: std::os::unix::net::UnixStream,
+	pr: &Mutex<PaymentsProcessor>,
+	control: &Control,
+) -> std::io::Result<()> {
+	let tolerance = NonZeroUsize::new(TOLERANCE).expect("non zero tolerance");
+	let mut reader = SpamTolerantReader::new(stream.try_clone()?, b'\n', tolerance);
+	let mut writer = BufWriter::new(stream);
+	loop {
+		let reply = match reader.get_next() {
+			Err(SpamReaderError::EOFReached) => return Ok(()),
+			//retry according to https://doc.rust-lang.org/std/io/trait.Read.html#tymethod.read
+			Err(SpamReaderError::IOError(std::io::ErrorKind::Interrupted)) => continue,
+			Err(SpamReaderError::IOError(e)) => return Err(e.into()),
+			Err(SpamReaderError::ToleranceExceeded) => {
+				writer.write_all(b"err ToleranceExceeded\n")?;
+				return writer.flush();
+			}
+			Ok(line) => match command(&String::from_utf8_lossy(line), pr, control) {
+				Ok(reply) => reply,
+				Err(kind) => format!("err {}\n", kind),
+			},
+		};
+		writer.write_all(reply.as_bytes())?;
+		writer.flush()?;
+	}
+}
+
+//the reply to a single command, newline included, or the kind of error it failed with
+#[doc(hidden)]
+fn command(line: &str, pr: &Mutex<PaymentsProcessor>, control: &Control) -> Result<String, String> {
+	const OK: &str = "ok\n";
+	let words: Vec<_> = line.split_whitespace().collect();
+	let client = |x: &str| u16::from_str(x).map_err(|_| "InvalidClient".to_owned());
+	let books = || pr.lock().map_err(|_| POISONED.to_owned());
+	match words[..] {
+		["pause"] => control.set_paused(pr, true)?,
+		["resume"] => control.set_paused(pr, false)?,
+		["snapshot", name] => {
+			let mut pr = books()?;
+			if pr.savepoints() >= MAX_SNAPSHOTS {
+				return Err("TooManySnapshots".to_owned());
+			}
+			pr.savepoint(name).map_err(|e| kind(&e))?
+		}
+		["restore", name] => {
+			let undone = books()?.undo_to_savepoint(name).map_err(|e| kind(&e))?;
+			return Ok(format!("ok {}\n", undone));
+		}
+		["release", name] => books()?.release_savepoint(name).map_err(|e| kind(&e))?,
+		["lock", id] => books()?.lock_client(client(id)?).map_err(|e| kind(&e))?,
+		["unlock", id] => books()?.unlock_client(client(id)?).map_err(|e| kind(&e))?,
+		["metrics"] => return Ok(control.metrics(&*books()?) + "\n"),
+		_ => return Err("UnknownCommand".to_owned()),
+	}
+	Ok(OK.to_owned())
+}
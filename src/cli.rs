@@ -0,0 +1,427 @@
+// Command line parsing, kept free of any processing so that every mistake is reported before
+// a single transaction is read
+
+use lib::{FixedDecimal, FixedDecimalMAXDISPLEN, IdempotencyKeyMAXLEN, UnknownFields};
+use std::num::NonZeroUsize;
+use std::str::FromStr;
+
+const MAX_TRANSACTION_LEN: usize =
+	"withdrawal".len() + 5 /*u16 max digits*/ + 10 /*u32 max digits*/+ FixedDecimalMAXDISPLEN + 2 * 3 /*ISO 4217 codes*/ + IdempotencyKeyMAXLEN + 1 /*\n*/;
+
+pub(crate) const USAGE: &str = "\
+mini-payments, a toy payments engine
+
+Usage: mini-payments [COMMAND] <INPUT_FILE>... [OPTIONS]
+       mini-payments serve [OPTIONS]
+       mini-payments http [OPTIONS]
+       mini-payments repl [OPTIONS]
+
+The input files are read in order into the same books, `-` reads stdin.
+
+Commands:
+  process   Processes the transactions and prints the account balances (default)
+  validate  Checks that every transaction can be read, without processing any unless `--dry-run`
+  convert   Converts the transactions into another format
+  audit     Processes the transactions and prints every inconsistency found in the books
+  replay    Processes the transactions printing the outcome of each one
+  serve     Listens on a local TCP port for transactions, one per line, applied to the same books
+            and acknowledged one by one (`report` dumps the account report)
+  http      Listens on a local TCP port for HTTP/1.1 requests submitting transactions (one or a
+            batch, CSV or JSON) and querying balances, dispute states and the account report
+  repl      Applies the CSV transactions typed one per line on stdin, along with commands inspecting
+            and manipulating the books and exporting the session as a test (see `.help`)
+  help      Prints this message
+
+Input options (all commands, serve only takes the input format and tolerance ones, http the
+unknown fields and tolerance ones, repl the tolerance one):
+  --input-format <FORMAT>   csv (default), jsonl or bin
+  --ignore-unknown-fields   Ignores the JSON fields that are not part of a transaction
+  --delimiter <CHAR>        Separator of text records, a single ASCII char or \\n, \\r, \\t, \\0 (default \\n)
+  --tolerance <BYTES>       Longest text record accepted before the input is considered spam
+                            (http: longest transaction line of a request body)
+  --reject-file <FILE>      Writes the transactions that could not be read or applied, as they were read
+  --strict                  Stops at the first transaction that cannot be read or applied
+  --output <FILE>           Writes to the file instead of stdout
+  --summary <FILE>          Writes the counts of the run (lines read, parsed, applied, rejected by kind),
+                            the bytes processed, the elapsed time and the exit status to the file
+  --summary-format <FORMAT> json (default) or kv (key=value lines)
+
+Processing options (process, audit, replay, serve, http, repl, validate --dry-run):
+  --rates <FILE>            Rate table of the conversions
+  --rounding <POLICY>       down, up, half-up or half-even (default)
+  --fees <FILE>             Fee schedule of deposits and withdrawals
+  --max-balance <AMOUNT>    Cap on the total of every balance
+  --key-retention <N>       Number of idempotency keys remembered (a count of keys, not
+                            a duration)
+  --atomic                  Applies each input file whole or nothing of it
+
+Other options:
+  --output-format <FORMAT>  process, replay, serve: csv (default), json or jsonl report
+                            convert: csv (default), jsonl or bin
+  --audit                   process: also audits the books once the input is processed
+  --until <N>               replay: stops after the first N transactions
+  --dry-run                 validate: also processes the transactions against throwaway books,
+                            counting the ones the processor would reject
+  --max-invalid <N>         validate: fails when more than N transactions cannot be read (default 0)
+  --max-failed <N>          validate --dry-run: fails when the processor would reject more than N
+                            transactions (default 0)
+  --port <N>                serve, http: port listened to on 127.0.0.1 (default 7878, http 8080,
+                            0 picks a free one)
+  --admin-socket <PATH>     serve, http: Unix domain socket of the control interface (pause, resume,
+                            snapshot, restore, release, lock, unlock, metrics)
+  --max-header <BYTES>      http: longest line of a request head, request line included (default 8192)
+  --max-body <BYTES>        http: largest request body (default 1048576)
+  -h, --help                Prints this message
+
+Exit status:
+  0  Every transaction was read and applied (or converted)
+  1  Failure, e.g. an invalid rate table or binary header
+  2  Invalid command line
+  3  I/O failure on an input, output, reject or summary file
+  4  A text record longer than the tolerance
+  5  Completed, but some transactions could not be read or applied (validate: beyond the thresholds)
+  6  The books are inconsistent: processor fault, failed trial balance or audit discrepancies
+";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Command {
+	Process,
+	Validate,
+	Convert,
+	Audit,
+	Replay,
+	Serve,
+	Http,
+	Repl,
+}
+
+impl Command {
+	pub(crate) fn name(self) -> &'static str {
+		match self {
+			Command::Process => "process",
+			Command::Validate => "validate",
+			Command::Convert => "convert",
+			Command::Audit => "audit",
+			Command::Replay => "replay",
+			Command::Serve => "serve",
+			Command::Http => "http",
+			Command::Repl => "repl",
+		}
+	}
+
+	//validate only processes with `--dry-run`, checked once all the options are known
+	fn processes(self) -> bool {
+		matches!(
+			self,
+			Command::Process
+				| Command::Audit
+				| Command::Replay
+				| Command::Validate
+				| Command::Serve
+				| Command::Http
+				| Command::Repl
+		)
+	}
+
+	//the servers take their transactions from their clients, and reply to them
+	fn serves(self) -> bool {
+		matches!(self, Command::Serve | Command::Http)
+	}
+
+	//the servers and the REPL read no input file, and write nothing but their replies
+	fn reads_files(self) -> bool {
+		!self.serves() && self != Command::Repl
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum InputFormat {
+	Csv,
+	JsonLines,
+	Binary,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum OutputFormat {
+	Csv,
+	Json,
+	JsonLines,
+	Binary,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum SummaryFormat {
+	Json,
+	KeyValue,
+}
+
+#[derive(Debug)]
+pub(crate) struct Options {
+	pub(crate) command: Command,
+	//processed in order, `-` being stdin
+	pub(crate) input_files: Vec<String>,
+	pub(crate) input_format: InputFormat,
+	pub(crate) unknown_fields: UnknownFields,
+	pub(crate) delimiter: u8,
+	pub(crate) tolerance: NonZeroUsize,
+	pub(crate) reject_file: Option<String>,
+	pub(crate) strict: bool,
+	pub(crate) output_file: Option<String>,
+	pub(crate) output_format: OutputFormat,
+	pub(crate) summary_file: Option<String>,
+	pub(crate) summary_format: SummaryFormat,
+	pub(crate) rates_file: Option<String>,
+	pub(crate) rounding: Option<String>,
+	pub(crate) fees_file: Option<String>,
+	pub(crate) max_balance: Option<FixedDecimal>,
+	pub(crate) key_retention: Option<usize>,
+	pub(crate) atomic: bool,
+	pub(crate) audit: bool,
+	pub(crate) until: Option<usize>,
+	pub(crate) dry_run: bool,
+	pub(crate) max_invalid: usize,
+	pub(crate) max_failed: usize,
+	pub(crate) port: u16,
+	pub(crate) admin_socket: Option<String>,
+	pub(crate) max_header: NonZeroUsize,
+	pub(crate) max_body: NonZeroUsize,
+}
+
+impl Options {
+	fn new(command: Command) -> Self {
+		Options {
+			command,
+			input_files: Vec::new(),
+			input_format: InputFormat::Csv,
+			unknown_fields: UnknownFields::Reject,
+			delimiter: b'\n',
+			tolerance: NonZeroUsize::new(MAX_TRANSACTION_LEN * 5).expect("non zero tolerance"),
+			reject_file: None,
+			strict: false,
+			output_file: None,
+			output_format: OutputFormat::Csv,
+			summary_file: None,
+			summary_format: SummaryFormat::Json,
+			rates_file: None,
+			rounding: None,
+			fees_file: None,
+			max_balance: None,
+			key_retention: None,
+			atomic: false,
+			audit: false,
+			until: None,
+			dry_run: false,
+			max_invalid: 0,
+			max_failed: 0,
+			port: match command {
+				Command::Http => 8080,
+				_ => 7878,
+			},
+			admin_socket: None,
+			max_header: NonZeroUsize::new(8192).expect("non zero max header"),
+			max_body: NonZeroUsize::new(1 << 20).expect("non zero max body"),
+		}
+	}
+}
+
+#[doc(hidden)]
+fn value(args: &mut impl Iterator<Item = String>, option: &str) -> Result<String, String> {
+	args
+		.next()
+		.filter(|x| !x.starts_with("--"))
+		.ok_or(format!("Missing value for option `{}`", option))
+}
+
+#[doc(hidden)]
+fn parsed_value<T: FromStr>(
+	args: &mut impl Iterator<Item = String>,
+	option: &str,
+) -> Result<T, String> {
+	let value = value(args, option)?;
+	T::from_str(&value).map_err(|_| format!("Invalid value `{}` for option `{}`", value, option))
+}
+
+#[doc(hidden)]
+fn delimiter(txt: &str) -> Option<u8> {
+	match txt {
+		"\\n" => Some(b'\n'),
+		"\\r" => Some(b'\r'),
+		"\\t" => Some(b'\t'),
+		"\\0" => Some(b'\0'),
+		_ => match txt.as_bytes() {
+			[byte] if byte.is_ascii() => Some(*byte),
+			_ => None,
+		},
+	}
+}
+
+#[doc(hidden)]
+fn is_processing_option(option: &str) -> bool {
+	matches!(
+		option,
+		"--rates" | "--rounding" | "--fees" | "--max-balance" | "--key-retention" | "--atomic"
+	)
+}
+
+/// Parses the arguments (without the program name), None when the help is asked for.\
+/// The command defaults to process
+pub(crate) fn parse(args: impl IntoIterator<Item = String>) -> Result<Option<Options>, String> {
+	let args: Vec<_> = args.into_iter().collect();
+	if args.first().is_none_or(|x| x == "help") || args.iter().any(|x| x == "-h" || x == "--help") {
+		return Ok(None);
+	}
+	let (command, rest) = match args[0].as_str() {
+		"process" => (Command::Process, &args[1..]),
+		"validate" => (Command::Validate, &args[1..]),
+		"convert" => (Command::Convert, &args[1..]),
+		"audit" => (Command::Audit, &args[1..]),
+		"replay" => (Command::Replay, &args[1..]),
+		"serve" => (Command::Serve, &args[1..]),
+		"http" => (Command::Http, &args[1..]),
+		"repl" => (Command::Repl, &args[1..]),
+		//no command, the first argument is the input file or an option
+		_ => (Command::Process, &args[..]),
+	};
+	let mut options = Options::new(command);
+	parse_options(&mut options, rest.iter().cloned())?;
+	Ok(Some(options))
+}
+
+#[doc(hidden)]
+fn parse_options(options: &mut Options, args: impl Iterator<Item = String>) -> Result<(), String> {
+	let mut args = args;
+	let mut output_format = None;
+	//the first processing option given, validate accepting them only with `--dry-run`
+	let mut processing_option = None;
+	let command = options.command;
+	while let Some(arg) = args.next() {
+		let option = arg.as_str();
+		let applies = match option {
+			//a throwaway batch has nothing to reject, and the servers and the REPL have no input file to reject
+			"--atomic" => command.processes() && command != Command::Validate && command.reads_files(),
+			_ if is_processing_option(option) => command.processes(),
+			"--audit" => command == Command::Process,
+			"--until" => command == Command::Replay,
+			"--dry-run" | "--max-invalid" | "--max-failed" => command == Command::Validate,
+			//HTTP requests tell the format of their body and of the report they accept, the REPL
+			//reads CSV transactions and prints CSV reports
+			"--input-format" | "--output-format" | "--ignore-unknown-fields"
+				if command == Command::Repl =>
+			{
+				false
+			}
+			"--input-format" | "--output-format" if command == Command::Http => false,
+			"--output-format" => command != Command::Validate && command != Command::Audit,
+			"--port" | "--admin-socket" => command.serves(),
+			"--max-header" | "--max-body" => command == Command::Http,
+			//there are no files besides the processing ones
+			"--delimiter" | "--reject-file" | "--strict" | "--output" | "--summary"
+			| "--summary-format" => command.reads_files(),
+			_ => true,
+		};
+		if !applies {
+			return Err(format!(
+				"Option `{}` does not apply to the {} command",
+				option,
+				command.name()
+			));
+		}
+		if command.processes() && is_processing_option(option) && processing_option.is_none() {
+			processing_option = Some(arg.clone());
+		}
+		match option {
+			"--input-format" => {
+				options.input_format = match value(&mut args, option)?.as_str() {
+					"csv" => InputFormat::Csv,
+					"jsonl" => InputFormat::JsonLines,
+					"bin" => InputFormat::Binary,
+					other => return Err(format!("Unknown input format `{}`", other)),
+				}
+			}
+			"--ignore-unknown-fields" => options.unknown_fields = UnknownFields::Ignore,
+			"--delimiter" => {
+				let txt = value(&mut args, option)?;
+				options.delimiter =
+					delimiter(&txt).ok_or(format!("Invalid value `{}` for option `{}`", txt, option))?;
+			}
+			"--tolerance" => options.tolerance = parsed_value(&mut args, option)?,
+			"--reject-file" => options.reject_file = Some(value(&mut args, option)?),
+			"--strict" => options.strict = true,
+			"--output" => options.output_file = Some(value(&mut args, option)?),
+			"--output-format" => output_format = Some(value(&mut args, option)?),
+			"--summary" => options.summary_file = Some(value(&mut args, option)?),
+			"--summary-format" => {
+				options.summary_format = match value(&mut args, option)?.as_str() {
+					"json" => SummaryFormat::Json,
+					"kv" => SummaryFormat::KeyValue,
+					other => return Err(format!("Unknown summary format `{}`", other)),
+				}
+			}
+			"--rates" => options.rates_file = Some(value(&mut args, option)?),
+			"--rounding" => options.rounding = Some(value(&mut args, option)?),
+			"--fees" => options.fees_file = Some(value(&mut args, option)?),
+			"--max-balance" => options.max_balance = Some(parsed_value(&mut args, option)?),
+			"--key-retention" => options.key_retention = Some(parsed_value(&mut args, option)?),
+			"--atomic" => options.atomic = true,
+			"--audit" => options.audit = true,
+			"--until" => options.until = Some(parsed_value(&mut args, option)?),
+			"--dry-run" => options.dry_run = true,
+			"--port" => options.port = parsed_value(&mut args, option)?,
+			"--admin-socket" => options.admin_socket = Some(value(&mut args, option)?),
+			"--max-header" => options.max_header = parsed_value(&mut args, option)?,
+			"--max-body" => options.max_body = parsed_value(&mut args, option)?,
+			"--max-invalid" => options.max_invalid = parsed_value(&mut args, option)?,
+			"--max-failed" => {
+				options.max_failed = parsed_value(&mut args, option)?;
+				processing_option.get_or_insert(arg);
+			}
+			"-" if options.input_files.iter().any(|x| x == "-") => {
+				return Err("The standard input `-` can only be read once".to_owned())
+			}
+			_ if option.starts_with('-') && option != "-" => {
+				return Err(format!(
+					"Unknown option `{}` for the {} command, see `mini-payments --help`",
+					option,
+					command.name()
+				))
+			}
+			_ if !command.reads_files() => {
+				return Err(format!(
+					"Unexpected argument `{}`, the {} command takes no input file",
+					option,
+					command.name()
+				))
+			}
+			_ => options.input_files.push(arg),
+		}
+	}
+	if options.input_files.is_empty() && command.reads_files() {
+		return Err("No input file detected".to_owned());
+	}
+	if command == Command::Serve && options.input_format == InputFormat::Binary {
+		return Err("Unknown input format `bin` for the serve command".to_owned());
+	}
+	options.output_format = match (output_format.as_deref(), command) {
+		(None | Some("csv"), _) => OutputFormat::Csv,
+		(Some("jsonl"), _) => OutputFormat::JsonLines,
+		(Some("json"), Command::Process | Command::Replay | Command::Serve) => OutputFormat::Json,
+		(Some("bin"), Command::Convert) => OutputFormat::Binary,
+		(Some(other), _) => {
+			return Err(format!(
+				"Unknown output format `{}` for the {} command",
+				other,
+				command.name()
+			))
+		}
+	};
+	if let (Command::Validate, false, Some(option)) = (command, options.dry_run, processing_option) {
+		return Err(format!(
+			"Option `{}` applies to the validate command only with `--dry-run`",
+			option
+		));
+	}
+	if options.atomic && options.strict {
+		return Err("Options `--atomic` and `--strict` cannot be combined".to_owned());
+	}
+	Ok(())
+}
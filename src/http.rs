@@ -0,0 +1,490 @@
+// An embedded HTTP/1.1 server applying the transactions of every request to the same books
+//
+//   POST /transactions               one transaction, CSV (`text/csv`) or JSON (`application/json`)
+//   POST /transactions/batch         CSV lines or a JSON array of transactions, each applied on its own
+//   GET  /clients/{client}           the balances of a client
+//   GET  /transactions/{client}/{tx} the dispute state of a transaction
+//   GET  /report                     the account report, CSV with `Accept: text/csv`, JSON otherwise
+//   GET  /changes/{since}            the clients changed since a report, up to the last one cut
+//   POST /changes/{since}            likewise, cutting a new report to be the next cursor (see delta_report)
+//
+// Replies are JSON (the CSV report aside). As with SpamTolerantReader, request sizes are bounded
+// before anything is buffered: a line of the request head longer than `--max-header` is answered
+// with 431 and a body larger than `--max-body` with 413, closing the connection as nothing can be
+// told about where the next request starts. A body line longer than the tolerance rejects the
+// request with 413 as well. Bodies must come with a Content-Length, chunked ones are not supported.
+// Transactions wait while ingestion is paused through the control interface (see admin.rs)
+
+use crate::admin::{self, Control};
+use crate::cli::Options;
+use crate::input::kind;
+use crate::server;
+use crate::status::Failure;
+use lib::{
+	CsvReport, DeltaError, JsonReport, PaymentsProcessor, PaymentsTransaction, ReportWriter,
+	SpamReaderError, SpamTolerantReader, TransactionState, UnknownFields,
+};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::net::TcpStream;
+use std::num::NonZeroUsize;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+//more headers than any client sends, a bound on the size of the head along with `--max-header`
+#[doc(hidden)]
+const MAX_HEADERS: usize = 64;
+
+//a connection left idle for longer is dropped, so that silent clients do not hold a thread forever
+#[doc(hidden)]
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+//what every connection needs out of the options
+#[doc(hidden)]
+#[derive(Clone, Copy)]
+struct Limits {
+	unknown_fields: UnknownFields,
+	tolerance: NonZeroUsize,
+	max_header: NonZeroUsize,
+	max_body: NonZeroUsize,
+}
+
+#[doc(hidden)]
+struct Request {
+	method: String,
+	path: String,
+	//names lowercased
+	headers: Vec<(String, String)>,
+	body: Vec<u8>,
+	keep_alive: bool,
+}
+
+impl Request {
+	fn header(&self, name: &str) -> Option<&str> {
+		self
+			.headers
+			.iter()
+			.find(|(x, _)| x == name)
+			.map(|(_, value)| value.as_str())
+	}
+
+	//the media type of a header, parameters (`; charset=...`) aside
+	fn media_type(&self, name: &str) -> Option<String> {
+		let value = self.header(name)?;
+		Some(value.split(';').next()?.trim().to_ascii_lowercase())
+	}
+}
+
+#[doc(hidden)]
+struct Response {
+	status: u16,
+	content_type: &'static str,
+	body: String,
+	//the connection cannot carry on past this response
+	close: bool,
+}
+
+impl Response {
+	fn json(status: u16, body: String) -> Self {
+		Response {
+			status,
+			content_type: "application/json",
+			body,
+			close: false,
+		}
+	}
+
+	//messages are ours, nothing to escape
+	fn error(status: u16, message: &str) -> Self {
+		Response::json(status, format!("{{\"error\":\"{}\"}}\n", message))
+	}
+
+	//an error of the request framing, after which the connection is closed
+	fn fatal(status: u16, message: &str) -> Self {
+		Response {
+			close: true,
+			..Response::error(status, message)
+		}
+	}
+
+	fn write(&self, out: &mut impl Write, keep_alive: bool) -> std::io::Result<()> {
+		let connection = match keep_alive && !self.close {
+			true => "keep-alive",
+			false => "close",
+		};
+		write!(
+			out,
+			"HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: {}\r\n\r\n{}",
+			self.status,
+			reason(self.status),
+			self.content_type,
+			self.body.len(),
+			connection,
+			self.body
+		)?;
+		out.flush()
+	}
+}
+
+#[doc(hidden)]
+fn reason(status: u16) -> &'static str {
+	match status {
+		200 => "OK",
+		400 => "Bad Request",
+		404 => "Not Found",
+		405 => "Method Not Allowed",
+		410 => "Gone",
+		411 => "Length Required",
+		413 => "Content Too Large",
+		415 => "Unsupported Media Type",
+		422 => "Unprocessable Content",
+		431 => "Request Header Fields Too Large",
+		500 => "Internal Server Error",
+		501 => "Not Implemented",
+		505 => "HTTP Version Not Supported",
+		_ => "Unknown",
+	}
+}
+
+/// Serves until the process is killed, each connection on its own thread
+pub(crate) fn serve(options: &Options) -> Result<(), Failure> {
+	let limits = Limits {
+		unknown_fields: options.unknown_fields,
+		tolerance: options.tolerance,
+		max_header: options.max_header,
+		max_body: options.max_body,
+	};
+	server::listen(options, "http://", move |stream, pr, control| {
+		connection(stream, pr, control, limits)
+	})
+}
+
+#[doc(hidden)]
+fn connection(
+	stream: TcpStream,
+	pr: &Mutex<PaymentsProcessor>,
+	control: &Control,
+	limits: Limits,
+) -> std::io::Result<()> {
+	stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+	let mut reader = BufReader::new(stream.try_clone()?);
+	let mut writer = BufWriter::new(stream);
+	loop {
+		let (response, keep_alive) = match read_request(&mut reader, limits)? {
+			Ok(None) => return Ok(()),
+			Ok(Some(request)) => (respond(&request, pr, control, limits), request.keep_alive),
+			Err(response) => (response, false),
+		};
+		response.write(&mut writer, keep_alive)?;
+		if !keep_alive || response.close {
+			return Ok(());
+		}
+	}
+}
+
+//a line of the request head without its CRLF, None when the connection is closed between requests
+#[doc(hidden)]
+fn read_line(
+	reader: &mut impl BufRead,
+	limit: NonZeroUsize,
+) -> std::io::Result<Result<Option<String>, Response>> {
+	let mut line = Vec::new();
+	//one byte past the limit tells an overlong line from one that fits exactly
+	reader
+		.take(limit.get() as u64 + 1)
+		.read_until(b'\n', &mut line)?;
+	if line.last() != Some(&b'\n') {
+		return Ok(match line.len() {
+			0 => Ok(None),
+			n if n > limit.get() => Err(Response::fatal(431, "HeaderTooLarge")),
+			_ => Err(Response::fatal(400, "IncompleteRequest")),
+		});
+	}
+	line.pop();
+	if line.last() == Some(&b'\r') {
+		line.pop();
+	}
+	Ok(
+		String::from_utf8(line)
+			.map(Some)
+			.map_err(|_| Response::fatal(400, "MalformedHead")),
+	)
+}
+
+//the next request of the connection, or the response rejecting it
+#[doc(hidden)]
+fn read_request(
+	reader: &mut impl BufRead,
+	limits: Limits,
+) -> std::io::Result<Result<Option<Request>, Response>> {
+	let request_line = match read_line(reader, limits.max_header)? {
+		Ok(Some(line)) => line,
+		other => return Ok(other.map(|_| None)),
+	};
+	let mut parts = request_line.split(' ');
+	let (Some(method), Some(path), Some(version), None) =
+		(parts.next(), parts.next(), parts.next(), parts.next())
+	else {
+		return Ok(Err(Response::fatal(400, "MalformedRequestLine")));
+	};
+	//HTTP/1.0 clients are served too, one request per connection
+	let keep_alive = match version {
+		"HTTP/1.1" => true,
+		"HTTP/1.0" => false,
+		_ => return Ok(Err(Response::fatal(505, "UnsupportedVersion"))),
+	};
+	let mut request = Request {
+		method: method.to_owned(),
+		path: path.to_owned(),
+		headers: Vec::new(),
+		body: Vec::new(),
+		keep_alive,
+	};
+	loop {
+		let line = match read_line(reader, limits.max_header)? {
+			Ok(Some(line)) => line,
+			Ok(None) => return Ok(Err(Response::fatal(400, "IncompleteRequest"))),
+			Err(response) => return Ok(Err(response)),
+		};
+		if line.is_empty() {
+			break;
+		}
+		if request.headers.len() == MAX_HEADERS {
+			return Ok(Err(Response::fatal(431, "TooManyHeaders")));
+		}
+		let Some((name, value)) = line.split_once(':') else {
+			return Ok(Err(Response::fatal(400, "MalformedHeader")));
+		};
+		request
+			.headers
+			.push((name.trim().to_ascii_lowercase(), value.trim().to_owned()));
+	}
+	match request
+		.header("connection")
+		.map(str::to_ascii_lowercase)
+		.as_deref()
+	{
+		Some("close") => request.keep_alive = false,
+		Some("keep-alive") => request.keep_alive = true,
+		_ => (),
+	}
+	if request.header("transfer-encoding").is_some() {
+		return Ok(Err(Response::fatal(501, "TransferEncodingNotSupported")));
+	}
+	let length = match request.header("content-length").map(usize::from_str) {
+		None if request.method == "POST" => return Ok(Err(Response::fatal(411, "LengthRequired"))),
+		None => 0,
+		Some(Ok(length)) => length,
+		Some(Err(_)) => return Ok(Err(Response::fatal(400, "MalformedContentLength"))),
+	};
+	if length > limits.max_body.get() {
+		return Ok(Err(Response::fatal(413, "BodyTooLarge")));
+	}
+	request.body = vec![0; length];
+	match reader.read_exact(&mut request.body) {
+		Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+			Ok(Err(Response::fatal(400, "IncompleteRequest")))
+		}
+		Err(e) => Err(e),
+		Ok(()) => Ok(Ok(Some(request))),
+	}
+}
+
+#[doc(hidden)]
+fn respond(
+	request: &Request,
+	pr: &Mutex<PaymentsProcessor>,
+	control: &Control,
+	limits: Limits,
+) -> Response {
+	let path = request.path.split('?').next().unwrap_or_default();
+	let segments: Vec<_> = path.trim_matches('/').split('/').collect();
+	let batch = match segments[..] {
+		["transactions"] => false,
+		["transactions", "batch"] => true,
+		_ => return query(request, &segments, pr),
+	};
+	match request.method.as_str() {
+		"POST" => submit(request, pr, control, limits, batch),
+		_ => Response::error(405, "MethodNotAllowed"),
+	}
+}
+
+//the GET endpoints, and the POST cutting a report
+#[doc(hidden)]
+fn query(request: &Request, segments: &[&str], pr: &Mutex<PaymentsProcessor>) -> Response {
+	let route = match *segments {
+		["report"] => Route::Report,
+		["changes", since] => match u64::from_str(since) {
+			Ok(since) => Route::Changes(since),
+			Err(_) => return Response::error(404, "NotFound"),
+		},
+		["clients", client] => match u16::from_str(client) {
+			Ok(client) => Route::Client(client),
+			Err(_) => return Response::error(404, "NotFound"),
+		},
+		["transactions", client, tx] => match (u16::from_str(client), u32::from_str(tx)) {
+			(Ok(client), Ok(tx)) => Route::Transaction(client, tx),
+			_ => return Response::error(404, "NotFound"),
+		},
+		_ => return Response::error(404, "NotFound"),
+	};
+	let cut = match (&route, request.method.as_str()) {
+		(_, "GET") => false,
+		(Route::Changes(_), "POST") => true,
+		_ => return Response::error(405, "MethodNotAllowed"),
+	};
+	let Ok(mut pr) = pr.lock() else {
+		return Response::error(500, admin::POISONED);
+	};
+	let body = match route {
+		Route::Changes(since) => {
+			let report = match cut {
+				true => pr.delta_report(since),
+				false => pr.changes_since(since),
+			};
+			match report {
+				Ok(report) => crate::render(|x| report.write_json(x)),
+				Err(DeltaError::ReportExpired) => return Response::error(410, "ReportExpired"),
+				Err(e) => return Response::error(404, &kind(&e)),
+			}
+		}
+		Route::Report => match request.media_type("accept").as_deref() {
+			Some("text/csv") => {
+				return Response {
+					content_type: "text/csv",
+					..Response::json(200, crate::render(|x| pr.write_report(&CsvReport, x)))
+				};
+			}
+			_ => crate::render(|x| pr.write_report(&JsonReport, x)),
+		},
+		Route::Client(client) => {
+			let statements = pr.client_statements(client);
+			if statements.is_empty() {
+				return Response::error(404, "ClientNotFound");
+			}
+			crate::render(|x| JsonReport.write_report(x, &statements))
+		}
+		Route::Transaction(client, tx) => {
+			let Some(state) = pr.transaction_state(client, tx) else {
+				return Response::error(404, "TransactionNotFound");
+			};
+			let amount = match state {
+				TransactionState::UnderDispute(held) | TransactionState::PreArbitration(held) => {
+					format!(",\"held\":\"{}\"", held)
+				}
+				TransactionState::Represented(amount) => format!(",\"represented\":\"{}\"", amount),
+				_ => String::new(),
+			};
+			format!(
+				"{{\"client\":{},\"tx\":{},\"state\":\"{}\"{}}}\n",
+				client,
+				tx,
+				kind(&state),
+				amount
+			)
+		}
+	};
+	Response::json(200, body)
+}
+
+#[doc(hidden)]
+enum Route {
+	Report,
+	Changes(u64),
+	Client(u16),
+	Transaction(u16, u32),
+}
+
+//the POST endpoints, one transaction or a batch of them
+#[doc(hidden)]
+fn submit(
+	request: &Request,
+	pr: &Mutex<PaymentsProcessor>,
+	control: &Control,
+	limits: Limits,
+	batch: bool,
+) -> Response {
+	let Ok(body) = std::str::from_utf8(&request.body) else {
+		return Response::error(400, "InvalidUtf8");
+	};
+	let transactions = match request.media_type("content-type").as_deref() {
+		Some("text/csv" | "text/plain") => match csv_lines(body, limits.tolerance) {
+			Ok(lines) => lines
+				.iter()
+				.map(|x| PaymentsTransaction::from_str(x))
+				.collect(),
+			Err(response) => return response,
+		},
+		Some("application/json") if batch => {
+			match PaymentsTransaction::from_json_array(body, limits.unknown_fields) {
+				Ok(transactions) => transactions,
+				Err(_) => return Response::error(400, "MalformedJson"),
+			}
+		}
+		Some("application/json") => vec![PaymentsTransaction::from_json(body, limits.unknown_fields)],
+		_ => return Response::error(415, "UnsupportedMediaType"),
+	};
+	if !batch && transactions.len() != 1 {
+		return Response::error(400, "ExpectedOneTransaction");
+	}
+	let results = control.ingest(pr, |pr| {
+		transactions
+			.into_iter()
+			.map(|tx| {
+				let tx = tx.map_err(|e| kind(&e))?;
+				pr.process_transaction(tx).map_err(|e| kind(&e))
+			})
+			.collect::<Vec<_>>()
+	});
+	let Some(results) = results else {
+		return Response::error(500, admin::POISONED);
+	};
+	results.iter().for_each(|x| control.record(x));
+	let result = |x: &Result<(), String>| match x {
+		Ok(()) => "{\"status\":\"ok\"}".to_owned(),
+		Err(e) => format!("{{\"status\":\"error\",\"error\":\"{}\"}}", e),
+	};
+	if !batch {
+		let status = match results[0] {
+			Ok(()) => 200,
+			Err(_) => 422,
+		};
+		return Response::json(status, result(&results[0]) + "\n");
+	}
+	let applied = results.iter().filter(|x| x.is_ok()).count();
+	Response::json(
+		200,
+		format!(
+			"{{\"applied\":{},\"failed\":{},\"results\":[{}]}}\n",
+			applied,
+			results.len() - applied,
+			results.iter().map(result).collect::<Vec<_>>().join(",")
+		),
+	)
+}
+
+//the transaction lines of a CSV body, blank lines and a leading header aside
+#[doc(hidden)]
+fn csv_lines(body: &str, tolerance: NonZeroUsize) -> Result<Vec<String>, Response> {
+	let mut reader = SpamTolerantReader::new(body.as_bytes(), b'\n', tolerance);
+	let mut lines = Vec::new();
+	loop {
+		match reader.get_next() {
+			Ok(line) => {
+				//split out of a &str on an ASCII delimiter, still UTF-8
+				let line = String::from_utf8_lossy(line);
+				let line = line.trim();
+				let header = lines.is_empty() && line.starts_with("type");
+				if !line.is_empty() && !header {
+					lines.push(line.to_owned());
+				}
+			}
+			Err(SpamReaderError::EOFReached) => return Ok(lines),
+			Err(SpamReaderError::ToleranceExceeded) => {
+				return Err(Response::error(413, "ToleranceExceeded"))
+			}
+			Err(SpamReaderError::IOError(_)) => return Err(Response::error(400, "MalformedBody")),
+		}
+	}
+}
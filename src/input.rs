@@ -0,0 +1,283 @@
+// Reading transactions out of the input file, whatever its format, and writing back the rejected ones
+
+use crate::cli::{InputFormat, Options};
+use crate::status::{Failure, Status};
+use lib::{
+	PaymentsTransaction, SpamReaderError, SpamTolerantReader, UnknownFields, WireError, WireMAGIC,
+	WireReader, WireVERSION,
+};
+use std::cell::Cell;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::ops::ControlFlow;
+use std::rc::Rc;
+use std::str::FromStr;
+
+//a transaction as read from the input
+pub(crate) struct Item<'a> {
+	//the bytes the transaction was read from, None when there is nothing left of them (truncated records)
+	pub(crate) raw: Option<&'a [u8]>,
+	//position of binary records, text records are referred to by their content
+	record: Option<usize>,
+	//the transaction, or the description of why it could not be read
+	pub(crate) tx: Result<PaymentsTransaction, String>,
+	//the first text line, if it looks like a header
+	pub(crate) header: bool,
+	//the kind of error that made the transaction unreadable, see kind
+	pub(crate) error: Option<String>,
+}
+
+impl Item<'_> {
+	//how the transaction is referred to in messages
+	pub(crate) fn id(&self) -> String {
+		match (self.record, self.raw) {
+			(Some(record), _) => format!("record {}", record),
+			(None, Some(raw)) => format!("{:?}", String::from_utf8_lossy(raw)),
+			(None, None) => String::new(),
+		}
+	}
+
+	//describes why the transaction failed, in the same form as the reading failures
+	pub(crate) fn describe(&self, err: impl std::fmt::Debug) -> String {
+		format!("[{}]. Error [{:?}]", self.id(), err)
+	}
+
+	fn fail(&mut self, err: impl std::fmt::Debug) {
+		self.error = Some(kind(&err));
+		self.tx = Err(self.describe(err));
+	}
+}
+
+/// The name of the variant of an error, without its payload, for counting errors by kind
+pub(crate) fn kind(err: &impl std::fmt::Debug) -> String {
+	let mut kind = format!("{:?}", err);
+	kind.truncate(kind.find(['(', ' ']).unwrap_or(kind.len()));
+	kind
+}
+
+//counts the bytes read from an input file
+#[doc(hidden)]
+struct Counting {
+	reader: Box<dyn Read>,
+	bytes: Rc<Cell<u64>>,
+}
+
+impl Read for Counting {
+	fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+		let n = self.reader.read(buf)?;
+		self.bytes.set(self.bytes.get() + n as u64);
+		Ok(n)
+	}
+}
+
+pub(crate) struct Input {
+	source: Source,
+	bytes: Rc<Cell<u64>>,
+}
+
+#[doc(hidden)]
+enum Source {
+	Text {
+		reader: SpamTolerantReader<Counting>,
+		json: Option<UnknownFields>,
+		first_line: bool,
+	},
+	Binary {
+		reader: WireReader<BufReader<Counting>>,
+		records: usize,
+	},
+}
+
+impl Input {
+	/// Opens one of the input files, `-` being stdin
+	pub(crate) fn open(options: &Options, path: &str) -> Result<Self, Failure> {
+		let reader: Box<dyn Read> = match path {
+			"-" => Box::new(std::io::stdin().lock()),
+			_ => Box::new(File::open(path).map_err(|e| {
+				Failure::io(format!(
+					"Could not open input file `{}`: [{:?}]",
+					path,
+					e.kind()
+				))
+			})?),
+		};
+		let bytes = Rc::new(Cell::new(0));
+		let f = Counting {
+			reader,
+			bytes: bytes.clone(),
+		};
+		let json = match options.input_format {
+			InputFormat::Csv => None,
+			InputFormat::JsonLines => Some(options.unknown_fields),
+			InputFormat::Binary => {
+				let reader = WireReader::new(BufReader::new(f)).map_err(|e| {
+					let message = format!("Invalid binary input `{}`: [{:?}]", path, e);
+					match e {
+						WireError::IOError(_) => Failure::io(message),
+						_ => Failure::from(message),
+					}
+				})?;
+				return Ok(Input {
+					source: Source::Binary { reader, records: 0 },
+					bytes,
+				});
+			}
+		};
+		Ok(Input {
+			source: Source::Text {
+				reader: SpamTolerantReader::new(f, options.delimiter, options.tolerance),
+				json,
+				first_line: true,
+			},
+			bytes,
+		})
+	}
+
+	/// The number of bytes read so far
+	pub(crate) fn bytes(&self) -> u64 {
+		self.bytes.get()
+	}
+
+	/// Hands every transaction to **f** until the input is exhausted or **f** breaks
+	pub(crate) fn for_each(
+		&mut self,
+		mut f: impl FnMut(Item<'_>) -> Result<ControlFlow<()>, Failure>,
+	) -> Result<(), Failure> {
+		loop {
+			let item = match &mut self.source {
+				Source::Text {
+					reader,
+					json,
+					first_line,
+				} => match reader.get_next() {
+					Err(SpamReaderError::ToleranceExceeded) => {
+						return Err(Failure::new(
+							Status::ToleranceExceeded,
+							"Terminating... incorrect buffer beyond Tolerance threshold detected!",
+						))
+					}
+					Err(SpamReaderError::IOError(e)) => match e {
+						std::io::ErrorKind::Interrupted => continue, //retry according to https://doc.rust-lang.org/std/io/trait.Read.html#tymethod.read
+						_ => {
+							return Err(Failure::io(format!(
+								"Terminating... Irrecoverable IO error: [{}]",
+								e
+							)))
+						}
+					},
+					Err(SpamReaderError::EOFReached) => return Ok(()),
+					Ok(buf) => {
+						let header = std::mem::take(first_line) && buf.starts_with(b"type");
+						let mut item = Item {
+							raw: Some(buf),
+							record: None,
+							tx: Err(String::new()),
+							header,
+							error: None,
+						};
+						match std::str::from_utf8(buf) {
+							Err(e) => item.fail(e),
+							Ok(line) => {
+								let tx = match json {
+									None => PaymentsTransaction::from_str(line),
+									Some(unknown_fields) => PaymentsTransaction::from_json(line, *unknown_fields),
+								};
+								match tx {
+									Ok(tr) => item.tx = Ok(tr),
+									Err(e) => item.fail(e),
+								}
+							}
+						}
+						item
+					}
+				},
+				Source::Binary { reader, records } => {
+					*records += 1;
+					let mut item = Item {
+						raw: None,
+						record: Some(*records),
+						tx: Err(String::new()),
+						header: false,
+						error: None,
+					};
+					match reader.get_next_raw() {
+						Err(WireError::EOFReached) => return Ok(()),
+						Err(WireError::IOError(e)) => {
+							return Err(Failure::io(format!(
+								"Terminating... Irrecoverable IO error: [{:?}]",
+								e
+							)))
+						}
+						Err(e) => item.fail(e),
+						Ok(raw) => {
+							item.raw = Some(raw);
+							match PaymentsTransaction::from_wire(raw) {
+								Ok(tr) => item.tx = Ok(tr),
+								Err(e) => item.fail(e),
+							}
+						}
+					}
+					item
+				}
+			};
+			if f(item)?.is_break() {
+				return Ok(());
+			}
+		}
+	}
+}
+
+//the rejected transactions, in the format of the input so that they can be fixed and fed back
+pub(crate) struct Rejects {
+	writer: BufWriter<File>,
+	//None for binary records
+	delimiter: Option<u8>,
+}
+
+impl Rejects {
+	pub(crate) fn create(options: &Options) -> Result<Option<Self>, Failure> {
+		let Some(reject_file) = &options.reject_file else {
+			return Ok(None);
+		};
+		let f = File::create(reject_file).map_err(|e| {
+			Failure::io(format!(
+				"Could not create the reject file: [{:?}]",
+				e.kind()
+			))
+		})?;
+		let mut rejects = Rejects {
+			writer: BufWriter::new(f),
+			delimiter: Some(options.delimiter),
+		};
+		if options.input_format == InputFormat::Binary {
+			rejects.delimiter = None;
+			rejects.write(&WireMAGIC)?;
+			rejects.write(&[WireVERSION])?;
+		}
+		Ok(Some(rejects))
+	}
+
+	fn write(&mut self, bytes: &[u8]) -> Result<(), Failure> {
+		self
+			.writer
+			.write_all(bytes)
+			.map_err(|e| Failure::io(format!("Could not write the reject file: [{}]", e)))
+	}
+
+	pub(crate) fn reject(&mut self, item: &Item) -> Result<(), Failure> {
+		if let Some(raw) = item.raw {
+			self.write(raw)?;
+			if let Some(delimiter) = self.delimiter {
+				self.write(&[delimiter])?;
+			}
+		}
+		Ok(())
+	}
+
+	pub(crate) fn finish(mut self) -> Result<(), Failure> {
+		self
+			.writer
+			.flush()
+			.map_err(|e| Failure::io(format!("Could not write the reject file: [{}]", e)))
+	}
+}
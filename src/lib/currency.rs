@@ -0,0 +1,51 @@
+/// An ISO 4217 alphabetic currency code (eg. EUR, USD)\
+/// only the shape of the code (3 uppercase ASCII letters) is validated, not its registration status
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Currency {
+	#[doc(hidden)]
+	code: [u8; 3],
+}
+
+#[non_exhaustive]
+#[derive(Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+	InvalidLength,
+	InvalidCharacter,
+}
+
+impl std::str::FromStr for Currency {
+	type Err = ErrorKind;
+	/// ```
+	/// use lib::Currency;
+	/// use std::str::FromStr;
+	///
+	/// let eur = Currency::from_str("EUR");
+	/// assert!(eur.is_ok());
+	/// assert_eq!(eur.unwrap().to_string(), "EUR");
+	/// assert!(Currency::from_str("eur").is_err());
+	/// ```
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let code: [u8; 3] = s
+			.as_bytes()
+			.try_into()
+			.map_err(|_| ErrorKind::InvalidLength)?;
+		if code.iter().all(u8::is_ascii_uppercase) {
+			Ok(Currency { code })
+		} else {
+			Err(ErrorKind::InvalidCharacter)
+		}
+	}
+}
+
+impl Currency {
+	pub fn as_str(&self) -> &str {
+		//only ASCII uppercase letters make it past from_str()
+		std::str::from_utf8(&self.code).expect("Invariant Violation... non ASCII currency code")
+	}
+}
+
+impl std::fmt::Display for Currency {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(f, "{}", self.as_str())
+	}
+}
@@ -0,0 +1,187 @@
+use crate::fixed_decimal::{FixedDecimal, Rounding};
+use std::collections::HashMap;
+use std::io::BufRead;
+
+/// The kinds of transactions fees are charged on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FeeKind {
+	Deposit,
+	Withdrawal,
+}
+
+/// A fee made of a flat part plus a percentage of the transaction amount,
+/// bound by a minimum and an optional cap
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Fee {
+	pub flat: FixedDecimal,
+	pub percent: FixedDecimal,
+	pub min: FixedDecimal,
+	pub max: Option<FixedDecimal>,
+}
+
+impl Fee {
+	/// Calculates the fee for the provided amount, [None] if the calculation overflows
+	pub fn calculate(&self, amount: FixedDecimal, rounding: Rounding) -> Option<FixedDecimal> {
+		let fee = amount
+			.checked_percentage(self.percent, rounding)?
+			.checked_add(self.flat)?
+			.max(self.min);
+		Some(self.max.map_or(fee, |max| fee.min(max)))
+	}
+}
+
+/// Describes when the fee of a disputed transaction is given back to the client
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FeeReversal {
+	#[default]
+	Never,
+	OnResolve,
+	OnChargeback,
+	Always,
+}
+
+/// The set of [Fee]s charged per transaction kind and client tier.\
+/// Clients belong to tier 0 unless assigned otherwise, and no fee is charged
+/// for a transaction kind and tier without a [Fee]
+#[derive(Debug, Default)]
+pub struct FeeSchedule {
+	#[doc(hidden)]
+	fees: HashMap<(FeeKind, u8), Fee>,
+	tiers: HashMap<u16, u8>,
+	rounding: Rounding,
+	reversal: FeeReversal,
+}
+
+/// Type of error that happens while building a [FeeSchedule], line numbers start from 1
+#[non_exhaustive]
+#[derive(Debug, PartialEq)]
+pub enum ErrorKind {
+	IOError(std::io::ErrorKind),
+	MalformedLine(usize),
+	UnknownDirective(usize),
+	InvalidSection(usize),
+	MinimumExceedsCap(usize),
+	DuplicateEntry(usize),
+}
+
+#[doc(hidden)]
+fn parse_section<T: std::str::FromStr>(txt: &str, line_no: usize) -> Result<T, ErrorKind> {
+	txt.parse().map_err(|_| ErrorKind::InvalidSection(line_no))
+}
+
+impl FeeSchedule {
+	pub fn with_fee(mut self, kind: FeeKind, tier: u8, fee: Fee) -> Self {
+		self.fees.insert((kind, tier), fee);
+		self
+	}
+
+	pub fn with_client_tier(mut self, client: u16, tier: u8) -> Self {
+		self.tiers.insert(client, tier);
+		self
+	}
+
+	pub fn with_rounding(mut self, rounding: Rounding) -> Self {
+		self.rounding = rounding;
+		self
+	}
+
+	pub fn with_reversal(mut self, reversal: FeeReversal) -> Self {
+		self.reversal = reversal;
+		self
+	}
+
+	pub fn client_tier(&self, client: u16) -> u8 {
+		self.tiers.get(&client).copied().unwrap_or_default()
+	}
+
+	pub fn reversal(&self) -> FeeReversal {
+		self.reversal
+	}
+
+	/// Calculates the fee charged to the client for a transaction of the provided kind and amount,
+	/// [None] if the calculation overflows
+	pub fn fee_for(&self, kind: FeeKind, client: u16, amount: FixedDecimal) -> Option<FixedDecimal> {
+		match self.fees.get(&(kind, self.client_tier(client))) {
+			Some(fee) => fee.calculate(amount, self.rounding),
+			None => Some(FixedDecimal::default()),
+		}
+	}
+
+	/// Reads a fee schedule out of lines matching one of the following patterns (ws=whitespace):
+	/// - {ws}**fee**{ws},{ws}**deposit|withdrawal**{ws},{ws}**{tier u8}**{ws},{ws}**{flat}**{ws},{ws}**{percent}**{ws},{ws}**{min}**{ws}[,{ws}**{max}**{ws}]
+	/// - {ws}**tier**{ws},{ws}**{client u16}**{ws},{ws}**{tier u8}**{ws}
+	/// - {ws}**rounding**{ws},{ws}**down|up|half-up|half-even**{ws}
+	/// - {ws}**reversal**{ws},{ws}**never|resolve|chargeback|always**{ws}
+	///
+	/// where amounts are in the **{number}.{(1..=4)digits}** form.
+	/// Empty lines and lines starting with **#** are skipped
+	/// ```
+	/// use lib::{FeeKind, FeeSchedule, FixedDecimal};
+	/// use std::str::FromStr;
+	///
+	/// let schedule = FeeSchedule::from_reader("fee, withdrawal, 0, 1.0, 0.5, 0.0\ntier, 7, 1".as_bytes());
+	/// assert!(schedule.is_ok());
+	/// let schedule = schedule.unwrap();
+	/// let amount = FixedDecimal::from_str("100.0").unwrap();
+	/// let fee = schedule.fee_for(FeeKind::Withdrawal, 1, amount).unwrap();
+	/// assert_eq!(fee.to_string(), "1.5");
+	/// let fee = schedule.fee_for(FeeKind::Withdrawal, 7, amount).unwrap();
+	/// assert_eq!(fee.to_string(), "0.0");
+	/// ```
+	pub fn from_reader(reader: impl BufRead) -> Result<Self, ErrorKind> {
+		let mut schedule = FeeSchedule::default();
+		for (i, line) in reader.lines().enumerate() {
+			let line = line.map_err(|x| ErrorKind::IOError(x.kind()))?;
+			let line_no = i + 1;
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+			let sections: Vec<_> = line.split(',').map(|x| x.trim()).collect();
+			match sections.as_slice() {
+				["fee", kind, tier, flat, percent, min, max @ ..] if max.len() <= 1 => {
+					let kind = match *kind {
+						"deposit" => FeeKind::Deposit,
+						"withdrawal" => FeeKind::Withdrawal,
+						_ => return Err(ErrorKind::InvalidSection(line_no)),
+					};
+					let fee = Fee {
+						flat: parse_section(flat, line_no)?,
+						percent: parse_section(percent, line_no)?,
+						min: parse_section(min, line_no)?,
+						max: max.first().map(|x| parse_section(x, line_no)).transpose()?,
+					};
+					if fee.max.is_some_and(|max| max < fee.min) {
+						return Err(ErrorKind::MinimumExceedsCap(line_no));
+					}
+					let tier = parse_section(tier, line_no)?;
+					if schedule.fees.insert((kind, tier), fee).is_some() {
+						return Err(ErrorKind::DuplicateEntry(line_no));
+					}
+				}
+				["tier", client, tier] => {
+					let client = parse_section(client, line_no)?;
+					let tier = parse_section(tier, line_no)?;
+					if schedule.tiers.insert(client, tier).is_some() {
+						return Err(ErrorKind::DuplicateEntry(line_no));
+					}
+				}
+				["rounding", rounding] => schedule.rounding = parse_section(rounding, line_no)?,
+				["reversal", reversal] => {
+					schedule.reversal = match *reversal {
+						"never" => FeeReversal::Never,
+						"resolve" => FeeReversal::OnResolve,
+						"chargeback" => FeeReversal::OnChargeback,
+						"always" => FeeReversal::Always,
+						_ => return Err(ErrorKind::InvalidSection(line_no)),
+					}
+				}
+				["fee" | "tier" | "rounding" | "reversal", ..] => {
+					return Err(ErrorKind::MalformedLine(line_no))
+				}
+				_ => return Err(ErrorKind::UnknownDirective(line_no)),
+			}
+		}
+		Ok(schedule)
+	}
+}
@@ -17,7 +17,6 @@ pub const MAX_DISP_LEN: usize = "1844674407370955.1615".len();
 
 ///The Maximum value of a [FixedDecimal]
 pub const MAX: FixedDecimal = FixedDecimal {
-	#[doc(hidden)]
 	data: UnderLying::MAX,
 };
 
@@ -46,6 +45,31 @@ impl std::fmt::Display for FixedDecimal {
 	}
 }
 
+/// The exact sum of two [FixedDecimal]s, which may exceed [MAX] and can therefore only be displayed
+#[doc(hidden)]
+pub(crate) struct WideSum(pub(crate) FixedDecimal, pub(crate) FixedDecimal);
+
+impl std::fmt::Display for WideSum {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		if let Some(sum) = self.0.checked_add(self.1) {
+			return sum.fmt(f);
+		}
+		let units = self.0.data as u128 + self.1.data as u128;
+		let decimal = format!(
+			"{:01$}",
+			units % DIVISOR as u128,
+			NUM_DECIMAL_DIGITS as usize
+		);
+		let decimal = decimal.trim_end_matches('0');
+		write!(
+			f,
+			"{}.{}",
+			units / DIVISOR as u128,
+			if decimal.is_empty() { "0" } else { decimal }
+		)
+	}
+}
+
 // using advice from :
 // https://stackoverflow.com/questions/55572098/how-to-construct-a-parseinterror-in-my-own-code
 #[non_exhaustive]
@@ -83,7 +107,100 @@ impl std::str::FromStr for FixedDecimal {
 	}
 }
 
+/// Rounding policy applied when the result of an operation has more decimal digits than a [FixedDecimal] can hold
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+	/// Towards zero (truncation)
+	Down,
+	/// Away from zero
+	Up,
+	/// To the nearest, ties away from zero
+	HalfUp,
+	/// To the nearest, ties to the even neighbour (banker's rounding)
+	#[default]
+	HalfEven,
+}
+
+impl std::str::FromStr for Rounding {
+	type Err = ErrorKind;
+	/// Accepts one of **down**, **up**, **half-up**, **half-even**
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"down" => Ok(Rounding::Down),
+			"up" => Ok(Rounding::Up),
+			"half-up" => Ok(Rounding::HalfUp),
+			"half-even" => Ok(Rounding::HalfEven),
+			_ => Err(ErrorKind::InvalidFormat),
+		}
+	}
+}
+
+//divides returning the rounded quotient and the remainder (numerator minus rounded quotient * divisor)
+#[doc(hidden)]
+fn rounded_div(numerator: u128, divisor: u128, rounding: Rounding) -> Option<(UnderLying, i128)> {
+	let (quotient, remainder) = (numerator / divisor, numerator % divisor);
+	let round_up = match rounding {
+		Rounding::Down => false,
+		Rounding::Up => remainder != 0,
+		Rounding::HalfUp => remainder * 2 >= divisor,
+		Rounding::HalfEven => {
+			remainder * 2 > divisor || (remainder * 2 == divisor && quotient % 2 == 1)
+		}
+	};
+	let data = UnderLying::try_from(quotient + round_up as u128).ok()?;
+	let remainder = if round_up {
+		remainder as i128 - divisor as i128
+	} else {
+		remainder as i128
+	};
+	Some((data, remainder))
+}
+
 impl FixedDecimal {
+	/// Multiplies two numbers rounding the exact product according to the provided [Rounding] policy.\
+	/// Along with the result, the rounding remainder (exact product minus result) is returned
+	/// in units of 1/10000th of the smallest representable digit
+	/// ```
+	/// use std::str::FromStr;
+	/// use lib::{FixedDecimal, Rounding};
+	///
+	/// let amount = FixedDecimal::from_str("10.0005").unwrap();
+	/// let rate = FixedDecimal::from_str("0.5").unwrap();
+	/// let (res, remainder) = amount.checked_mul(rate, Rounding::Down).unwrap();
+	/// assert_eq!(res.to_string(), "5.0002");
+	/// assert_eq!(remainder, 5000);
+	/// let (res, remainder) = amount.checked_mul(rate, Rounding::Up).unwrap();
+	/// assert_eq!(res.to_string(), "5.0003");
+	/// assert_eq!(remainder, -5000);
+	/// ```
+	pub fn checked_mul(self, rhs: FixedDecimal, rounding: Rounding) -> Option<(Self, i64)> {
+		let (data, remainder) = rounded_div(
+			self.data as u128 * rhs.data as u128,
+			DIVISOR as u128,
+			rounding,
+		)?;
+		Some((FixedDecimal { data }, remainder as i64))
+	}
+
+	/// Calculates the provided percentage of self, rounding according to the provided [Rounding] policy
+	/// ```
+	/// use std::str::FromStr;
+	/// use lib::{FixedDecimal, Rounding};
+	///
+	/// let amount = FixedDecimal::from_str("10.0").unwrap();
+	/// let percent = FixedDecimal::from_str("1.25").unwrap();
+	/// let res = amount.checked_percentage(percent, Rounding::HalfEven).unwrap();
+	/// assert_eq!(res.to_string(), "0.125");
+	/// ```
+	pub fn checked_percentage(self, percent: FixedDecimal, rounding: Rounding) -> Option<Self> {
+		let (data, _) = rounded_div(
+			self.data as u128 * percent.data as u128,
+			DIVISOR as u128 * 100,
+			rounding,
+		)?;
+		Some(FixedDecimal { data })
+	}
+
 	pub fn checked_add(self, rhs: FixedDecimal) -> Option<Self> {
 		let inner = self.data.checked_add(rhs.data)?;
 		Some(FixedDecimal { data: inner })
@@ -94,6 +211,17 @@ impl FixedDecimal {
 		Some(FixedDecimal { data: inner })
 	}
 
+	//signed representation in units of the smallest representable digit, used by the ledger
+	pub(crate) fn to_units(self) -> i128 {
+		self.data as i128
+	}
+
+	pub(crate) fn from_units(units: i128) -> Option<Self> {
+		Some(FixedDecimal {
+			data: UnderLying::try_from(units).ok()?,
+		})
+	}
+
 	pub fn get_whole_part(&self) -> UnderLying {
 		(self.data - self.data % DIVISOR) / DIVISOR
 	}
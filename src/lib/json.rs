@@ -0,0 +1,271 @@
+// A minimal JSON parser for the flat objects of the JSON Lines input (and arrays of them),
+// without any dependency
+//
+// Only the fields of the top level object are handed out. Nested objects and arrays are validated
+// and skipped up to MAX_DEPTH levels, so the recursion is bounded, and strings are borrowed from
+// the input unless they contain escapes, so allocations never exceed the size of the input
+
+use crate::transaction::ErrorKind;
+use std::borrow::Cow;
+
+#[doc(hidden)]
+const MAX_DEPTH: usize = 16;
+
+#[doc(hidden)]
+#[derive(Debug, PartialEq)]
+pub(crate) enum Value<'a> {
+	String(Cow<'a, str>),
+	//the literal as found in the input, never converted to a float
+	Number(&'a str),
+	Bool(bool),
+	Null,
+	//an object or an array, validated but not kept
+	Nested,
+}
+
+#[doc(hidden)]
+struct Parser<'a> {
+	src: &'a str,
+	pos: usize,
+}
+
+/// Parses a single JSON object spanning the whole input (surrounding whitespace aside),
+/// calling **field** for each of its fields in order
+pub(crate) fn parse_object<'a>(
+	src: &'a str,
+	mut field: impl FnMut(Cow<'a, str>, Value<'a>) -> Result<(), ErrorKind>,
+) -> Result<(), ErrorKind> {
+	let mut parser = Parser { src, pos: 0 };
+	parser.skip_whitespace();
+	parser.expect(b'{')?;
+	parser.skip_whitespace();
+	if !parser.eat(b'}') {
+		loop {
+			parser.skip_whitespace();
+			let name = parser.string()?;
+			parser.skip_whitespace();
+			parser.expect(b':')?;
+			parser.skip_whitespace();
+			let value = parser.value(1)?;
+			field(name, value)?;
+			parser.skip_whitespace();
+			if parser.eat(b'}') {
+				break;
+			}
+			parser.expect(b',')?;
+		}
+	}
+	parser.skip_whitespace();
+	match parser.pos == src.len() {
+		true => Ok(()),
+		false => Err(ErrorKind::MalformedJson),
+	}
+}
+
+/// Parses a JSON array spanning the whole input (surrounding whitespace aside),
+/// calling **element** with the text of each of its elements in order
+pub(crate) fn parse_array<'a>(
+	src: &'a str,
+	mut element: impl FnMut(&'a str) -> Result<(), ErrorKind>,
+) -> Result<(), ErrorKind> {
+	let mut parser = Parser { src, pos: 0 };
+	parser.skip_whitespace();
+	parser.expect(b'[')?;
+	parser.skip_whitespace();
+	if !parser.eat(b']') {
+		loop {
+			parser.skip_whitespace();
+			let start = parser.pos;
+			parser.value(1)?;
+			element(&src[start..parser.pos])?;
+			parser.skip_whitespace();
+			if parser.eat(b']') {
+				break;
+			}
+			parser.expect(b',')?;
+		}
+	}
+	parser.skip_whitespace();
+	match parser.pos == src.len() {
+		true => Ok(()),
+		false => Err(ErrorKind::MalformedJson),
+	}
+}
+
+impl<'a> Parser<'a> {
+	fn peek(&self) -> Option<u8> {
+		self.src.as_bytes().get(self.pos).copied()
+	}
+
+	fn eat(&mut self, byte: u8) -> bool {
+		let found = self.peek() == Some(byte);
+		self.pos += found as usize;
+		found
+	}
+
+	fn expect(&mut self, byte: u8) -> Result<(), ErrorKind> {
+		match self.eat(byte) {
+			true => Ok(()),
+			false => Err(ErrorKind::MalformedJson),
+		}
+	}
+
+	fn skip_whitespace(&mut self) {
+		while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+			self.pos += 1;
+		}
+	}
+
+	fn value(&mut self, depth: usize) -> Result<Value<'a>, ErrorKind> {
+		match self.peek().ok_or(ErrorKind::MalformedJson)? {
+			b'"' => self.string().map(Value::String),
+			b'{' | b'[' => self.nested(depth + 1).map(|_| Value::Nested),
+			b't' => self.literal("true", Value::Bool(true)),
+			b'f' => self.literal("false", Value::Bool(false)),
+			b'n' => self.literal("null", Value::Null),
+			_ => self.number().map(Value::Number),
+		}
+	}
+
+	fn literal(&mut self, txt: &str, value: Value<'a>) -> Result<Value<'a>, ErrorKind> {
+		match self.src[self.pos..].starts_with(txt) {
+			true => {
+				self.pos += txt.len();
+				Ok(value)
+			}
+			false => Err(ErrorKind::MalformedJson),
+		}
+	}
+
+	//-?(0|[1-9][0-9]*)(.[0-9]+)?([eE][+-]?[0-9]+)?
+	fn number(&mut self) -> Result<&'a str, ErrorKind> {
+		let start = self.pos;
+		self.eat(b'-');
+		if !self.eat(b'0') && self.digits() == 0 {
+			return Err(ErrorKind::MalformedJson);
+		}
+		if self.eat(b'.') && self.digits() == 0 {
+			return Err(ErrorKind::MalformedJson);
+		}
+		if self.eat(b'e') || self.eat(b'E') {
+			let _ = self.eat(b'+') || self.eat(b'-');
+			if self.digits() == 0 {
+				return Err(ErrorKind::MalformedJson);
+			}
+		}
+		Ok(&self.src[start..self.pos])
+	}
+
+	fn digits(&mut self) -> usize {
+		let start = self.pos;
+		while self.peek().is_some_and(|x| x.is_ascii_digit()) {
+			self.pos += 1;
+		}
+		self.pos - start
+	}
+
+	fn nested(&mut self, depth: usize) -> Result<(), ErrorKind> {
+		if depth > MAX_DEPTH {
+			return Err(ErrorKind::MalformedJson);
+		}
+		let close = match self.peek() {
+			Some(b'{') => b'}',
+			_ => b']',
+		};
+		self.pos += 1;
+		self.skip_whitespace();
+		if self.eat(close) {
+			return Ok(());
+		}
+		loop {
+			self.skip_whitespace();
+			if close == b'}' {
+				self.string()?;
+				self.skip_whitespace();
+				self.expect(b':')?;
+				self.skip_whitespace();
+			}
+			self.value(depth)?;
+			self.skip_whitespace();
+			if self.eat(close) {
+				return Ok(());
+			}
+			self.expect(b',')?;
+		}
+	}
+
+	fn string(&mut self) -> Result<Cow<'a, str>, ErrorKind> {
+		self.expect(b'"')?;
+		let start = self.pos;
+		//strings without escapes are borrowed as they are
+		loop {
+			match self.peek().ok_or(ErrorKind::MalformedJson)? {
+				b'"' => {
+					self.pos += 1;
+					return Ok(Cow::Borrowed(&self.src[start..self.pos - 1]));
+				}
+				b'\\' => break,
+				0..=0x1F => return Err(ErrorKind::MalformedJson),
+				_ => self.pos += 1,
+			}
+		}
+		let mut owned = self.src[start..self.pos].to_owned();
+		loop {
+			//copying whole chars, the input being a &str
+			let rest = &self.src[self.pos..];
+			let ch = rest.chars().next().ok_or(ErrorKind::MalformedJson)?;
+			self.pos += ch.len_utf8();
+			match ch {
+				'"' => return Ok(Cow::Owned(owned)),
+				'\\' => owned.push(self.escape()?),
+				'\u{0}'..='\u{1F}' => return Err(ErrorKind::MalformedJson),
+				ch => owned.push(ch),
+			}
+		}
+	}
+
+	fn escape(&mut self) -> Result<char, ErrorKind> {
+		let byte = self.peek().ok_or(ErrorKind::MalformedJson)?;
+		self.pos += 1;
+		match byte {
+			b'"' => Ok('"'),
+			b'\\' => Ok('\\'),
+			b'/' => Ok('/'),
+			b'b' => Ok('\u{8}'),
+			b'f' => Ok('\u{C}'),
+			b'n' => Ok('\n'),
+			b'r' => Ok('\r'),
+			b't' => Ok('\t'),
+			b'u' => {
+				let high = self.hex4()?;
+				let code = match high {
+					0xD800..=0xDBFF => {
+						self.expect(b'\\')?;
+						self.expect(b'u')?;
+						let low = self.hex4()?;
+						if !(0xDC00..=0xDFFF).contains(&low) {
+							return Err(ErrorKind::MalformedJson);
+						}
+						0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+					}
+					code => code,
+				};
+				//lone low surrogates are not chars
+				char::from_u32(code).ok_or(ErrorKind::MalformedJson)
+			}
+			_ => Err(ErrorKind::MalformedJson),
+		}
+	}
+
+	fn hex4(&mut self) -> Result<u32, ErrorKind> {
+		let hex = self
+			.src
+			.get(self.pos..self.pos + 4)
+			.ok_or(ErrorKind::MalformedJson)?;
+		if !hex.bytes().all(|x| x.is_ascii_hexdigit()) {
+			return Err(ErrorKind::MalformedJson);
+		}
+		self.pos += 4;
+		u32::from_str_radix(hex, 16).map_err(|_| ErrorKind::MalformedJson)
+	}
+}
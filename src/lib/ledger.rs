@@ -0,0 +1,291 @@
+// Double-entry books underneath the PaymentsProcessor
+//
+// Every account balance is the sum of its credits minus the sum of its debits, so client balances
+// (what the house owes its clients) are positive while external funding (money that came in from
+// outside the system) is negative. Since every posting debits exactly as much as it credits
+// in each currency, the balances of all accounts of a currency always sum to zero.
+//
+// Client balances are kept with their ClientState (they are what gets reported), every other
+// account lives in the Ledger, both are only ever touched through Ledger::post
+
+use crate::currency::Currency;
+use crate::fixed_decimal::FixedDecimal;
+use std::collections::{BTreeMap, HashMap};
+
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Account {
+	Available(u16),
+	Held(u16),
+	//counterpart of money entering (deposits, disputes) or leaving (withdrawals) the system
+	ExternalFunding,
+	//disputed money lost to chargebacks, given back by representments
+	ChargebackLoss,
+	//the house account collecting fees
+	Fees,
+	//counterpart of both legs of a conversion, one per currency
+	FxClearing,
+}
+
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Side {
+	Debit,
+	Credit,
+}
+
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Entry {
+	currency: Option<Currency>,
+	account: Account,
+	side: Side,
+	amount: FixedDecimal,
+}
+
+impl Entry {
+	pub(crate) fn debit(currency: Option<Currency>, account: Account, amount: FixedDecimal) -> Self {
+		Entry {
+			currency,
+			account,
+			side: Side::Debit,
+			amount,
+		}
+	}
+
+	pub(crate) fn credit(currency: Option<Currency>, account: Account, amount: FixedDecimal) -> Self {
+		Entry {
+			currency,
+			account,
+			side: Side::Credit,
+			amount,
+		}
+	}
+
+	fn reversed(&self) -> Self {
+		Entry {
+			side: match self.side {
+				Side::Debit => Side::Credit,
+				Side::Credit => Side::Debit,
+			},
+			..*self
+		}
+	}
+
+	fn signed_units(&self) -> i128 {
+		match self.side {
+			Side::Debit => -self.amount.to_units(),
+			Side::Credit => self.amount.to_units(),
+		}
+	}
+}
+
+#[doc(hidden)]
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Balance {
+	pub(crate) available: FixedDecimal,
+	pub(crate) held: FixedDecimal,
+}
+
+#[doc(hidden)]
+#[derive(Debug, PartialEq)]
+pub(crate) enum PostingError {
+	//debits and credits differ in at least one currency
+	Unbalanced,
+	Underflow(Account),
+	Overflow(Account),
+	//the total (available + held) of a client balance would grow beyond the maximum balance
+	ExceedsMaxBalance,
+}
+
+//receipt of an applied posting, all it takes to reverse it
+#[doc(hidden)]
+#[derive(Debug, Clone)]
+pub(crate) struct Posting {
+	entries: Vec<Entry>,
+	//client balances opened by the posting, closed again when it is reversed
+	opened: Vec<Option<Currency>>,
+}
+
+#[doc(hidden)]
+#[derive(Debug, Default)]
+pub(crate) struct Ledger {
+	accounts: HashMap<(Option<Currency>, Account), i128>,
+	postings: u64,
+	//client balances open in a named currency, reports lay out their columns according to it
+	named_balances: usize,
+	//cap on the total of every client balance, FixedDecimal's range when None
+	max_balance: Option<FixedDecimal>,
+}
+
+impl Ledger {
+	pub(crate) fn set_max_balance(&mut self, max_balance: FixedDecimal) {
+		self.max_balance = Some(max_balance);
+	}
+
+	/// Applies all entries of a posting to the client balances and the ledger accounts, or none of them.\
+	/// Client balances (and the house fees account) may never go negative nor exceed [FixedDecimal]'s range
+	pub(crate) fn post(
+		&mut self,
+		balances: &mut BTreeMap<Option<Currency>, Balance>,
+		entries: &[Entry],
+	) -> Result<Posting, PostingError> {
+		let opened = self.apply(balances, entries, true)?;
+		self.postings += 1;
+		self.named_balances += opened.iter().filter(|x| x.is_some()).count();
+		Ok(Posting {
+			entries: entries.to_vec(),
+			opened,
+		})
+	}
+
+	/// Undoes an applied posting, the balance cap is not enforced since the books go back to a previous state
+	pub(crate) fn reverse(
+		&mut self,
+		balances: &mut BTreeMap<Option<Currency>, Balance>,
+		posting: &Posting,
+	) -> Result<(), PostingError> {
+		let entries: Vec<_> = posting.entries.iter().map(Entry::reversed).collect();
+		self.apply(balances, &entries, false)?;
+		for currency in &posting.opened {
+			balances.remove(currency);
+		}
+		self.postings -= 1;
+		self.named_balances -= posting.opened.iter().filter(|x| x.is_some()).count();
+		Ok(())
+	}
+
+	fn apply(
+		&mut self,
+		balances: &mut BTreeMap<Option<Currency>, Balance>,
+		entries: &[Entry],
+		capped: bool,
+	) -> Result<Vec<Option<Currency>>, PostingError> {
+		let mut net: Vec<(Option<Currency>, i128)> = Vec::new();
+		for entry in entries {
+			match net
+				.iter_mut()
+				.find(|(currency, _)| *currency == entry.currency)
+			{
+				Some((_, sum)) => *sum += entry.signed_units(),
+				None => net.push((entry.currency, entry.signed_units())),
+			}
+		}
+		if net.iter().any(|(_, sum)| *sum != 0) {
+			return Err(PostingError::Unbalanced);
+		}
+		//everything is staged first so that a failing entry leaves the books untouched
+		let mut staged_balances: Vec<(Option<Currency>, Balance)> = Vec::new();
+		let mut staged_accounts: Vec<((Option<Currency>, Account), i128)> = Vec::new();
+		for entry in entries {
+			match entry.account {
+				Account::Available(_) | Account::Held(_) => {
+					let index = match staged_balances
+						.iter()
+						.position(|(currency, _)| *currency == entry.currency)
+					{
+						Some(index) => index,
+						None => {
+							let balance = balances.get(&entry.currency).copied().unwrap_or_default();
+							staged_balances.push((entry.currency, balance));
+							staged_balances.len() - 1
+						}
+					};
+					let balance = &mut staged_balances[index].1;
+					let funds = match entry.account {
+						Account::Available(_) => &mut balance.available,
+						_ => &mut balance.held,
+					};
+					*funds = match entry.side {
+						Side::Credit => funds
+							.checked_add(entry.amount)
+							.ok_or(PostingError::Overflow(entry.account))?,
+						Side::Debit => funds
+							.checked_sub(entry.amount)
+							.ok_or(PostingError::Underflow(entry.account))?,
+					};
+				}
+				account => {
+					let key = (entry.currency, account);
+					let index = match staged_accounts.iter().position(|(k, _)| *k == key) {
+						Some(index) => index,
+						None => {
+							staged_accounts.push((key, self.balance(entry.currency, account)));
+							staged_accounts.len() - 1
+						}
+					};
+					let units = &mut staged_accounts[index].1;
+					*units = units
+						.checked_add(entry.signed_units())
+						.ok_or(PostingError::Overflow(account))?;
+					if account == Account::Fees && FixedDecimal::from_units(*units).is_none() {
+						return Err(match entry.side {
+							Side::Credit => PostingError::Overflow(account),
+							Side::Debit => PostingError::Underflow(account),
+						});
+					}
+				}
+			}
+		}
+		//a balance already beyond a (lowered) cap may still shrink, it just cannot grow any further
+		for (currency, balance) in &staged_balances {
+			let total = balance.available.checked_add(balance.held);
+			let previous = balances
+				.get(currency)
+				.map_or(0, |x| x.available.to_units() + x.held.to_units());
+			match (total, self.max_balance.filter(|_| capped)) {
+				(None, _) => return Err(PostingError::ExceedsMaxBalance),
+				(Some(total), Some(max)) if total > max && total.to_units() > previous => {
+					return Err(PostingError::ExceedsMaxBalance)
+				}
+				_ => {}
+			}
+		}
+		let mut opened = Vec::new();
+		for (currency, balance) in staged_balances {
+			if balances.insert(currency, balance).is_none() {
+				opened.push(currency);
+			}
+		}
+		for (key, units) in staged_accounts {
+			self.accounts.insert(key, units);
+		}
+		Ok(opened)
+	}
+
+	/// Returns the balance of a non client account in units of the smallest [FixedDecimal] digit
+	pub(crate) fn balance(&self, currency: Option<Currency>, account: Account) -> i128 {
+		self
+			.accounts
+			.get(&(currency, account))
+			.copied()
+			.unwrap_or_default()
+	}
+
+	/// Whether any client holds a balance in a named currency
+	pub(crate) fn has_named_balances(&self) -> bool {
+		self.named_balances > 0
+	}
+
+	pub(crate) fn postings(&self) -> u64 {
+		self.postings
+	}
+
+	/// Sums every account of the books per currency, including the provided client balances
+	pub(crate) fn trial_balance<'a>(
+		&self,
+		clients: impl Iterator<Item = &'a BTreeMap<Option<Currency>, Balance>>,
+	) -> BTreeMap<Option<Currency>, i128> {
+		let mut sums: BTreeMap<Option<Currency>, i128> = BTreeMap::new();
+		for ((currency, _), units) in &self.accounts {
+			*sums.entry(*currency).or_default() += units;
+		}
+		for balances in clients {
+			for (currency, balance) in balances {
+				*sums.entry(*currency).or_default() +=
+					balance.available.to_units() + balance.held.to_units();
+			}
+		}
+		sums
+	}
+}
@@ -1,17 +1,49 @@
+mod currency;
+mod fees;
 mod fixed_decimal;
+mod json;
+mod ledger;
 mod processor;
+mod rates;
+mod report;
 mod spam_tolerant_reader;
 mod transaction;
+mod wire;
+
+pub use currency::Currency;
+pub use currency::ErrorKind as CurrencyError;
+
+pub use fees::ErrorKind as FeeScheduleError;
+pub use fees::{Fee, FeeKind, FeeReversal, FeeSchedule};
 
 pub use fixed_decimal::ErrorKind as FixedDecimalError;
 pub use fixed_decimal::FixedDecimal;
+pub use fixed_decimal::Rounding;
 pub use fixed_decimal::MAX as FixedDecimalMAX;
 pub use fixed_decimal::MAX_DISP_LEN as FixedDecimalMAXDISPLEN;
 
 pub use transaction::ErrorKind as TransactionError;
-pub use transaction::{PaymentsTransaction, TransactionPayload};
+pub use transaction::MAX_KEY_LEN as IdempotencyKeyMAXLEN;
+pub use transaction::{PaymentsTransaction, TransactionPayload, UnknownFields};
+
+pub use processor::BatchError;
+pub use processor::DeltaError;
+pub use processor::{
+	ConversionAudit, Discrepancy, PaymentsProcessor, ProcessTransactionError, ProcessorFault,
+	TransactionState,
+};
 
-pub use processor::{PaymentsProcessor, ProcessTransactionError};
+pub use rates::ErrorKind as RateTableError;
+pub use rates::RateTable;
+
+pub use report::{
+	AccountChange, AccountStatement, CsvReport, DeltaReport, JsonLinesReport, JsonReport,
+	ReportWriter,
+};
 
 pub use spam_tolerant_reader::ErrorKind as SpamReaderError;
 pub use spam_tolerant_reader::SpamTolerantReader;
+
+pub use wire::ErrorKind as WireError;
+pub use wire::{WireReader, WireWriter};
+pub use wire::{MAGIC as WireMAGIC, RECORD_LEN as WireRECORDLEN, VERSION as WireVERSION};
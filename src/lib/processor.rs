@@ -8,12 +8,28 @@
 // 	fn output(&self);
 // }
 
-use crate::fixed_decimal::FixedDecimal as FixDec;
+use crate::currency::Currency;
+use crate::fees::{FeeKind, FeeReversal, FeeSchedule};
+use crate::fixed_decimal::{FixedDecimal as FixDec, Rounding};
+use crate::ledger::{Account, Balance, Entry, Ledger, Posting, PostingError};
+use crate::rates::RateTable;
+use crate::report::{AccountStatement, CsvReport, ReportWriter};
 use crate::transaction::{PaymentsTransaction, TransactionPayload as TrPl};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+mod changes;
+mod clients;
+mod idempotency;
+mod journal;
+use changes::ChangeLog;
+pub use changes::ErrorKind as DeltaError;
+use clients::ClientTable;
+use idempotency::IdempotencyStore;
+pub use journal::ErrorKind as BatchError;
+use journal::Journal;
 
 /// Describes the kinds of errors that may arise while a [PaymentsProcessor] processes [PaymentsTransaction]s
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ProcessTransactionError {
 	NoAvailableFunds,
 	AssociatedTransactionNoFound,
@@ -25,32 +41,252 @@ pub enum ProcessTransactionError {
 	UndisputedTransactionCannotBeChargedBack,
 	ClientNotFound,
 	AccountFrozen,
+	InvalidDisputeAmount,
+	DisputedAmountExceedsTransaction,
+	AmountExceedsDisputedFunds,
+	DisputeRoundsExhausted,
+	TransactionCouldNotBeRepresented,
+	TransactionCouldNotBePreArbitrated,
+	TransactionNotChargedBack,
+	ChargebackAlreadyRepresented,
+	TransactionNotRepresented,
+	/// A represented chargeback has to be contested by a pre-arbitration before it is charged back again
+	RepresentmentNotPreArbitrated,
+	ConversionRateNotFound,
+	ConversionOverflow,
+	FeeExceedsAmount,
+	FeeOverflow,
+	/// The transaction would grow a balance (available, held or their total) beyond the maximum balance
+	BalanceOverflow,
+	DuplicateTransaction,
+	/// The idempotency key was already used by a transaction with a different content
+	IdempotencyKeyReused,
+	/// The processor's own bookkeeping is broken, the transaction was not applied and the processor is poisoned
+	Fault(ProcessorFault),
+	/// A fault occurred earlier, no further transactions are accepted
+	ProcessorPoisoned,
 }
 use ProcessTransactionError as TrErr;
 
-#[doc(hidden)]
+/// A broken invariant of the [PaymentsProcessor]'s own bookkeeping, as opposed to a rejected transaction.\
+/// The transaction raising it is not applied, so the balances stay those of the last consistent state
+/// and can still be reported, but the processor is poisoned and accepts no further transactions
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProcessorFault {
+	/// A posting to the books was rejected even though the transaction was valid
+	PostingRejected { client: u16, tx: u32 },
+	/// The amounts disputed and charged back against a transaction exceed the transaction amount
+	DisputedAmountOutOfRange { client: u16, tx: u32 },
+	/// Undoing an applied transaction was rejected by the books
+	UndoRejected { client: u16, tx: u32 },
+	/// The fees collected by the house account fall outside of [FixDec]'s range
+	HouseBalanceOutOfRange { currency: Option<Currency> },
+}
+
+/// Audit trail of an executed conversion, kept along with the conversion transaction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConversionAudit {
+	pub rate: FixDec,
+	pub rounding: Rounding,
+	/// The amount credited to the target currency
+	pub credited: FixDec,
+	/// The exact converted amount minus the credited amount, see [crate::FixedDecimal::checked_mul]
+	pub remainder: i64,
+}
+
+/// An inconsistency between the recorded transactions and the balances or books of a [PaymentsProcessor],
+/// as reported by [PaymentsProcessor::verify].\
+/// Expected amounts are [None] when their recomputation falls outside of [FixDec]'s range
+#[non_exhaustive]
 #[derive(Debug, PartialEq)]
-enum TransactionState {
+pub enum Discrepancy {
+	AvailableMismatch {
+		client: u16,
+		currency: Option<Currency>,
+		recorded: FixDec,
+		expected: Option<FixDec>,
+	},
+	HeldMismatch {
+		client: u16,
+		currency: Option<Currency>,
+		recorded: FixDec,
+		expected: Option<FixDec>,
+	},
+	TotalOverflow {
+		client: u16,
+		currency: Option<Currency>,
+	},
+	LockedWithoutChargeback {
+		client: u16,
+	},
+	/// The held amount of a transaction does not match the disputes raised and settled against it
+	DisputedAmountMismatch {
+		client: u16,
+		tx: u32,
+	},
+	/// The charged back amount of a transaction does not match the chargebacks and representments against it
+	ChargedBackMismatch {
+		client: u16,
+		tx: u32,
+	},
+	HouseMismatch {
+		currency: Option<Currency>,
+		recorded: FixDec,
+		expected: Option<FixDec>,
+	},
+	UnbalancedBooks {
+		currency: Option<Currency>,
+	},
+}
+
+//A resolved transaction may be disputed again, but only up to this many dispute rounds in total
+#[doc(hidden)]
+const MAX_DISPUTE_ROUNDS: u8 = 2;
+
+// Dispute lifecycle of a (withdrawal) transaction, any transition not listed is illegal
+// and is rejected with a dedicated error (see the transition table test in processor_tests.rs)
+//
+// Executed       --dispute-->        UnderDispute
+// UnderDispute   --dispute-->        UnderDispute   (concurrent partial dispute)
+// UnderDispute   --resolve-->        UnderDispute | Resolved | ChargedBack  (partial | last portion settled)
+// UnderDispute   --chargeback-->     UnderDispute | ChargedBack
+// Resolved       --dispute-->        UnderDispute   (re-dispute, while dispute rounds remain)
+// ChargedBack    --representment-->  Represented    (chargeback reversal, at most once)
+// Represented    --prearbitration--> PreArbitration
+// PreArbitration --resolve-->        PreArbitration | Resolved | ChargedBack
+// PreArbitration --chargeback-->     PreArbitration | ChargedBack
+/// Where a transaction stands in its dispute lifecycle, see [PaymentsProcessor::transaction_state]
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransactionState {
+	/// Applied and never disputed (or not disputable at all)
 	Executed,
-	UnderDispute,
+	/// The outstanding held portion, the sum of all concurrent (partial) disputes not yet settled
+	UnderDispute(FixDec),
 	Resolved,
 	ChargedBack,
+	/// The charged back amount that was reversed back to the client
+	Represented(FixDec),
+	/// The outstanding held portion of a representment being contested
+	PreArbitration(FixDec),
 }
 use TransactionState as TrS;
 
+impl TransactionState {
+	fn outstanding(&self) -> Option<FixDec> {
+		match self {
+			TrS::UnderDispute(held) | TrS::PreArbitration(held) => Some(*held),
+			_ => None,
+		}
+	}
+}
+
+//cumulative amounts moved by each step of the dispute lifecycle (in units of the smallest digit,
+//since several dispute rounds may exceed FixedDecimal's range), so that balances can be recomputed
+#[doc(hidden)]
+#[derive(Debug, Default, Clone, PartialEq)]
+struct DisputeTally {
+	disputed: i128,
+	//the part of disputed taken back from the client's available funds by a later dispute round
+	reopened: i128,
+	resolved: i128,
+	charged_back: i128,
+	represented: i128,
+	prearbitrated: i128,
+}
+
+#[doc(hidden)]
+#[derive(Debug, Clone, PartialEq)]
+struct TransactionRecord {
+	payload: TrPl,
+	currency: Option<Currency>,
+	state: TransactionState,
+	dispute_rounds: u8,
+	//disputed portions currently charged back, reset when the chargeback is represented
+	charged_back: FixDec,
+	//disputed portions resolved back to the client and not taken back by a later dispute round
+	resolved: FixDec,
+	represented: bool,
+	conversion: Option<ConversionAudit>,
+	//fee charged to the client on top of (withdrawals) or out of (deposits) the transaction amount
+	fee: FixDec,
+	fee_reversed: bool,
+	tally: DisputeTally,
+}
+
+impl TransactionRecord {
+	fn new(payload: TrPl, currency: Option<Currency>) -> Self {
+		TransactionRecord {
+			payload,
+			currency,
+			state: TrS::Executed,
+			dispute_rounds: 0,
+			charged_back: FixDec::default(),
+			resolved: FixDec::default(),
+			represented: false,
+			conversion: None,
+			fee: FixDec::default(),
+			fee_reversed: false,
+			tally: DisputeTally::default(),
+		}
+	}
+
+	//once every disputed portion has been settled the transaction is considered charged back
+	//if any of the portions was charged back
+	fn settled(&self, remaining: FixDec, charged_back: FixDec) -> TransactionState {
+		if remaining != FixDec::default() {
+			match self.state {
+				TrS::PreArbitration(_) => TrS::PreArbitration(remaining),
+				_ => TrS::UnderDispute(remaining),
+			}
+		} else if charged_back == FixDec::default() {
+			TrS::Resolved
+		} else {
+			TrS::ChargedBack
+		}
+	}
+}
+
+#[doc(hidden)]
+type Balances = BTreeMap<Option<Currency>, Balance>;
+
 #[doc(hidden)]
 #[derive(Debug, Default)]
 struct ClientState {
-	available: FixDec,
-	held: FixDec,
+	//one balance per currency (None being the default unnamed currency) so that amounts in
+	//different currencies are never mixed, transactions always act on the balance of their own currency
+	balances: Balances,
 	locked: bool,
-	transactions: HashMap<u32, (TrPl, TransactionState)>,
+	//locked by an operator rather than by a chargeback, kept apart so that audits can still tell
+	//a chargeback lock without any chargeback
+	frozen: bool,
+	//times an operator lifted the locks, which undoing transactions applied before must not reinstate
+	unlocks: u64,
+	transactions: HashMap<u32, TransactionRecord>,
+}
+
+impl ClientState {
+	fn is_locked(&self) -> bool {
+		self.locked || self.frozen
+	}
 }
 
 /// A type that accepts and processes [PaymentsTransaction]s and can output its state per specification
 #[derive(Debug, Default)]
 pub struct PaymentsProcessor {
-	data: HashMap<u16, ClientState>,
+	data: ClientTable,
+	rates: RateTable,
+	fees: FeeSchedule,
+	//books of every account other than the client balances, every balance change is posted through it
+	ledger: Ledger,
+	//set by the first fault, after which no transaction is accepted
+	fault: Option<ProcessorFault>,
+	//inverse operations of the transactions applied within the open batch
+	journal: Journal,
+	//outcomes of the recent transactions carrying an idempotency key
+	idempotency: IdempotencyStore,
+	//clients changed since the recent delta reports
+	changes: ChangeLog,
 }
 
 #[doc(hidden)]
@@ -61,170 +297,738 @@ fn get_withdrawal_amount_or_err(tx: &TrPl, er: TrErr) -> Result<FixDec, TrErr> {
 	}
 }
 
+#[doc(hidden)]
+fn get_settled_amount_or_err(
+	requested: Option<FixDec>,
+	tr_state: &TransactionState,
+	er: TrErr,
+) -> Result<(FixDec, FixDec), TrErr> {
+	let outstanding = tr_state.outstanding().ok_or(er)?;
+	let amount = requested.unwrap_or(outstanding);
+	if amount == FixDec::default() {
+		return Err(TrErr::InvalidDisputeAmount);
+	}
+	let remaining = outstanding
+		.checked_sub(amount)
+		.ok_or(TrErr::AmountExceedsDisputedFunds)?;
+	Ok((amount, remaining))
+}
+
 #[doc(hidden)]
 fn find_transaction(
 	clientstate: &mut ClientState,
 	tx_id: u32,
-) -> Result<
-	(
-		&mut TrPl,
-		&mut TransactionState,
-		&mut FixDec,
-		&mut FixDec,
-		&mut bool,
-	),
-	TrErr,
-> {
-	if let Some((a, b)) = clientstate.transactions.get_mut(&tx_id) {
-		Ok((
-			a,
-			b,
-			&mut clientstate.available,
-			&mut clientstate.held,
-			&mut clientstate.locked,
-		))
+) -> Result<(&mut TransactionRecord, &mut Balances, &mut bool), TrErr> {
+	let record = clientstate
+		.transactions
+		.get_mut(&tx_id)
+		.ok_or(TrErr::AssociatedTransactionNoFound)?;
+	Ok((record, &mut clientstate.balances, &mut clientstate.locked))
+}
+
+#[doc(hidden)]
+fn find_client(data: &mut ClientTable, cl: u16) -> Result<&mut ClientState, TrErr> {
+	if let Some(entry) = data.get_mut(cl) {
+		Ok(entry)
 	} else {
-		Err(TrErr::AssociatedTransactionNoFound)
+		Err(TrErr::ClientNotFound)
 	}
 }
 
+//the only posting failures a client may cause are overdrawing their available funds and overflowing
+//their balance or the house account, anything else means that the bookkeeping of the processor is broken
 #[doc(hidden)]
-impl PaymentsProcessor {
-	fn find_client(&mut self, cl: u16) -> Result<&mut ClientState, TrErr> {
-		if let Some(entry) = self.data.get_mut(&cl) {
-			Ok(entry)
-		} else {
-			Err(TrErr::ClientNotFound)
-		}
+fn posting_err(err: PostingError, client: u16, tx: u32) -> TrErr {
+	match err {
+		PostingError::Underflow(Account::Available(_)) => TrErr::NoAvailableFunds,
+		PostingError::Overflow(Account::Available(_) | Account::Held(_))
+		| PostingError::ExceedsMaxBalance => TrErr::BalanceOverflow,
+		PostingError::Overflow(Account::Fees) => TrErr::FeeOverflow,
+		_ => TrErr::Fault(ProcessorFault::PostingRejected { client, tx }),
 	}
+}
 
-	fn process_deposit(&mut self, cl: u16, tx_id: u32, amount: FixDec) -> Result<(), TrErr> {
-		let entry = self.data.entry(cl).or_insert_with(Default::default);
-		entry
-			.transactions
-			.insert(tx_id, (TrPl::Deposit(amount), TransactionState::Executed))
-			.is_none()
-			.then(|| ())
-			.expect("Duplicate transaction ID enountered during deposit! which is against spec");
-		entry.available = entry
-			.available
-			.checked_add(amount)
-			.expect("Invariant Violation... Available funds underflow");
-		Ok(())
+//the entries giving the fee of a transaction settling into the provided state back to the client
+//out of the house account, if the reversal policy says so
+#[doc(hidden)]
+fn fee_reversal(
+	record: &TransactionRecord,
+	settled: &TransactionState,
+	policy: FeeReversal,
+	cl: u16,
+) -> Option<[Entry; 2]> {
+	let due = matches!(
+		(settled, policy),
+		(TrS::Resolved, FeeReversal::OnResolve | FeeReversal::Always)
+			| (
+				TrS::ChargedBack,
+				FeeReversal::OnChargeback | FeeReversal::Always
+			)
+	);
+	(due && !record.fee_reversed && record.fee != FixDec::default()).then(|| {
+		[
+			Entry::debit(record.currency, Account::Fees, record.fee),
+			Entry::credit(record.currency, Account::Available(cl), record.fee),
+		]
+	})
+}
+
+#[doc(hidden)]
+impl PaymentsProcessor {
+	fn process_deposit(
+		&mut self,
+		cl: u16,
+		tx_id: u32,
+		amount: FixDec,
+		currency: Option<Currency>,
+	) -> Result<Posting, TrErr> {
+		let fee = self
+			.fees
+			.fee_for(FeeKind::Deposit, cl, amount)
+			.ok_or(TrErr::FeeOverflow)?;
+		let credited = amount.checked_sub(fee).ok_or(TrErr::FeeExceedsAmount)?;
+		//transaction IDs are unique per spec, a repeated one is rejected rather than trusted
+		if self
+			.data
+			.get(cl)
+			.is_some_and(|x| x.transactions.contains_key(&tx_id))
+		{
+			return Err(TrErr::DuplicateTransaction);
+		}
+		let entry = self.data.get_or_insert(cl);
+		let posting = self
+			.ledger
+			.post(
+				&mut entry.balances,
+				&[
+					Entry::debit(currency, Account::ExternalFunding, amount),
+					Entry::credit(currency, Account::Available(cl), credited),
+					Entry::credit(currency, Account::Fees, fee),
+				],
+			)
+			.map_err(|err| posting_err(err, cl, tx_id))?;
+		let mut record = TransactionRecord::new(TrPl::Deposit(amount), currency);
+		record.fee = fee;
+		entry.transactions.insert(tx_id, record);
+		Ok(posting)
 	}
 
 	//any transaction other than deposits not referencing an existing client will not add one to the Processor so that space is not wasted
 	//this is ofcourse only due to spec output requirements , a proper implementation would record all (valid) transactions
-	fn process_withdrawal(&mut self, cl: u16, tx_id: u32, amount: FixDec) -> Result<(), TrErr> {
-		let entry = self.find_client(cl)?;
+	fn process_withdrawal(
+		&mut self,
+		cl: u16,
+		tx_id: u32,
+		amount: FixDec,
+		currency: Option<Currency>,
+	) -> Result<Posting, TrErr> {
+		//the fee is charged on top of the withdrawn amount
+		let debited = self
+			.fees
+			.fee_for(FeeKind::Withdrawal, cl, amount)
+			.and_then(|fee| Some((fee, amount.checked_add(fee)?)));
+		let entry = find_client(&mut self.data, cl)?;
 		// appplying logic from https://www.google.com/search?client=firefox-b-d&q=can+you+deposit+on+a+frozen+account%3F
-		(!entry.locked).then(|| ()).ok_or(TrErr::AccountFrozen)?;
-		entry
-			.transactions
-			.insert(tx_id, (TrPl::Withdrawal(amount), TrS::Executed))
-			.is_none()
-			.then(|| ())
-			.expect("Duplicate transaction ID enountered during withdrawal! which is against spec");
-		entry.available = entry
-			.available
-			.checked_sub(amount)
-			.ok_or(TrErr::NoAvailableFunds)?;
-		Ok(())
+		(!entry.is_locked())
+			.then_some(())
+			.ok_or(TrErr::AccountFrozen)?;
+		if entry.transactions.contains_key(&tx_id) {
+			return Err(TrErr::DuplicateTransaction);
+		}
+		let (fee, debited) = debited.ok_or(TrErr::FeeOverflow)?;
+		//a client never funded in this currency has no funds to withdraw
+		if !entry.balances.contains_key(&currency) {
+			return Err(TrErr::NoAvailableFunds);
+		}
+		let posting = self
+			.ledger
+			.post(
+				&mut entry.balances,
+				&[
+					Entry::debit(currency, Account::Available(cl), debited),
+					Entry::credit(currency, Account::ExternalFunding, amount),
+					Entry::credit(currency, Account::Fees, fee),
+				],
+			)
+			.map_err(|err| posting_err(err, cl, tx_id))?;
+		let mut record = TransactionRecord::new(TrPl::Withdrawal(amount), currency);
+		record.fee = fee;
+		entry.transactions.insert(tx_id, record);
+		Ok(posting)
 	}
 
-	fn process_dispute(&mut self, cl: u16, tx_id: u32) -> Result<(), TrErr> {
-		let (tx, tr_state, _, held, _) = find_transaction(self.find_client(cl)?, tx_id)?;
-		//it only makes sense for withdrawals to be disputed
-		let amount = get_withdrawal_amount_or_err(tx, TrErr::TransactionCouldNotBeDisputed)?;
-		if *tr_state != TrS::Executed {
-			return Err(TrErr::TransactionAlreadyDisputed);
+	fn process_convert(
+		&mut self,
+		cl: u16,
+		tx_id: u32,
+		amount: FixDec,
+		from: Option<Currency>,
+		to: Currency,
+	) -> Result<Posting, TrErr> {
+		//the default currency has no code, so it can never be found in the rate table
+		let rate = from
+			.and_then(|from| self.rates.get(from, to))
+			.ok_or(TrErr::ConversionRateNotFound)?;
+		let rounding = self.rates.rounding();
+		let (credited, remainder) = amount
+			.checked_mul(rate, rounding)
+			.ok_or(TrErr::ConversionOverflow)?;
+		let entry = find_client(&mut self.data, cl)?;
+		(!entry.is_locked())
+			.then_some(())
+			.ok_or(TrErr::AccountFrozen)?;
+		if entry.transactions.contains_key(&tx_id) {
+			return Err(TrErr::DuplicateTransaction);
 		}
-		//Discussion point, invariant violation (held <= total, and total cannot overflow *see deposit logic*)
-		//do we panic, assert, return error, unreachable!()?
-		let new_held = held
-			.checked_add(amount)
-			.expect("Invariant Violation... Held funds amount overflow");
-		*tr_state = TrS::UnderDispute;
-		*held = new_held;
-		Ok(())
+		if !entry.balances.contains_key(&from) {
+			return Err(TrErr::NoAvailableFunds);
+		}
+		//both legs clear through the FX clearing account of their own currency
+		let posting = self
+			.ledger
+			.post(
+				&mut entry.balances,
+				&[
+					Entry::debit(from, Account::Available(cl), amount),
+					Entry::credit(from, Account::FxClearing, amount),
+					Entry::debit(Some(to), Account::FxClearing, credited),
+					Entry::credit(Some(to), Account::Available(cl), credited),
+				],
+			)
+			.map_err(|err| posting_err(err, cl, tx_id))?;
+		let mut record = TransactionRecord::new(TrPl::Convert(amount, to), from);
+		record.conversion = Some(ConversionAudit {
+			rate,
+			rounding,
+			credited,
+			remainder,
+		});
+		entry.transactions.insert(tx_id, record);
+		Ok(posting)
 	}
 
-	fn process_resolve(&mut self, cl: u16, tx_id: u32) -> Result<(), TrErr> {
-		let (tx, tr_state, available, held, _) = find_transaction(self.find_client(cl)?, tx_id)?;
-		let amount = get_withdrawal_amount_or_err(tx, TrErr::TransactionCouldNotBeResolved)?;
-		if *tr_state != TrS::UnderDispute {
-			return Err(TrErr::UndisputedTransactionCannotBeResolved);
+	fn process_dispute(
+		&mut self,
+		cl: u16,
+		tx_id: u32,
+		amount: Option<FixDec>,
+	) -> Result<Posting, TrErr> {
+		let (record, balances, _) = find_transaction(find_client(&mut self.data, cl)?, tx_id)?;
+		//it only makes sense for withdrawals to be disputed
+		let tx_amount =
+			get_withdrawal_amount_or_err(&record.payload, TrErr::TransactionCouldNotBeDisputed)?;
+		//several partial disputes may be raised concurrently against the same transaction, a new round
+		//may also reopen what the earlier ones resolved
+		let (outstanding, dispute_rounds, reopenable) = match record.state {
+			TrS::Executed => (FixDec::default(), 1, FixDec::default()),
+			TrS::UnderDispute(outstanding) => (outstanding, record.dispute_rounds, FixDec::default()),
+			TrS::Resolved if record.dispute_rounds < MAX_DISPUTE_ROUNDS => (
+				FixDec::default(),
+				record.dispute_rounds + 1,
+				record.resolved,
+			),
+			TrS::Resolved => return Err(TrErr::DisputeRoundsExhausted),
+			_ => return Err(TrErr::TransactionAlreadyDisputed),
+		};
+		let out_of_range = ProcessorFault::DisputedAmountOutOfRange {
+			client: cl,
+			tx: tx_id,
+		};
+		let undisputed = tx_amount
+			.checked_sub(outstanding)
+			.and_then(|x| x.checked_sub(record.charged_back))
+			.and_then(|x| x.checked_sub(record.resolved))
+			.ok_or(TrErr::Fault(out_of_range.clone()))?;
+		let disputable = undisputed
+			.checked_add(reopenable)
+			.ok_or(TrErr::Fault(out_of_range.clone()))?;
+		let amount = amount.unwrap_or(disputable);
+		if amount == FixDec::default() {
+			return Err(TrErr::InvalidDisputeAmount);
 		}
-		let new_held = held
-			.checked_sub(amount)
-			.expect("Invariant Violation... Held funds amount underflow");
-		let new_available = available
+		if amount > disputable {
+			return Err(TrErr::DisputedAmountExceedsTransaction);
+		}
+		let new_outstanding = outstanding
 			.checked_add(amount)
-			.expect("Invariant Violation... Available funds amount overflow");
-		*tr_state = TrS::Resolved;
-		*held = new_held;
-		*available = new_available;
-		Ok(())
+			.ok_or(TrErr::Fault(out_of_range))?;
+		//the undisputed funds come back from outside the system and are held until the dispute settles,
+		//the rest being taken back from the funds an earlier round resolved to the client
+		let reopened = amount.checked_sub(undisputed).unwrap_or_default();
+		let fresh = amount.checked_sub(reopened).unwrap_or_default();
+		let mut entries = Vec::new();
+		if fresh != FixDec::default() {
+			entries.push(Entry::debit(
+				record.currency,
+				Account::ExternalFunding,
+				fresh,
+			));
+			entries.push(Entry::credit(record.currency, Account::Held(cl), fresh));
+		}
+		if reopened != FixDec::default() {
+			entries.push(Entry::debit(
+				record.currency,
+				Account::Available(cl),
+				reopened,
+			));
+			entries.push(Entry::credit(record.currency, Account::Held(cl), reopened));
+		}
+		let posting = self
+			.ledger
+			.post(balances, &entries)
+			.map_err(|err| posting_err(err, cl, tx_id))?;
+		record.state = TrS::UnderDispute(new_outstanding);
+		record.resolved = record.resolved.checked_sub(reopened).unwrap_or_default();
+		record.tally.disputed += amount.to_units();
+		record.tally.reopened += reopened.to_units();
+		record.dispute_rounds = dispute_rounds;
+		Ok(posting)
 	}
 
-	fn process_chargeback(&mut self, cl: u16, tx_id: u32) -> Result<(), TrErr> {
-		let (tx, tr_state, _, held, locked) = find_transaction(self.find_client(cl)?, tx_id)?;
-		let amount = get_withdrawal_amount_or_err(tx, TrErr::TransactionCouldNotBeChargedBack)?;
-		if *tr_state != TrS::UnderDispute {
-			return Err(TrErr::UndisputedTransactionCannotBeChargedBack);
+	fn process_resolve(
+		&mut self,
+		cl: u16,
+		tx_id: u32,
+		amount: Option<FixDec>,
+	) -> Result<Posting, TrErr> {
+		let (record, balances, _) = find_transaction(find_client(&mut self.data, cl)?, tx_id)?;
+		get_withdrawal_amount_or_err(&record.payload, TrErr::TransactionCouldNotBeResolved)?;
+		let (amount, remaining) = get_settled_amount_or_err(
+			amount,
+			&record.state,
+			TrErr::UndisputedTransactionCannotBeResolved,
+		)?;
+		let resolved = record.resolved.checked_add(amount).ok_or(TrErr::Fault(
+			ProcessorFault::DisputedAmountOutOfRange {
+				client: cl,
+				tx: tx_id,
+			},
+		))?;
+		let settled = record.settled(remaining, record.charged_back);
+		let fee_reversal = fee_reversal(record, &settled, self.fees.reversal(), cl);
+		let mut entries = vec![
+			Entry::debit(record.currency, Account::Held(cl), amount),
+			Entry::credit(record.currency, Account::Available(cl), amount),
+		];
+		entries.extend(fee_reversal.into_iter().flatten());
+		let posting = self
+			.ledger
+			.post(balances, &entries)
+			.map_err(|err| posting_err(err, cl, tx_id))?;
+		record.tally.resolved += amount.to_units();
+		record.resolved = resolved;
+		record.state = settled;
+		record.fee_reversed |= fee_reversal.is_some();
+		Ok(posting)
+	}
+
+	fn process_chargeback(
+		&mut self,
+		cl: u16,
+		tx_id: u32,
+		amount: Option<FixDec>,
+	) -> Result<Posting, TrErr> {
+		let (record, balances, locked) = find_transaction(find_client(&mut self.data, cl)?, tx_id)?;
+		get_withdrawal_amount_or_err(&record.payload, TrErr::TransactionCouldNotBeChargedBack)?;
+		//a chargeback settles either a dispute or the pre-arbitration contesting a representment,
+		//in which case the client loses the funds held once again
+		match record.state {
+			TrS::UnderDispute(_) | TrS::PreArbitration(_) => (),
+			TrS::Represented(_) => return Err(TrErr::RepresentmentNotPreArbitrated),
+			TrS::Executed | TrS::Resolved | TrS::ChargedBack => {
+				return Err(TrErr::UndisputedTransactionCannotBeChargedBack)
+			}
 		}
-		let new_held = held
-			.checked_sub(amount)
-			.expect("Invariant Violation... Held funds amount underflow");
-		*tr_state = TrS::ChargedBack;
-		*held = new_held;
+		let (amount, remaining) = get_settled_amount_or_err(
+			amount,
+			&record.state,
+			TrErr::UndisputedTransactionCannotBeChargedBack,
+		)?;
+		let charged_back = record.charged_back.checked_add(amount).ok_or(TrErr::Fault(
+			ProcessorFault::DisputedAmountOutOfRange {
+				client: cl,
+				tx: tx_id,
+			},
+		))?;
+		let settled = record.settled(remaining, charged_back);
+		let fee_reversal = fee_reversal(record, &settled, self.fees.reversal(), cl);
+		let mut entries = vec![
+			Entry::debit(record.currency, Account::Held(cl), amount),
+			Entry::credit(record.currency, Account::ChargebackLoss, amount),
+		];
+		entries.extend(fee_reversal.into_iter().flatten());
+		let posting = self
+			.ledger
+			.post(balances, &entries)
+			.map_err(|err| posting_err(err, cl, tx_id))?;
+		record.charged_back = charged_back;
+		record.tally.charged_back += amount.to_units();
+		record.state = settled;
+		record.fee_reversed |= fee_reversal.is_some();
 		*locked = true;
-		Ok(())
+		Ok(posting)
+	}
+
+	//a representment reverses the whole charged back amount back to the client
+	//the account stays locked since it has suffered a chargeback
+	fn process_representment(&mut self, cl: u16, tx_id: u32) -> Result<Posting, TrErr> {
+		let (record, balances, _) = find_transaction(find_client(&mut self.data, cl)?, tx_id)?;
+		get_withdrawal_amount_or_err(&record.payload, TrErr::TransactionCouldNotBeRepresented)?;
+		if record.state != TrS::ChargedBack {
+			return Err(TrErr::TransactionNotChargedBack);
+		}
+		if record.represented {
+			return Err(TrErr::ChargebackAlreadyRepresented);
+		}
+		let amount = record.charged_back;
+		let posting = self
+			.ledger
+			.post(
+				balances,
+				&[
+					Entry::debit(record.currency, Account::ChargebackLoss, amount),
+					Entry::credit(record.currency, Account::Available(cl), amount),
+				],
+			)
+			.map_err(|err| posting_err(err, cl, tx_id))?;
+		record.state = TrS::Represented(amount);
+		record.charged_back = FixDec::default();
+		record.represented = true;
+		record.tally.represented += amount.to_units();
+		Ok(posting)
+	}
+
+	//pre-arbitration contests a representment, holding the represented amount once again
+	fn process_prearbitration(&mut self, cl: u16, tx_id: u32) -> Result<Posting, TrErr> {
+		let (record, balances, _) = find_transaction(find_client(&mut self.data, cl)?, tx_id)?;
+		get_withdrawal_amount_or_err(&record.payload, TrErr::TransactionCouldNotBePreArbitrated)?;
+		let amount = match record.state {
+			TrS::Represented(amount) => amount,
+			_ => return Err(TrErr::TransactionNotRepresented),
+		};
+		let posting = self
+			.ledger
+			.post(
+				balances,
+				&[
+					Entry::debit(record.currency, Account::Available(cl), amount),
+					Entry::credit(record.currency, Account::Held(cl), amount),
+				],
+			)
+			.map_err(|err| posting_err(err, cl, tx_id))?;
+		record.state = TrS::PreArbitration(amount);
+		record.tally.prearbitrated += amount.to_units();
+		Ok(posting)
 	}
 }
 
 impl PaymentsProcessor {
-	///Attempts to process the provided [PaymentsTransaction]
+	///Attempts to process the provided [PaymentsTransaction].\
+	///A retry of a transaction carrying a remembered idempotency key returns the original outcome
+	///without being applied again, while reusing the key for a different transaction is rejected
+	///with [ProcessTransactionError::IdempotencyKeyReused]
 	pub fn process_transaction(&mut self, tx: PaymentsTransaction) -> Result<(), TrErr> {
-		match tx.payload {
-			TrPl::Deposit(amount) => self.process_deposit(tx.client, tx.tx, amount),
-			TrPl::Withdrawal(amount) => self.process_withdrawal(tx.client, tx.tx, amount),
-			TrPl::Dispute => self.process_dispute(tx.client, tx.tx),
-			TrPl::Resolve => self.process_resolve(tx.client, tx.tx),
-			TrPl::ChargeBack => self.process_chargeback(tx.client, tx.tx),
+		if self.fault.is_some() {
+			return Err(TrErr::ProcessorPoisoned);
+		}
+		if let Some(outcome) = self.idempotency.lookup(&tx) {
+			return outcome;
+		}
+		let keyed = tx.idempotency_key.is_some().then(|| tx.clone());
+		let before = self.unrecorded(tx.client);
+		let snapshot = self
+			.journal
+			.is_active()
+			.then(|| self.snapshot(tx.client, tx.tx));
+		let res = match tx.payload {
+			TrPl::Deposit(amount) => self.process_deposit(tx.client, tx.tx, amount, tx.currency),
+			TrPl::Withdrawal(amount) => self.process_withdrawal(tx.client, tx.tx, amount, tx.currency),
+			TrPl::Dispute(amount) => self.process_dispute(tx.client, tx.tx, amount),
+			TrPl::Resolve(amount) => self.process_resolve(tx.client, tx.tx, amount),
+			TrPl::ChargeBack(amount) => self.process_chargeback(tx.client, tx.tx, amount),
+			TrPl::Representment => self.process_representment(tx.client, tx.tx),
+			TrPl::PreArbitration => self.process_prearbitration(tx.client, tx.tx),
+			TrPl::Convert(amount, to) => self.process_convert(tx.client, tx.tx, amount, tx.currency, to),
+		};
+		let res = match res {
+			Ok(posting) => {
+				self.record_change(tx.client, before);
+				if let Some(snapshot) = snapshot {
+					self.journal.record(tx.client, tx.tx, snapshot, posting);
+				}
+				Ok(())
+			}
+			Err(TrErr::Fault(fault)) => {
+				self.fault = Some(fault.clone());
+				return Err(TrErr::Fault(fault));
+			}
+			Err(err) => Err(err),
+		};
+		if let Some(keyed) = keyed {
+			self.idempotency.register(keyed, &res);
+		}
+		res
+	}
+
+	/// Returns the fault that poisoned the processor, if any
+	pub fn fault(&self) -> Option<&ProcessorFault> {
+		self.fault.as_ref()
+	}
+
+	/// Sets the [RateTable] used to process conversions
+	pub fn with_rates(mut self, rates: RateTable) -> Self {
+		self.rates = rates;
+		self
+	}
+
+	/// Caps the total (available + held) of every client balance, transactions that would grow
+	/// a balance beyond it are rejected with [ProcessTransactionError::BalanceOverflow]
+	pub fn with_max_balance(mut self, max_balance: FixDec) -> Self {
+		self.ledger.set_max_balance(max_balance);
+		self
+	}
+
+	/// Remembers the outcomes of the last **retention** transactions carrying an idempotency key (100000 by default).\
+	/// Retries of a remembered transaction are answered with its original outcome without being applied again,
+	/// older keys are forgotten so their retries are processed as new transactions.
+	///
+	/// The retention window is a number of keys, not a duration: the processor never reads a clock,
+	/// so that replaying the same input always gives the same outcome. Size it after the number of
+	/// keyed transactions that may arrive within the longest retry delay of the upstream systems
+	pub fn with_idempotency_retention(mut self, retention: usize) -> Self {
+		self.idempotency.set_retention(retention);
+		self
+	}
+
+	/// Sets the [FeeSchedule] applied to deposits and withdrawals
+	pub fn with_fees(mut self, fees: FeeSchedule) -> Self {
+		self.fees = fees;
+		self
+	}
+
+	/// Returns the state of every client balance, sorted by client and currency
+	pub fn statements(&self) -> Vec<AccountStatement> {
+		self.statement_iter().collect()
+	}
+
+	//spec sugests that the output is sorted by client ID (so unit tests are also built using that assumption),
+	//clients are stored in ID order and their balances in currency order so that reports stream as they are
+	#[doc(hidden)]
+	fn statement_iter(&self) -> impl Iterator<Item = AccountStatement> + '_ {
+		self
+			.data
+			.iter()
+			.flat_map(|(cl, state)| client_statements(cl, state))
+	}
+
+	/// Returns the state of every balance of a client, sorted by currency
+	pub fn client_statements(&self, client: u16) -> Vec<AccountStatement> {
+		self
+			.data
+			.get(client)
+			.map(|state| client_statements(client, state).collect())
+			.unwrap_or_default()
+	}
+
+	/// Renders the state of every client balance with the provided [ReportWriter]
+	pub fn write_report(
+		&self,
+		writer: &dyn ReportWriter,
+		out: &mut dyn std::fmt::Write,
+	) -> std::fmt::Result {
+		writer.write_statements(
+			out,
+			&mut self.statement_iter(),
+			self.ledger.has_named_balances(),
+		)
+	}
+
+	/// Returns the fee charged for a transaction, if the transaction exists
+	pub fn transaction_fee(&self, client: u16, tx: u32) -> Option<FixDec> {
+		Some(self.data.get(client)?.transactions.get(&tx)?.fee)
+	}
+
+	/// Returns the dispute state of a transaction, if the transaction exists
+	pub fn transaction_state(&self, client: u16, tx: u32) -> Option<TransactionState> {
+		Some(self.data.get(client)?.transactions.get(&tx)?.state.clone())
+	}
+
+	/// Returns the fees collected by the house account in the provided currency
+	pub fn house_balance(&self, currency: Option<Currency>) -> Result<FixDec, ProcessorFault> {
+		FixDec::from_units(self.ledger.balance(currency, Account::Fees))
+			.ok_or(ProcessorFault::HouseBalanceOutOfRange { currency })
+	}
+
+	/// Runs a trial balance over the books, every posting debits exactly as much as it credits
+	/// so the balances of all accounts (clients' included) must sum to zero in every currency
+	pub fn is_balanced(&self) -> bool {
+		self
+			.ledger
+			.trial_balance(self.data.values().map(|x| &x.balances))
+			.values()
+			.all(|x| *x == 0)
+	}
+
+	/// Returns the number of postings made to the books
+	pub fn postings(&self) -> u64 {
+		self.ledger.postings()
+	}
+
+	/// Walks every client, recomputes their balances (and the house account) out of the recorded transactions
+	/// and their dispute lifecycle, and reports every [Discrepancy] found, sorted by client and transaction
+	pub fn verify(&self) -> Vec<Discrepancy> {
+		let mut found = Vec::new();
+		let mut expected_fees: BTreeMap<Option<Currency>, i128> = BTreeMap::new();
+		for (client, state) in self.data.iter() {
+			//expected (available, held) per currency
+			let mut expected: BTreeMap<Option<Currency>, (i128, i128)> = BTreeMap::new();
+			let mut transactions: Vec<_> = state.transactions.iter().collect();
+			transactions.sort_unstable_by_key(|(tx, _)| **tx);
+			for (&tx, record) in transactions {
+				let fee = if record.fee_reversed {
+					0
+				} else {
+					record.fee.to_units()
+				};
+				*expected_fees.entry(record.currency).or_default() += fee;
+				let tally = &record.tally;
+				let (available, held) = expected.entry(record.currency).or_default();
+				match record.payload {
+					TrPl::Deposit(amount) => *available += amount.to_units() - fee,
+					TrPl::Withdrawal(amount) => {
+						*available += tally.resolved + tally.represented
+							- tally.prearbitrated
+							- tally.reopened
+							- amount.to_units()
+							- fee;
+						let outstanding =
+							tally.disputed + tally.prearbitrated - tally.resolved - tally.charged_back;
+						*held += outstanding;
+						if record.state.outstanding().map_or(0, FixDec::to_units) != outstanding {
+							found.push(Discrepancy::DisputedAmountMismatch { client, tx });
+						}
+						if record.charged_back.to_units() != tally.charged_back - tally.represented {
+							found.push(Discrepancy::ChargedBackMismatch { client, tx });
+						}
+					}
+					TrPl::Convert(amount, to) => {
+						*available -= amount.to_units();
+						expected.entry(Some(to)).or_default().0 +=
+							record.conversion.map_or(0, |x| x.credited.to_units());
+					}
+					_ => {}
+				}
+			}
+			let currencies: BTreeSet<_> = expected
+				.keys()
+				.chain(state.balances.keys())
+				.copied()
+				.collect();
+			for currency in currencies {
+				let balance = state.balances.get(&currency).copied().unwrap_or_default();
+				let (available, held) = expected.get(&currency).copied().unwrap_or_default();
+				if balance.available.to_units() != available {
+					found.push(Discrepancy::AvailableMismatch {
+						client,
+						currency,
+						recorded: balance.available,
+						expected: FixDec::from_units(available),
+					});
+				}
+				if balance.held.to_units() != held {
+					found.push(Discrepancy::HeldMismatch {
+						client,
+						currency,
+						recorded: balance.held,
+						expected: FixDec::from_units(held),
+					});
+				}
+				if balance.available.checked_add(balance.held).is_none() {
+					found.push(Discrepancy::TotalOverflow { client, currency });
+				}
+			}
+			if state.locked
+				&& !state
+					.transactions
+					.values()
+					.any(|x| x.tally.charged_back > 0)
+			{
+				found.push(Discrepancy::LockedWithoutChargeback { client });
+			}
+		}
+		let trial_balance = self
+			.ledger
+			.trial_balance(self.data.values().map(|x| &x.balances));
+		for (&currency, &sum) in &trial_balance {
+			let house = self.ledger.balance(currency, Account::Fees);
+			let fees = expected_fees.get(&currency).copied().unwrap_or_default();
+			if house != fees {
+				found.push(Discrepancy::HouseMismatch {
+					currency,
+					recorded: FixDec::from_units(house).unwrap_or_default(),
+					expected: FixDec::from_units(fees),
+				});
+			}
+			if sum != 0 {
+				found.push(Discrepancy::UnbalancedBooks { currency });
+			}
 		}
+		found
+	}
+
+	/// Locks a client on behalf of an operator, the client's withdrawals and conversions being rejected
+	/// with [ProcessTransactionError::AccountFrozen] until [PaymentsProcessor::unlock_client],
+	/// whatever transactions are undone meanwhile
+	pub fn lock_client(&mut self, client: u16) -> Result<(), TrErr> {
+		let before = self.unrecorded(client);
+		find_client(&mut self.data, client)?.frozen = true;
+		self.record_change(client, before);
+		Ok(())
+	}
+
+	/// Lifts the lock of a client, whether set by an operator or by a chargeback.\
+	/// Operators act outside of the transactions: undoing transactions applied before the unlock
+	/// (rollbacks, savepoints, [PaymentsProcessor::undo_last]) does not lock the client again
+	pub fn unlock_client(&mut self, client: u16) -> Result<(), TrErr> {
+		let before = self.unrecorded(client);
+		let state = find_client(&mut self.data, client)?;
+		state.locked = false;
+		state.frozen = false;
+		state.unlocks += 1;
+		self.record_change(client, before);
+		Ok(())
+	}
+
+	/// Returns the rate and rounding details of an executed conversion
+	pub fn conversion_audit(&self, client: u16, tx: u32) -> Option<ConversionAudit> {
+		self.data.get(client)?.transactions.get(&tx)?.conversion
 	}
 }
 
+#[doc(hidden)]
+fn client_statements(
+	client: u16,
+	state: &ClientState,
+) -> impl Iterator<Item = AccountStatement> + '_ {
+	state
+		.balances
+		.iter()
+		.map(move |(currency, balance)| AccountStatement {
+			client,
+			currency: *currency,
+			available: balance.available,
+			held: balance.held,
+			locked: state.is_locked(),
+		})
+}
+
 impl std::fmt::Display for PaymentsProcessor {
-	///Outputs the state of Self according to specification\
-	///**client,available,held,total,locked**\
-	///**{u16},{FixedDecimal},{FixedDecimal},{FixedDecimal},{true/false}**\
-	///**.**\
-	///**.**\
-	///**.**
+	///Outputs the state of Self according to specification, see [CsvReport]
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
-		//spec is unclear if we need to print the header if there are no clients
-		writeln!(f, "client,available,held,total,locked")?;
-		//spec sugests that the output is sorted by client ID (so unit tests are also built using that assumption)
-		//I will not use a std::collections::BTreeMap internally since it makes my searches O(logN)
-		//but i will collect into a Vec<> and sort before printing (heavy operation but very infrequent)
-		let mut sorted_client_records: Vec<_> = self.data.iter().collect();
-		sorted_client_records.sort_unstable_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap());
-		for (cl, record) in sorted_client_records {
-			writeln!(
-				f,
-				"{},{},{},{},{}",
-				cl,
-				record.available,
-				record.held,
-				record
-					.available
-					.checked_add(record.held)
-					.expect("Invariant Violation, total funds overflow"),
-				record.locked,
-			)?;
-		}
-		Ok(())
+		CsvReport.write_statements(
+			f,
+			&mut self.statement_iter(),
+			self.ledger.has_named_balances(),
+		)
 	}
 }
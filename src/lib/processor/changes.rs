@@ -0,0 +1,165 @@
+use super::{client_statements, PaymentsProcessor};
+use crate::report::{AccountChange, AccountStatement, DeltaReport};
+use std::collections::{BTreeMap, VecDeque};
+
+#[doc(hidden)]
+const DEFAULT_HISTORY: usize = 64;
+
+//the statements of the clients changed between two reports, as they were at the first of them
+#[doc(hidden)]
+type Before = BTreeMap<u16, Vec<AccountStatement>>;
+
+//report IDs are cut one after the other, 0 standing for the empty books the processor starts with.
+//Every client changed is recorded along with its statements before the change, once per interval
+//between two reports, so that the changes since any report kept are told apart from those that were
+//undone or changed back. Only the intervals of the last reports (up to the history) are kept
+#[doc(hidden)]
+#[derive(Debug)]
+pub(super) struct ChangeLog {
+	//clients changed since the last report
+	pending: Before,
+	//clients changed up to each report kept, oldest first
+	intervals: VecDeque<(u64, Before)>,
+	last: u64,
+	history: usize,
+}
+
+impl Default for ChangeLog {
+	fn default() -> Self {
+		ChangeLog {
+			pending: Before::new(),
+			intervals: VecDeque::new(),
+			last: 0,
+			history: DEFAULT_HISTORY,
+		}
+	}
+}
+
+impl ChangeLog {
+	fn evict(&mut self) {
+		while self.intervals.len() > self.history {
+			self.intervals.pop_front();
+		}
+	}
+
+	//the oldest report the changes can be told since
+	fn oldest(&self) -> u64 {
+		self
+			.intervals
+			.front()
+			.map_or(self.last, |(report, _)| report - 1)
+	}
+}
+
+/// Type of error returned by [PaymentsProcessor::delta_report] and [PaymentsProcessor::changes_since]
+#[non_exhaustive]
+#[derive(Debug, PartialEq)]
+pub enum ErrorKind {
+	/// No report with this ID was cut yet
+	UnknownReport,
+	/// The report is older than the history kept, see [PaymentsProcessor::with_report_history]
+	ReportExpired,
+}
+
+impl PaymentsProcessor {
+	//the statements of a client before it changes, None if it was recorded since the last report already.
+	//Taken before a transaction and recorded once it was applied, so that rejected ones leave no trace
+	pub(super) fn unrecorded(&self, client: u16) -> Option<Vec<AccountStatement>> {
+		if self.changes.pending.contains_key(&client) {
+			return None;
+		}
+		Some(self.statements_of(client))
+	}
+
+	pub(super) fn record_change(&mut self, client: u16, before: Option<Vec<AccountStatement>>) {
+		if let Some(before) = before {
+			self.changes.pending.insert(client, before);
+		}
+	}
+
+	//records the state of a client about to change for sure
+	pub(super) fn touch(&mut self, client: u16) {
+		let before = self.unrecorded(client);
+		self.record_change(client, before);
+	}
+
+	fn statements_of(&self, client: u16) -> Vec<AccountStatement> {
+		match self.data.get(client) {
+			Some(state) => client_statements(client, state).collect(),
+			None => Vec::new(),
+		}
+	}
+
+	/// Cuts a new report and returns the clients whose balances or lock state changed since
+	/// report **since**, 0 standing for the empty books the processor started with.\
+	/// Each consumer keeps the ID of the last report it got as a cursor, clients changed and then
+	/// changed back (or undone) in between are left out
+	pub fn delta_report(&mut self, since: u64) -> Result<DeltaReport, ErrorKind> {
+		self.check_report(since)?;
+		self.changes.last += 1;
+		let pending = std::mem::take(&mut self.changes.pending);
+		self
+			.changes
+			.intervals
+			.push_back((self.changes.last, pending));
+		let report = self.changes_since(since);
+		self.changes.evict();
+		report
+	}
+
+	/// Returns the clients changed since report **since** as [PaymentsProcessor::delta_report] does,
+	/// without cutting a new one: the changes are told up to now and the report is the last one cut
+	pub fn changes_since(&self, since: u64) -> Result<DeltaReport, ErrorKind> {
+		self.check_report(since)?;
+		//the earliest state recorded after the report is the one it saw
+		let mut before = Before::new();
+		let intervals = self
+			.changes
+			.intervals
+			.iter()
+			.filter(|(report, _)| *report > since)
+			.map(|(_, interval)| interval);
+		for (client, statements) in intervals.chain([&self.changes.pending]).flatten() {
+			before.entry(*client).or_insert_with(|| statements.clone());
+		}
+		let changes = before
+			.into_iter()
+			.filter_map(|(client, before)| {
+				let after = self.statements_of(client);
+				(before != after).then_some(AccountChange {
+					client,
+					before,
+					after,
+				})
+			})
+			.collect();
+		Ok(DeltaReport {
+			since,
+			report: self.changes.last,
+			changes,
+		})
+	}
+
+	fn check_report(&self, since: u64) -> Result<(), ErrorKind> {
+		if since > self.changes.last {
+			return Err(ErrorKind::UnknownReport);
+		}
+		if since < self.changes.oldest() {
+			return Err(ErrorKind::ReportExpired);
+		}
+		Ok(())
+	}
+
+	/// Returns the ID of the last report cut by [PaymentsProcessor::delta_report]
+	pub fn last_report(&self) -> u64 {
+		self.changes.last
+	}
+
+	/// Keeps the changes of the last **history** reports (64 by default),
+	/// asking for the changes since an older report fails with [ErrorKind::ReportExpired]
+	pub fn with_report_history(mut self, history: usize) -> Self {
+		self.changes.history = history.max(1);
+		self.changes.evict();
+		self
+	}
+}
@@ -0,0 +1,58 @@
+use super::ClientState;
+use std::collections::BTreeSet;
+
+//one slot per client ID
+#[doc(hidden)]
+const SLOTS: usize = u16::MAX as usize + 1;
+
+//client IDs being u16, clients are kept in a dense table indexed by ID rather than hashed: a lookup is
+//a single index and iterating visits clients in ID order, so reports stream without collecting and
+//sorting anything. Slots are boxed so that the table weighs a pointer per ID (512 KiB), and it is only
+//allocated with the first client so that processors left empty cost nothing. The IDs in use are
+//indexed apart, so that walking the clients costs the clients there are rather than every slot
+#[doc(hidden)]
+#[derive(Debug, Default)]
+pub(super) struct ClientTable {
+	slots: Vec<Option<Box<ClientState>>>,
+	occupied: BTreeSet<u16>,
+}
+
+impl ClientTable {
+	pub(super) fn get(&self, client: u16) -> Option<&ClientState> {
+		self.slots.get(client as usize)?.as_deref()
+	}
+
+	pub(super) fn get_mut(&mut self, client: u16) -> Option<&mut ClientState> {
+		self.slots.get_mut(client as usize)?.as_deref_mut()
+	}
+
+	pub(super) fn get_or_insert(&mut self, client: u16) -> &mut ClientState {
+		if self.slots.is_empty() {
+			self.slots.resize_with(SLOTS, || None);
+		}
+		let slot = &mut self.slots[client as usize];
+		if slot.is_none() {
+			self.occupied.insert(client);
+		}
+		slot.get_or_insert_with(Box::default)
+	}
+
+	pub(super) fn remove(&mut self, client: u16) {
+		if let Some(slot) = self.slots.get_mut(client as usize) {
+			*slot = None;
+			self.occupied.remove(&client);
+		}
+	}
+
+	//in client ID order
+	pub(super) fn iter(&self) -> impl Iterator<Item = (u16, &ClientState)> {
+		self
+			.occupied
+			.iter()
+			.filter_map(|&client| Some((client, self.slots[client as usize].as_deref()?)))
+	}
+
+	pub(super) fn values(&self) -> impl Iterator<Item = &ClientState> {
+		self.iter().map(|(_, state)| state)
+	}
+}
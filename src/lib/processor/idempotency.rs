@@ -0,0 +1,77 @@
+use super::TrErr;
+use crate::transaction::PaymentsTransaction;
+use std::collections::{HashMap, VecDeque};
+
+#[doc(hidden)]
+const DEFAULT_RETENTION: usize = 100_000;
+
+//outcomes of the transactions processed with an idempotency key,
+//only the most recently registered keys (up to the retention, a count rather than a duration) are remembered
+#[doc(hidden)]
+#[derive(Debug)]
+pub(super) struct IdempotencyStore {
+	outcomes: HashMap<String, (PaymentsTransaction, Result<(), TrErr>)>,
+	//keys in registration order, oldest first
+	order: VecDeque<String>,
+	//number of keys registered and not forgotten by an undo, marks taken by the journal refer to it
+	registered: u64,
+	retention: usize,
+}
+
+impl Default for IdempotencyStore {
+	fn default() -> Self {
+		IdempotencyStore {
+			outcomes: HashMap::new(),
+			order: VecDeque::new(),
+			registered: 0,
+			retention: DEFAULT_RETENTION,
+		}
+	}
+}
+
+impl IdempotencyStore {
+	pub(super) fn set_retention(&mut self, retention: usize) {
+		self.retention = retention;
+		self.evict();
+	}
+
+	//the outcome of the original transaction if this one is a retry of it
+	pub(super) fn lookup(&self, tx: &PaymentsTransaction) -> Option<Result<(), TrErr>> {
+		let (original, outcome) = self.outcomes.get(tx.idempotency_key.as_ref()?)?;
+		Some(match original == tx {
+			true => outcome.clone(),
+			false => Err(TrErr::IdempotencyKeyReused),
+		})
+	}
+
+	pub(super) fn register(&mut self, tx: PaymentsTransaction, outcome: &Result<(), TrErr>) {
+		if let Some(key) = tx.idempotency_key.clone() {
+			self.outcomes.insert(key.clone(), (tx, outcome.clone()));
+			self.order.push_back(key);
+			self.registered += 1;
+			self.evict();
+		}
+	}
+
+	pub(super) fn registered(&self) -> u64 {
+		self.registered
+	}
+
+	//forgets the keys registered after the mark, newest first
+	pub(super) fn forget_since(&mut self, mark: u64) {
+		while self.registered > mark {
+			self.registered -= 1;
+			if let Some(key) = self.order.pop_back() {
+				self.outcomes.remove(&key);
+			}
+		}
+	}
+
+	fn evict(&mut self) {
+		while self.order.len() > self.retention {
+			if let Some(key) = self.order.pop_front() {
+				self.outcomes.remove(&key);
+			}
+		}
+	}
+}
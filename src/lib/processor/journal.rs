@@ -0,0 +1,300 @@
+use super::{PaymentsProcessor, ProcessorFault, TransactionRecord};
+use crate::ledger::Posting;
+use std::collections::VecDeque;
+
+//the part of a client's state a transaction may change, taken right before it is applied
+#[doc(hidden)]
+#[derive(Debug)]
+pub(super) struct Snapshot {
+	//the transaction creates the client (deposits only)
+	new_client: bool,
+	locked: bool,
+	//operator unlocks of the client so far, an unlock after the transaction outlives its undo
+	unlocks: u64,
+	//None when the transaction creates the record
+	record: Option<TransactionRecord>,
+	//idempotency keys registered before the transaction
+	keys: u64,
+}
+
+//the inverse operation of an applied transaction
+#[doc(hidden)]
+#[derive(Debug)]
+pub(super) struct UndoEntry {
+	client: u16,
+	tx: u32,
+	snapshot: Snapshot,
+	posting: Posting,
+}
+
+//a position in the journal along with the idempotency keys registered up to it
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy)]
+struct Mark {
+	seq: usize,
+	keys: u64,
+}
+
+//positions in the journal are sequence numbers of applied transactions, entries nobody can undo
+//anymore (older than the open batch, the oldest savepoint and the undo depth) are dropped from the front
+#[doc(hidden)]
+#[derive(Debug, Default)]
+pub(super) struct Journal {
+	entries: VecDeque<UndoEntry>,
+	//sequence number of the oldest entry kept
+	base: usize,
+	//position at which the open batch began
+	batch: Option<Mark>,
+	savepoints: Vec<(String, Mark)>,
+	//number of most recent transactions that can always be undone
+	depth: usize,
+}
+
+impl Journal {
+	pub(super) fn is_active(&self) -> bool {
+		self.batch.is_some() || !self.savepoints.is_empty() || self.depth > 0
+	}
+
+	pub(super) fn set_depth(&mut self, depth: usize) {
+		self.depth = depth;
+		self.trim();
+	}
+
+	pub(super) fn record(&mut self, client: u16, tx: u32, snapshot: Snapshot, posting: Posting) {
+		self.entries.push_back(UndoEntry {
+			client,
+			tx,
+			snapshot,
+			posting,
+		});
+		self.trim();
+	}
+
+	fn len(&self) -> usize {
+		self.base + self.entries.len()
+	}
+
+	fn trim(&mut self) {
+		let needed = self
+			.batch
+			.into_iter()
+			.chain(self.savepoints.iter().map(|(_, mark)| *mark))
+			.map(|mark| mark.seq)
+			.chain([self.len().saturating_sub(self.depth)])
+			.min()
+			.unwrap_or_default();
+		while self.base < needed && self.entries.pop_front().is_some() {
+			self.base += 1;
+		}
+	}
+}
+
+/// Type of error returned by the batch API of a [PaymentsProcessor]
+#[non_exhaustive]
+#[derive(Debug, PartialEq)]
+pub enum ErrorKind {
+	BatchAlreadyOpen,
+	NoOpenBatch,
+	DuplicateSavepoint,
+	UnknownSavepoint,
+	/// Fewer transactions than requested can be undone
+	NotEnoughHistory,
+	/// Undoing would reach past the beginning of the open batch
+	UndoBeyondBatch,
+	/// Undoing a transaction failed, the processor is poisoned
+	Fault(ProcessorFault),
+}
+
+impl PaymentsProcessor {
+	pub(super) fn snapshot(&self, client: u16, tx: u32) -> Snapshot {
+		let state = self.data.get(client);
+		Snapshot {
+			new_client: state.is_none(),
+			locked: state.is_some_and(|x| x.locked),
+			unlocks: state.map_or(0, |x| x.unlocks),
+			record: state.and_then(|x| x.transactions.get(&tx)).cloned(),
+			keys: self.idempotency.registered(),
+		}
+	}
+
+	fn mark(&self) -> Mark {
+		Mark {
+			seq: self.journal.len(),
+			keys: self.idempotency.registered(),
+		}
+	}
+
+	//restores the client's balances, lock flag and record to what they were before the transaction
+	fn undo(&mut self, entry: UndoEntry) -> Result<(), ProcessorFault> {
+		let fault = ProcessorFault::UndoRejected {
+			client: entry.client,
+			tx: entry.tx,
+		};
+		self.touch(entry.client);
+		let state = self.data.get_mut(entry.client).ok_or(fault.clone())?;
+		self
+			.ledger
+			.reverse(&mut state.balances, &entry.posting)
+			.map_err(|_| fault)?;
+		//the lock lifted by an operator stays lifted, as do the ones they set (see lock_client)
+		if state.unlocks == entry.snapshot.unlocks {
+			state.locked = entry.snapshot.locked;
+		}
+		self.idempotency.forget_since(entry.snapshot.keys);
+		match entry.snapshot.record {
+			Some(record) => state.transactions.insert(entry.tx, record),
+			None => state.transactions.remove(&entry.tx),
+		};
+		//entries are undone newest first, so nothing else can be left of a client the transaction created
+		if entry.snapshot.new_client {
+			self.data.remove(entry.client);
+		}
+		Ok(())
+	}
+
+	//undoes every transaction after the provided mark, newest first,
+	//forgetting the savepoints set and the idempotency keys registered after it
+	fn undo_to(&mut self, mark: Mark) -> Result<usize, ErrorKind> {
+		let mut undone = 0;
+		while self.journal.len() > mark.seq {
+			let entry = self
+				.journal
+				.entries
+				.pop_back()
+				.ok_or(ErrorKind::NotEnoughHistory)?;
+			if let Err(fault) = self.undo(entry) {
+				self.fault = Some(fault.clone());
+				return Err(ErrorKind::Fault(fault));
+			}
+			undone += 1;
+		}
+		self.journal.savepoints.retain(|(_, x)| x.seq <= mark.seq);
+		self.idempotency.forget_since(mark.keys);
+		Ok(undone)
+	}
+}
+
+impl PaymentsProcessor {
+	/// Opens a batch, the transactions processed until [PaymentsProcessor::commit]
+	/// or [PaymentsProcessor::rollback] are applied all or nothing
+	pub fn begin(&mut self) -> Result<(), ErrorKind> {
+		if self.journal.batch.is_some() {
+			return Err(ErrorKind::BatchAlreadyOpen);
+		}
+		self.journal.batch = Some(self.mark());
+		Ok(())
+	}
+
+	/// Keeps every transaction processed since the batch began
+	pub fn commit(&mut self) -> Result<(), ErrorKind> {
+		self.journal.batch.take().ok_or(ErrorKind::NoOpenBatch)?;
+		self.journal.trim();
+		Ok(())
+	}
+
+	/// Undoes every transaction processed since the batch began, restoring balances,
+	/// lock flags and transaction records exactly as they were (a client unlocked by an operator meanwhile
+	/// staying unlocked, see [PaymentsProcessor::unlock_client]).\
+	/// The idempotency keys of the transactions processed within the batch are forgotten
+	pub fn rollback(&mut self) -> Result<(), ErrorKind> {
+		let start = self.journal.batch.ok_or(ErrorKind::NoOpenBatch)?;
+		self.undo_to(start)?;
+		self.journal.batch = None;
+		self.journal.trim();
+		Ok(())
+	}
+
+	pub fn in_batch(&self) -> bool {
+		self.journal.batch.is_some()
+	}
+
+	/// Marks the current state so that the transactions processed afterwards can be undone
+	/// with [PaymentsProcessor::undo_to_savepoint]
+	pub fn savepoint(&mut self, name: &str) -> Result<(), ErrorKind> {
+		if self.journal.savepoints.iter().any(|(x, _)| x == name) {
+			return Err(ErrorKind::DuplicateSavepoint);
+		}
+		let mark = self.mark();
+		self.journal.savepoints.push((name.to_owned(), mark));
+		Ok(())
+	}
+
+	/// Undoes every transaction processed since the savepoint, returning how many were undone.\
+	/// The savepoint is kept while the savepoints set after it are forgotten
+	pub fn undo_to_savepoint(&mut self, name: &str) -> Result<usize, ErrorKind> {
+		let mark = self
+			.journal
+			.savepoints
+			.iter()
+			.find(|(x, _)| x == name)
+			.map(|(_, mark)| *mark)
+			.ok_or(ErrorKind::UnknownSavepoint)?;
+		if self.journal.batch.is_some_and(|start| start.seq > mark.seq) {
+			return Err(ErrorKind::UndoBeyondBatch);
+		}
+		self.undo_to(mark)
+	}
+
+	/// Forgets the savepoint along with the savepoints set after it
+	pub fn release_savepoint(&mut self, name: &str) -> Result<(), ErrorKind> {
+		let index = self
+			.journal
+			.savepoints
+			.iter()
+			.position(|(x, _)| x == name)
+			.ok_or(ErrorKind::UnknownSavepoint)?;
+		self.journal.savepoints.truncate(index);
+		self.journal.trim();
+		Ok(())
+	}
+
+	/// Forgets the oldest savepoint alone, returning its name, so that the transactions
+	/// only it kept undoable can be dropped
+	pub fn expire_oldest_savepoint(&mut self) -> Option<String> {
+		if self.journal.savepoints.is_empty() {
+			return None;
+		}
+		let (name, _) = self.journal.savepoints.remove(0);
+		self.journal.trim();
+		Some(name)
+	}
+
+	/// Returns the number of savepoints set
+	pub fn savepoints(&self) -> usize {
+		self.journal.savepoints.len()
+	}
+
+	/// Returns the number of applied transactions kept to be undone, as needed by
+	/// the open batch, the savepoints and the undo depth
+	pub fn undoable(&self) -> usize {
+		self.journal.entries.len()
+	}
+
+	/// Undoes the last **n** applied transactions, either all of them or none.\
+	/// Transactions can be undone while they are within a batch, after a savepoint
+	/// or among the most recent ones kept by [PaymentsProcessor::with_undo_depth]
+	pub fn undo_last(&mut self, n: usize) -> Result<(), ErrorKind> {
+		if n > self.journal.entries.len() {
+			return Err(ErrorKind::NotEnoughHistory);
+		}
+		let seq = self.journal.len() - n;
+		if self.journal.batch.is_some_and(|start| start.seq > seq) {
+			return Err(ErrorKind::UndoBeyondBatch);
+		}
+		let keys = match n {
+			0 => self.idempotency.registered(),
+			_ => {
+				self.journal.entries[self.journal.entries.len() - n]
+					.snapshot
+					.keys
+			}
+		};
+		self.undo_to(Mark { seq, keys }).map(|_| ())
+	}
+
+	/// Keeps the last **depth** applied transactions undoable at any time
+	pub fn with_undo_depth(mut self, depth: usize) -> Self {
+		self.journal.set_depth(depth);
+		self
+	}
+}
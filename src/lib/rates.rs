@@ -0,0 +1,103 @@
+use crate::currency::Currency;
+use crate::fixed_decimal::{FixedDecimal, Rounding};
+use std::collections::HashMap;
+use std::io::BufRead;
+
+/// A table of conversion rates between currency pairs along with the [Rounding] policy
+/// applied to converted amounts.\
+/// Rates are directional, converting from EUR to USD does not imply a rate from USD to EUR
+#[derive(Debug, Default)]
+pub struct RateTable {
+	#[doc(hidden)]
+	rates: HashMap<(Currency, Currency), FixedDecimal>,
+	rounding: Rounding,
+}
+
+/// Type of error that happens while building a [RateTable], line numbers start from 1
+#[non_exhaustive]
+#[derive(Debug, PartialEq)]
+pub enum ErrorKind {
+	IOError(std::io::ErrorKind),
+	MalformedLine(usize),
+	InvalidCurrency(usize),
+	InvalidRate(usize),
+	SameCurrencyPair(usize),
+	DuplicatePair(usize),
+}
+
+impl RateTable {
+	pub fn with_rounding(mut self, rounding: Rounding) -> Self {
+		self.rounding = rounding;
+		self
+	}
+
+	pub fn rounding(&self) -> Rounding {
+		self.rounding
+	}
+
+	/// Adds (or replaces) the rate converting **from** into **to**, returning the previous rate if any
+	pub fn insert(
+		&mut self,
+		from: Currency,
+		to: Currency,
+		rate: FixedDecimal,
+	) -> Option<FixedDecimal> {
+		self.rates.insert((from, to), rate)
+	}
+
+	pub fn get(&self, from: Currency, to: Currency) -> Option<FixedDecimal> {
+		self.rates.get(&(from, to)).copied()
+	}
+
+	/// Reads a rate table out of lines of the form
+	/// {ws}**{ISO 4217 code}**{ws},{ws}**{ISO 4217 code}**{ws},{ws}**{number}.{(1..=4)digits}**{ws}\
+	/// Empty lines and lines starting with **#** are skipped
+	/// ```
+	/// use lib::{Currency, RateTable};
+	/// use std::str::FromStr;
+	///
+	/// let table = RateTable::from_reader("# from,to,rate\nEUR, USD, 1.0843\n".as_bytes());
+	/// assert!(table.is_ok());
+	/// let eur = Currency::from_str("EUR").unwrap();
+	/// let usd = Currency::from_str("USD").unwrap();
+	/// assert_eq!(table.unwrap().get(eur, usd).map(|x| x.to_string()), Some("1.0843".to_owned()));
+	/// ```
+	pub fn from_reader(reader: impl BufRead) -> Result<Self, ErrorKind> {
+		let mut table = RateTable::default();
+		for (i, line) in reader.lines().enumerate() {
+			let line = line.map_err(|x| ErrorKind::IOError(x.kind()))?;
+			let line_no = i + 1;
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+			let mut it = line.split(',').map(|x| x.trim());
+			let sections = [it.next(), it.next(), it.next()];
+			if it.next().is_some() {
+				return Err(ErrorKind::MalformedLine(line_no));
+			}
+			let [Some(from), Some(to), Some(rate)] = sections else {
+				return Err(ErrorKind::MalformedLine(line_no));
+			};
+			let from = from
+				.parse::<Currency>()
+				.map_err(|_| ErrorKind::InvalidCurrency(line_no))?;
+			let to = to
+				.parse::<Currency>()
+				.map_err(|_| ErrorKind::InvalidCurrency(line_no))?;
+			let rate = rate
+				.parse::<FixedDecimal>()
+				.map_err(|_| ErrorKind::InvalidRate(line_no))?;
+			if rate == FixedDecimal::default() {
+				return Err(ErrorKind::InvalidRate(line_no));
+			}
+			if from == to {
+				return Err(ErrorKind::SameCurrencyPair(line_no));
+			}
+			if table.insert(from, to, rate).is_some() {
+				return Err(ErrorKind::DuplicatePair(line_no));
+			}
+		}
+		Ok(table)
+	}
+}
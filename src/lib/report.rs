@@ -0,0 +1,240 @@
+use crate::currency::Currency;
+use crate::fixed_decimal::{FixedDecimal, WideSum};
+use std::fmt::Write;
+
+/// The state of a client's balance in one currency, as reported by a [ReportWriter]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AccountStatement {
+	pub client: u16,
+	/// None for the default (unnamed) currency
+	pub currency: Option<Currency>,
+	pub available: FixedDecimal,
+	pub held: FixedDecimal,
+	pub locked: bool,
+}
+
+impl AccountStatement {
+	/// The exact total (available + held), which may exceed the range of a [FixedDecimal] and can therefore only be displayed
+	pub fn total(&self) -> impl std::fmt::Display {
+		WideSum(self.available, self.held)
+	}
+}
+
+/// Renders the [AccountStatement]s of a [crate::PaymentsProcessor], sorted by client and currency.\
+/// Amounts are always rendered exactly as a [FixedDecimal] displays them
+pub trait ReportWriter {
+	/// Renders the statements as they are yielded, without collecting them\
+	/// **multi_currency** tells whether any of them is held in a named currency, since some formats
+	/// have to know it before the first statement
+	fn write_statements(
+		&self,
+		out: &mut dyn Write,
+		statements: &mut dyn Iterator<Item = AccountStatement>,
+		multi_currency: bool,
+	) -> std::fmt::Result;
+
+	/// Renders a report out of statements already sorted by client and currency
+	fn write_report(&self, out: &mut dyn Write, statements: &[AccountStatement]) -> std::fmt::Result {
+		let multi_currency = statements.iter().any(|x| x.currency.is_some());
+		self.write_statements(out, &mut statements.iter().copied(), multi_currency)
+	}
+}
+
+/// The CSV report of the specification\
+/// **client,available,held,total,locked**\
+/// **{u16},{FixedDecimal},{FixedDecimal},{FixedDecimal},{true/false}**
+///
+/// Once any balance is held in a named currency, one row per client and currency is output instead\
+/// (the default currency being left empty)\
+/// **client,currency,available,held,total,locked**\
+/// **{u16},{ISO 4217 code},{FixedDecimal},{FixedDecimal},{FixedDecimal},{true/false}**
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CsvReport;
+
+impl ReportWriter for CsvReport {
+	fn write_statements(
+		&self,
+		out: &mut dyn Write,
+		statements: &mut dyn Iterator<Item = AccountStatement>,
+		multi_currency: bool,
+	) -> std::fmt::Result {
+		//spec is unclear if we need to print the header if there are no clients
+		if multi_currency {
+			writeln!(out, "client,currency,available,held,total,locked")?;
+		} else {
+			writeln!(out, "client,available,held,total,locked")?;
+		}
+		for statement in statements {
+			write!(out, "{},", statement.client)?;
+			if multi_currency {
+				write!(
+					out,
+					"{},",
+					statement.currency.as_ref().map_or("", Currency::as_str)
+				)?;
+			}
+			writeln!(
+				out,
+				"{},{},{},{}",
+				statement.available,
+				statement.held,
+				statement.total(),
+				statement.locked,
+			)?;
+		}
+		Ok(())
+	}
+}
+
+/// A JSON array with one object per client and currency, amounts being strings so they are never read as floats\
+/// **[{"client":{u16},"currency":{"ISO 4217 code"/null},"available":"{FixedDecimal}","held":"{FixedDecimal}","total":"{FixedDecimal}","locked":{true/false}}, ...]**
+#[derive(Debug, Default, Clone, Copy)]
+pub struct JsonReport;
+
+impl ReportWriter for JsonReport {
+	fn write_statements(
+		&self,
+		out: &mut dyn Write,
+		statements: &mut dyn Iterator<Item = AccountStatement>,
+		_multi_currency: bool,
+	) -> std::fmt::Result {
+		write!(out, "[")?;
+		let mut empty = true;
+		for statement in statements {
+			if !empty {
+				write!(out, ",")?;
+			}
+			writeln!(out)?;
+			write_json_object(out, &statement)?;
+			empty = false;
+		}
+		if !empty {
+			writeln!(out)?;
+		}
+		writeln!(out, "]")
+	}
+}
+
+/// One JSON object per line, per client and currency, in the same shape as the objects of [JsonReport]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct JsonLinesReport;
+
+impl ReportWriter for JsonLinesReport {
+	fn write_statements(
+		&self,
+		out: &mut dyn Write,
+		statements: &mut dyn Iterator<Item = AccountStatement>,
+		_multi_currency: bool,
+	) -> std::fmt::Result {
+		for statement in statements {
+			write_json_object(out, &statement)?;
+			writeln!(out)?;
+		}
+		Ok(())
+	}
+}
+
+/// A client whose balances or lock state changed between two reports, with its statements
+/// as of both of them (sorted by currency, empty when the client did not exist)
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountChange {
+	pub client: u16,
+	pub before: Vec<AccountStatement>,
+	pub after: Vec<AccountStatement>,
+}
+
+/// The clients that changed since report **since**, as of report **report**, sorted by client.\
+/// **report** is the cursor to ask for the next changes with, see [crate::PaymentsProcessor::delta_report]
+///
+/// Displays as CSV, one row per client and currency, the cells of a side being left empty
+/// when the balance did not exist on it\
+/// **client,currency,before_available,before_held,before_total,before_locked,available,held,total,locked**
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeltaReport {
+	pub since: u64,
+	pub report: u64,
+	pub changes: Vec<AccountChange>,
+}
+
+impl DeltaReport {
+	/// Renders the report as JSON, the statements being objects of the same shape as those of [JsonReport]\
+	/// **{"report":{u64},"since":{u64},"changes":[{"client":{u16},"before":[...],"after":[...]}, ...]}**
+	pub fn write_json(&self, out: &mut dyn Write) -> std::fmt::Result {
+		write!(
+			out,
+			"{{\"report\":{},\"since\":{},\"changes\":[",
+			self.report, self.since
+		)?;
+		for (i, change) in self.changes.iter().enumerate() {
+			if i > 0 {
+				write!(out, ",")?;
+			}
+			write!(out, "{{\"client\":{}", change.client)?;
+			for (name, statements) in [("before", &change.before), ("after", &change.after)] {
+				write!(out, ",\"{}\":[", name)?;
+				for (i, statement) in statements.iter().enumerate() {
+					if i > 0 {
+						write!(out, ",")?;
+					}
+					write_json_object(out, statement)?;
+				}
+				write!(out, "]")?;
+			}
+			write!(out, "}}")?;
+		}
+		writeln!(out, "]}}")
+	}
+}
+
+impl std::fmt::Display for DeltaReport {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		writeln!(
+			f,
+			"client,currency,before_available,before_held,before_total,before_locked,available,held,total,locked"
+		)?;
+		for change in &self.changes {
+			let mut currencies: Vec<_> = change
+				.before
+				.iter()
+				.chain(&change.after)
+				.map(|x| x.currency)
+				.collect();
+			currencies.sort_unstable();
+			currencies.dedup();
+			for currency in currencies {
+				write!(
+					f,
+					"{},{}",
+					change.client,
+					currency.as_ref().map_or("", Currency::as_str)
+				)?;
+				for side in [&change.before, &change.after] {
+					match side.iter().find(|x| x.currency == currency) {
+						Some(x) => write!(f, ",{},{},{},{}", x.available, x.held, x.total(), x.locked)?,
+						None => write!(f, ",,,,")?,
+					}
+				}
+				writeln!(f)?;
+			}
+		}
+		Ok(())
+	}
+}
+
+//currency codes and amounts never contain characters that need escaping
+#[doc(hidden)]
+fn write_json_object(out: &mut dyn Write, statement: &AccountStatement) -> std::fmt::Result {
+	write!(out, "{{\"client\":{},\"currency\":", statement.client)?;
+	match statement.currency {
+		Some(currency) => write!(out, "\"{}\"", currency)?,
+		None => write!(out, "null")?,
+	}
+	write!(
+		out,
+		",\"available\":\"{}\",\"held\":\"{}\",\"total\":\"{}\",\"locked\":{}}}",
+		statement.available,
+		statement.held,
+		statement.total(),
+		statement.locked
+	)
+}
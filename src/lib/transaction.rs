@@ -1,25 +1,43 @@
+use crate::currency::Currency;
 use crate::fixed_decimal::FixedDecimal;
+use crate::json::{self, Value};
+use std::borrow::Cow;
 
 //Discussion point, I could make Client and Tx types using:
 //https://doc.rust-lang.org/rust-by-example/generics/new_types.html
 //didn't do it so i can keep the code compact
 
-/// Describes the type of transaction (with associated amounts for deposits and withdrawals)
-#[derive(Debug, PartialEq)]
+/// Describes the type of transaction (with associated amounts for deposits and withdrawals)\
+/// Disputes, resolutions and chargebacks may optionally carry an amount, which makes them partial.
+/// When the amount is omitted they act on the whole (outstanding) amount of the referenced transaction.\
+/// Representments reverse a chargeback and pre-arbitrations contest a representment
+#[derive(Debug, Clone, PartialEq)]
 pub enum TransactionPayload {
 	Deposit(FixedDecimal),
 	Withdrawal(FixedDecimal),
-	Dispute,
-	Resolve,
-	ChargeBack,
+	Dispute(Option<FixedDecimal>),
+	Resolve(Option<FixedDecimal>),
+	ChargeBack(Option<FixedDecimal>),
+	Representment,
+	PreArbitration,
+	/// Converts an amount of the transaction's currency into the target currency
+	Convert(FixedDecimal, Currency),
 }
 
-/// Describes a payment transaction
-#[derive(Debug, PartialEq)]
+///The max number of chars of an idempotency key
+pub const MAX_KEY_LEN: usize = 64;
+
+/// Describes a payment transaction\
+/// Deposits and withdrawals without a currency act on the client's default (unnamed) currency balance,
+/// all the other transaction types act on the currency of the transaction they reference.\
+/// Retries of a transaction carrying an idempotency key are answered with the outcome of the original
+#[derive(Debug, Clone, PartialEq)]
 pub struct PaymentsTransaction {
 	pub client: u16,
 	pub tx: u32,
 	pub payload: TransactionPayload,
+	pub currency: Option<Currency>,
+	pub idempotency_key: Option<String>,
 }
 
 // DISCUSSION POINT, I could extract a trait representing transactions
@@ -36,7 +54,7 @@ pub struct PaymentsTransaction {
 // 	fn get_payload(&self) -> PayloadT;
 // }
 
-/// Type of error that happens during &str to -> PaymentsTransaction conversion
+/// Type of error that happens during &str to -> PaymentsTransaction conversion (either from CSV or JSON)
 #[non_exhaustive]
 #[derive(Debug, PartialEq)]
 pub enum ErrorKind {
@@ -47,16 +65,42 @@ pub enum ErrorKind {
 	UnexpectedErrorType,
 	UnexpectedTrailingSection,
 	MissingTransactionAmount,
+	InvalidCurrency,
+	MissingCurrency,
+	InvalidIdempotencyKey,
+	/// The input is not a single well formed JSON object
+	MalformedJson,
+	/// A JSON field that is not part of a transaction, rejected by [UnknownFields::Reject]
+	UnknownField,
+	DuplicateField,
+}
+
+/// What to do with the fields of a JSON transaction that are not part of a transaction
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum UnknownFields {
+	#[default]
+	Reject,
+	Ignore,
 }
 
 impl std::str::FromStr for PaymentsTransaction {
 	type Err = ErrorKind;
 	/// Valid input for conversion is a comma separated list of fields matching one of the following patterns (ws=whitespace):
-	/// - {ws}**deposit**{ws},{ws}**u16**{ws},{ws}**u32**{ws},{ws}**{number}.{(1..=4)digits}**{ws}
-	/// - {ws}**withdrawal**{ws},{ws}**u16**{ws},{ws}**u32**{ws},{ws}**{number}.{(1..=4)digits}**{ws}
-	/// - {ws}**dispute**{ws},{ws}**u16**{ws},{ws}**u32**{ws}
-	/// - {ws}**resolve**{ws},{ws}**u16**{ws},{ws}**u32**{ws}
-	/// - {ws}**chargeback**{ws},{ws}**u16**{ws},{ws}**u32**{ws}
+	/// - {ws}**deposit**{ws},{ws}**u16**{ws},{ws}**u32**{ws},{ws}**{number}.{(1..=4)digits}**{ws}[,{ws}[**{ISO 4217 code}**]{ws}]
+	/// - {ws}**withdrawal**{ws},{ws}**u16**{ws},{ws}**u32**{ws},{ws}**{number}.{(1..=4)digits}**{ws}[,{ws}[**{ISO 4217 code}**]{ws}]
+	/// - {ws}**dispute**{ws},{ws}**u16**{ws},{ws}**u32**{ws}[,{ws}[**{number}.{(1..=4)digits}**]{ws}]
+	/// - {ws}**resolve**{ws},{ws}**u16**{ws},{ws}**u32**{ws}[,{ws}[**{number}.{(1..=4)digits}**]{ws}]
+	/// - {ws}**chargeback**{ws},{ws}**u16**{ws},{ws}**u32**{ws}[,{ws}[**{number}.{(1..=4)digits}**]{ws}]
+	/// - {ws}**representment**{ws},{ws}**u16**{ws},{ws}**u32**{ws}
+	/// - {ws}**prearbitration**{ws},{ws}**u16**{ws},{ws}**u32**{ws}
+	/// - {ws}**convert**{ws},{ws}**u16**{ws},{ws}**u32**{ws},{ws}**{number}.{(1..=4)digits}**{ws},{ws}**{ISO 4217 code}**{ws},{ws}**{ISO 4217 code}**{ws}
+	///
+	/// The amount of disputes, resolutions and chargebacks is optional and may also be left empty,
+	/// as is the currency of deposits and withdrawals.\
+	/// Conversions debit the amount from the first currency and credit its converted value to the second.\
+	/// Any transaction may carry an idempotency key of up to [MAX_KEY_LEN] printable ASCII chars (commas aside) as a 7th section,
+	/// the sections between the payload and the key being left empty
+	/// eg. **deposit, 321, 1, 100.0, , , retry-key-1**
 	/// ```
 	/// use lib::PaymentsTransaction;
 	/// use std::str::FromStr;
@@ -73,20 +117,210 @@ impl std::str::FromStr for PaymentsTransaction {
 	/// assert_eq!(tr.payload, TransactionPayload::Deposit(amount.unwrap()));
 	/// ```
 	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		let mut it = s.split(',').map(|x| x.trim());
-		let transaction_sections = [it.next(), it.next(), it.next()];
+		let sections: Vec<_> = s.split(',').map(|x| x.trim()).collect();
+		if sections.len() > KEY_SECTION + 1 {
+			return Err(ErrorKind::UnexpectedTrailingSection);
+		}
+		PaymentsTransaction::from_sections(&sections)
+	}
+}
+
+#[doc(hidden)]
+const KEY_SECTION: usize = 6;
+
+impl std::fmt::Display for PaymentsTransaction {
+	/// Outputs the canonical comma separated form of the transaction, which [std::str::FromStr] parses back
+	/// ```
+	/// use lib::PaymentsTransaction;
+	/// use std::str::FromStr;
+	///
+	/// let tr = PaymentsTransaction::from_str(" dispute , 1, 5 , ,, ").unwrap();
+	/// assert_eq!(tr.to_string(), "dispute,1,5");
+	/// let tr = PaymentsTransaction::from_str("deposit, 1, 5, 1.50, EUR, , key").unwrap();
+	/// assert_eq!(tr.to_string(), "deposit,1,5,1.5,EUR,,key");
+	/// ```
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		let sections = self.sections();
+		//trailing empty sections are left out
+		let last = sections.iter().rposition(|x| !x.is_empty()).unwrap_or(0);
+		write!(f, "{}", sections[0])?;
+		for section in sections.iter().take(last + 1).skip(1) {
+			write!(f, ",{}", section)?;
+		}
+		Ok(())
+	}
+}
+
+//the names of the JSON fields, in the order of the CSV sections
+#[doc(hidden)]
+const JSON_FIELDS: [&str; KEY_SECTION + 1] = [
+	"type",
+	"client",
+	"tx",
+	"amount",
+	"currency",
+	"target_currency",
+	"idempotency_key",
+];
+
+impl PaymentsTransaction {
+	/// Valid input for conversion is a single JSON object with the fields of the equivalent CSV sections
+	/// (see the [std::str::FromStr] implementation of [PaymentsTransaction]), held to the same rules:
+	/// - **"type"**: string
+	/// - **"client"**: u16 number
+	/// - **"tx"**: u32 number
+	/// - **"amount"**: optional string of the form **{number}.{(1..=4)digits}**, never a JSON number
+	///   so that amounts are always exact
+	/// - **"currency"**, **"target_currency"**: optional string with an ISO 4217 code
+	/// - **"idempotency_key"**: optional string
+	///
+	/// Optional fields may be omitted or null, fields may come in any order but only once.
+	/// Any other field is either rejected or ignored according to the provided [UnknownFields] policy
+	/// ```
+	/// use lib::{FixedDecimal, PaymentsTransaction, TransactionPayload, UnknownFields};
+	/// use std::str::FromStr;
+	///
+	/// let json = r#"{"type":"deposit","client":1,"tx":5,"amount":"1.5"}"#;
+	/// let tr = PaymentsTransaction::from_json(json, UnknownFields::Reject).unwrap();
+	/// assert_eq!(tr, PaymentsTransaction::from_str("deposit, 1, 5, 1.5").unwrap());
+	/// let json = r#"{"type":"deposit","client":1,"tx":5,"amount":1.5}"#;
+	/// assert!(PaymentsTransaction::from_json(json, UnknownFields::Reject).is_err());
+	/// ```
+	pub fn from_json(s: &str, unknown_fields: UnknownFields) -> Result<Self, ErrorKind> {
+		let mut fields: [Option<Cow<str>>; KEY_SECTION + 1] = Default::default();
+		json::parse_object(s, |name, value| {
+			let index = match JSON_FIELDS.iter().position(|x| *x == name) {
+				Some(index) => index,
+				None if unknown_fields == UnknownFields::Ignore => return Ok(()),
+				None => return Err(ErrorKind::UnknownField),
+			};
+			if fields[index].is_some() {
+				return Err(ErrorKind::DuplicateField);
+			}
+			fields[index] = Some(match (index, value) {
+				(1 | 2, Value::Number(number)) => Cow::Borrowed(number),
+				(0 | 3.., Value::String(txt)) => txt,
+				(3.., Value::Null) => Cow::Borrowed(""),
+				_ => return Err(ErrorKind::CouldNotParseSection),
+			});
+			Ok(())
+		})?;
+		let sections: Vec<_> = fields.iter().map(|x| x.as_deref().unwrap_or("")).collect();
+		//unlike an empty CSV section, an amount omitted (or null) is missing rather than malformed
+		PaymentsTransaction::from_sections(&sections).map_err(|e| match e {
+			ErrorKind::CouldNotParseSection if sections[3].is_empty() => {
+				ErrorKind::MissingTransactionAmount
+			}
+			e => e,
+		})
+	}
+
+	/// Parses a JSON array of transactions (see [PaymentsTransaction::from_json]), every element on its own
+	/// so that a single invalid transaction does not spoil the others.\
+	/// Fails only when the input is not a well formed array
+	/// ```
+	/// use lib::{PaymentsTransaction, TransactionError, UnknownFields};
+	/// use std::str::FromStr;
+	///
+	/// let json = r#"[{"type":"deposit","client":1,"tx":5,"amount":"1.5"}, {"type":"deposit"}]"#;
+	/// let trs = PaymentsTransaction::from_json_array(json, UnknownFields::Reject).unwrap();
+	/// assert_eq!(trs[0], PaymentsTransaction::from_str("deposit, 1, 5, 1.5"));
+	/// assert!(trs[1].is_err());
+	/// let json = r#"[{"type":"deposit","client":1,"tx":5,"amount":"1.5"}"#;
+	/// let trs = PaymentsTransaction::from_json_array(json, UnknownFields::Reject);
+	/// assert_eq!(trs, Err(TransactionError::MalformedJson));
+	/// ```
+	pub fn from_json_array(
+		s: &str,
+		unknown_fields: UnknownFields,
+	) -> Result<Vec<Result<Self, ErrorKind>>, ErrorKind> {
+		let mut transactions = Vec::new();
+		json::parse_array(s, |element| {
+			transactions.push(PaymentsTransaction::from_json(element, unknown_fields));
+			Ok(())
+		})?;
+		Ok(transactions)
+	}
+
+	/// Outputs the transaction as a JSON object, leaving out the fields it does not carry
+	/// ```
+	/// use lib::{PaymentsTransaction, UnknownFields};
+	/// use std::str::FromStr;
+	///
+	/// let tr = PaymentsTransaction::from_str("deposit, 1, 5, 1.5").unwrap();
+	/// assert_eq!(tr.to_json(), r#"{"type":"deposit","client":1,"tx":5,"amount":"1.5"}"#);
+	/// assert_eq!(PaymentsTransaction::from_json(&tr.to_json(), UnknownFields::Reject), Ok(tr));
+	/// ```
+	pub fn to_json(&self) -> String {
+		let sections = self.sections();
+		let mut json = format!(
+			r#"{{"type":"{}","client":{},"tx":{}"#,
+			sections[0], self.client, self.tx
+		);
+		for (name, section) in JSON_FIELDS.iter().zip(&sections).skip(3) {
+			if !section.is_empty() {
+				//only idempotency keys may contain chars that need escaping
+				let escaped = section.replace('\\', "\\\\").replace('"', "\\\"");
+				json.push_str(&format!(r#","{}":"{}""#, name, escaped));
+			}
+		}
+		json.push('}');
+		json
+	}
+
+	//the text of the CSV sections, empty for the ones the transaction does not carry
+	fn sections(&self) -> [Cow<'_, str>; KEY_SECTION + 1] {
+		let text = |x: Option<String>| x.map_or(Cow::Borrowed(""), Cow::Owned);
+		let (kind, amount, target) = match &self.payload {
+			TransactionPayload::Deposit(amount) => ("deposit", Some(*amount), None),
+			TransactionPayload::Withdrawal(amount) => ("withdrawal", Some(*amount), None),
+			TransactionPayload::Dispute(amount) => ("dispute", *amount, None),
+			TransactionPayload::Resolve(amount) => ("resolve", *amount, None),
+			TransactionPayload::ChargeBack(amount) => ("chargeback", *amount, None),
+			TransactionPayload::Representment => ("representment", None, None),
+			TransactionPayload::PreArbitration => ("prearbitration", None, None),
+			TransactionPayload::Convert(amount, to) => ("convert", Some(*amount), Some(*to)),
+		};
+		[
+			Cow::Borrowed(kind),
+			Cow::Owned(self.client.to_string()),
+			Cow::Owned(self.tx.to_string()),
+			text(amount.map(|x| x.to_string())),
+			text(self.currency.map(|x| x.to_string())),
+			text(target.map(|x| x.to_string())),
+			text(self.idempotency_key.clone()),
+		]
+	}
+
+	fn from_sections(sections: &[&str]) -> Result<Self, ErrorKind> {
 		// Deliberately not iterating over the amount here
 		// since extract_payload() will conditionally next() the iterator on the correct conditions
-		let payload = extract_payload(transaction_sections[0], &mut it)?;
-		if it.next().is_some() {
-			Err(ErrorKind::UnexpectedTrailingSection)
-		} else {
-			Ok(PaymentsTransaction {
-				client: extract_number(transaction_sections[1])?,
-				tx: extract_number(transaction_sections[2])?,
-				payload,
-			})
+		let mut it = sections.iter().copied().take(KEY_SECTION).skip(3);
+		let (payload, currency) = extract_payload(sections.first().copied(), &mut it)?;
+		if it.any(|x| !x.is_empty()) {
+			return Err(ErrorKind::UnexpectedTrailingSection);
 		}
+		Ok(PaymentsTransaction {
+			client: extract_number(sections.get(1).copied())?,
+			tx: extract_number(sections.get(2).copied())?,
+			payload,
+			currency,
+			idempotency_key: extract_key(sections.get(KEY_SECTION).copied())?,
+		})
+	}
+}
+
+#[doc(hidden)]
+fn extract_key(txt: Option<&str>) -> Result<Option<String>, ErrorKind> {
+	match txt {
+		None | Some("") => Ok(None),
+		//a comma would split the key in the comma separated form
+		Some(key)
+			if key.len() <= MAX_KEY_LEN && key.bytes().all(|x| x.is_ascii_graphic() && x != b',') =>
+		{
+			Ok(Some(key.to_owned()))
+		}
+		Some(_) => Err(ErrorKind::InvalidIdempotencyKey),
 	}
 }
 
@@ -114,7 +348,7 @@ where
 fn extract_payload<'a>(
 	txt_type: Option<&str>,
 	it: &mut impl Iterator<Item = &'a str>,
-) -> Result<TransactionPayload, ErrorKind> {
+) -> Result<(TransactionPayload, Option<Currency>), ErrorKind> {
 	fn get_amount<'a>(it: &mut impl Iterator<Item = &'a str>) -> Result<FixedDecimal, ErrorKind> {
 		it.next()
 			.ok_or(ErrorKind::MissingTransactionAmount)?
@@ -122,12 +356,76 @@ fn extract_payload<'a>(
 			//TODO, maybe make this error more fine grained
 			.map_err(|_| ErrorKind::CouldNotParseSection)
 	}
+	//a missing or empty section means that no amount was provided
+	fn get_optional_amount<'a>(
+		it: &mut impl Iterator<Item = &'a str>,
+	) -> Result<Option<FixedDecimal>, ErrorKind> {
+		match it.next() {
+			None | Some("") => Ok(None),
+			Some(txt) => txt
+				.parse::<FixedDecimal>()
+				.map(Some)
+				//TODO, maybe make this error more fine grained
+				.map_err(|_| ErrorKind::CouldNotParseSection),
+		}
+	}
+	//an empty amount section is tolerated for transactions that never carry an amount
+	fn get_no_amount<'a>(
+		it: &mut impl Iterator<Item = &'a str>,
+		payload: TransactionPayload,
+	) -> Result<TransactionPayload, ErrorKind> {
+		match it.next() {
+			None | Some("") => Ok(payload),
+			Some(_) => Err(ErrorKind::UnexpectedTrailingSection),
+		}
+	}
+	//a missing or empty section means that the default currency is used
+	fn get_optional_currency<'a>(
+		it: &mut impl Iterator<Item = &'a str>,
+	) -> Result<Option<Currency>, ErrorKind> {
+		match it.next() {
+			None | Some("") => Ok(None),
+			Some(code) => code
+				.parse::<Currency>()
+				.map(Some)
+				.map_err(|_| ErrorKind::InvalidCurrency),
+		}
+	}
+	fn get_currency<'a>(it: &mut impl Iterator<Item = &'a str>) -> Result<Currency, ErrorKind> {
+		get_optional_currency(it)?.ok_or(ErrorKind::MissingCurrency)
+	}
+	//the currency of disputes and the like is dictated by the transaction they reference
+	fn get_no_currency<'a>(
+		payload: TransactionPayload,
+		it: &mut impl Iterator<Item = &'a str>,
+	) -> Result<(TransactionPayload, Option<Currency>), ErrorKind> {
+		match it.next() {
+			None | Some("") => Ok((payload, None)),
+			Some(_) => Err(ErrorKind::UnexpectedTrailingSection),
+		}
+	}
 	match txt_type.ok_or(ErrorKind::EmptySection)? {
-		"deposit" => Ok(TransactionPayload::Deposit(get_amount(it)?)),
-		"withdrawal" => Ok(TransactionPayload::Withdrawal(get_amount(it)?)),
-		"dispute" => Ok(TransactionPayload::Dispute),
-		"resolve" => Ok(TransactionPayload::Resolve),
-		"chargeback" => Ok(TransactionPayload::ChargeBack),
+		"deposit" => Ok((
+			TransactionPayload::Deposit(get_amount(it)?),
+			get_optional_currency(it)?,
+		)),
+		"withdrawal" => Ok((
+			TransactionPayload::Withdrawal(get_amount(it)?),
+			get_optional_currency(it)?,
+		)),
+		"convert" => {
+			let amount = get_amount(it)?;
+			let from = get_currency(it)?;
+			Ok((
+				TransactionPayload::Convert(amount, get_currency(it)?),
+				Some(from),
+			))
+		}
+		"dispute" => get_no_currency(TransactionPayload::Dispute(get_optional_amount(it)?), it),
+		"resolve" => get_no_currency(TransactionPayload::Resolve(get_optional_amount(it)?), it),
+		"chargeback" => get_no_currency(TransactionPayload::ChargeBack(get_optional_amount(it)?), it),
+		"representment" => get_no_currency(get_no_amount(it, TransactionPayload::Representment)?, it),
+		"prearbitration" => get_no_currency(get_no_amount(it, TransactionPayload::PreArbitration)?, it),
 		_ => Err(ErrorKind::UnknownTransactionType),
 	}
 }
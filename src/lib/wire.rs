@@ -0,0 +1,288 @@
+use crate::currency::Currency;
+use crate::fixed_decimal::FixedDecimal;
+use crate::transaction::{PaymentsTransaction, TransactionPayload};
+use std::io::{Read, Write};
+
+// Stream layout (all integers little endian):
+//   header: MAGIC, VERSION
+//   records of RECORD_LEN bytes each:
+//     kind      u8       transaction type code, AMOUNT_FLAG set when an amount is carried
+//     client    u16
+//     tx        u32
+//     amount    u64      raw FixedDecimal units, 0 when no amount is carried
+//     currency  [u8; 3]  ISO 4217 code, zeroes for none
+//     target    [u8; 3]  ISO 4217 code of conversions, zeroes for none
+//     checksum  u32      CRC-32 of the previous bytes of the record
+// Records have a fixed size, so a corrupt record is skipped without losing track of the next one
+
+/// The first bytes of a binary transaction stream
+pub const MAGIC: [u8; 4] = *b"MPTX";
+
+/// The version of the binary record layout written by [WireWriter]
+pub const VERSION: u8 = 1;
+
+/// The size in bytes of a binary transaction record, checksum included
+pub const RECORD_LEN: usize = 25;
+
+#[doc(hidden)]
+const AMOUNT_FLAG: u8 = 0x80;
+
+#[doc(hidden)]
+const CHECKSUMMED_LEN: usize = RECORD_LEN - 4;
+
+/// Type of error that happens while encoding or decoding binary transaction records
+#[non_exhaustive]
+#[derive(Debug, PartialEq)]
+pub enum ErrorKind {
+	IOError(std::io::ErrorKind),
+	EOFReached,
+	/// The stream does not start with [MAGIC]
+	BadMagic,
+	UnsupportedVersion(u8),
+	/// The stream ends in the middle of a record
+	TruncatedRecord,
+	ChecksumMismatch,
+	UnknownTransactionType,
+	InvalidCurrency,
+	/// The amount or currencies carried do not match the transaction type
+	InvalidRecord,
+	/// Idempotency keys have no place in the fixed size record
+	KeyNotEncodable,
+}
+
+//CRC-32 (IEEE 802.3), the table is computed at compile time
+#[doc(hidden)]
+const CRC_TABLE: [u32; 256] = {
+	let mut table = [0u32; 256];
+	let mut i = 0;
+	while i < 256 {
+		let mut crc = i as u32;
+		let mut bit = 0;
+		while bit < 8 {
+			crc = if crc & 1 == 1 {
+				(crc >> 1) ^ 0xEDB8_8320
+			} else {
+				crc >> 1
+			};
+			bit += 1;
+		}
+		table[i] = crc;
+		i += 1;
+	}
+	table
+};
+
+#[doc(hidden)]
+fn crc32(bytes: &[u8]) -> u32 {
+	!bytes.iter().fold(!0u32, |crc, byte| {
+		CRC_TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+	})
+}
+
+#[doc(hidden)]
+fn currency_bytes(currency: Option<Currency>) -> [u8; 3] {
+	let mut bytes = [0; 3];
+	if let Some(currency) = currency {
+		bytes.copy_from_slice(currency.as_str().as_bytes());
+	}
+	bytes
+}
+
+#[doc(hidden)]
+fn currency_from_bytes(bytes: &[u8]) -> Result<Option<Currency>, ErrorKind> {
+	if bytes == [0; 3] {
+		return Ok(None);
+	}
+	let code = std::str::from_utf8(bytes).map_err(|_| ErrorKind::InvalidCurrency)?;
+	code
+		.parse::<Currency>()
+		.map(Some)
+		.map_err(|_| ErrorKind::InvalidCurrency)
+}
+
+impl PaymentsTransaction {
+	/// Encodes the transaction into a fixed size binary record (see [WireWriter] for the stream format).\
+	/// Transactions carrying an idempotency key cannot be encoded
+	/// ```
+	/// use lib::PaymentsTransaction;
+	/// use std::str::FromStr;
+	///
+	/// let tr = PaymentsTransaction::from_str("convert, 1, 5, 1.5, EUR, USD").unwrap();
+	/// let record = tr.to_wire().unwrap();
+	/// assert_eq!(PaymentsTransaction::from_wire(&record), Ok(tr));
+	/// ```
+	pub fn to_wire(&self) -> Result<[u8; RECORD_LEN], ErrorKind> {
+		if self.idempotency_key.is_some() {
+			return Err(ErrorKind::KeyNotEncodable);
+		}
+		let (kind, amount, target) = match &self.payload {
+			TransactionPayload::Deposit(amount) => (1, Some(*amount), None),
+			TransactionPayload::Withdrawal(amount) => (2, Some(*amount), None),
+			TransactionPayload::Dispute(amount) => (3, *amount, None),
+			TransactionPayload::Resolve(amount) => (4, *amount, None),
+			TransactionPayload::ChargeBack(amount) => (5, *amount, None),
+			TransactionPayload::Representment => (6, None, None),
+			TransactionPayload::PreArbitration => (7, None, None),
+			TransactionPayload::Convert(amount, to) => (8, Some(*amount), Some(*to)),
+		};
+		let mut record = [0; RECORD_LEN];
+		record[0] = kind | if amount.is_some() { AMOUNT_FLAG } else { 0 };
+		record[1..3].copy_from_slice(&self.client.to_le_bytes());
+		record[3..7].copy_from_slice(&self.tx.to_le_bytes());
+		let units = amount.map_or(0, |x| x.to_units() as u64);
+		record[7..15].copy_from_slice(&units.to_le_bytes());
+		record[15..18].copy_from_slice(&currency_bytes(self.currency));
+		record[18..21].copy_from_slice(&currency_bytes(target));
+		let checksum = crc32(&record[..CHECKSUMMED_LEN]);
+		record[CHECKSUMMED_LEN..].copy_from_slice(&checksum.to_le_bytes());
+		Ok(record)
+	}
+
+	/// Decodes a binary record, holding it to the same rules as the text formats
+	pub fn from_wire(record: &[u8; RECORD_LEN]) -> Result<Self, ErrorKind> {
+		let (checksummed, checksum) = record.split_at(CHECKSUMMED_LEN);
+		if crc32(checksummed)
+			!= u32::from_le_bytes([checksum[0], checksum[1], checksum[2], checksum[3]])
+		{
+			return Err(ErrorKind::ChecksumMismatch);
+		}
+		let kind = record[0] & !AMOUNT_FLAG;
+		if !(1..=8).contains(&kind) {
+			return Err(ErrorKind::UnknownTransactionType);
+		}
+		let mut units = [0; 8];
+		units.copy_from_slice(&record[7..15]);
+		let units = u64::from_le_bytes(units);
+		let amount = match record[0] & AMOUNT_FLAG {
+			0 if units == 0 => None,
+			0 => return Err(ErrorKind::InvalidRecord),
+			_ => Some(FixedDecimal::from_units(units as i128).ok_or(ErrorKind::InvalidRecord)?),
+		};
+		let currency = currency_from_bytes(&record[15..18])?;
+		let target = currency_from_bytes(&record[18..21])?;
+		let payload = match (kind, amount, currency, target) {
+			(1, Some(amount), _, None) => TransactionPayload::Deposit(amount),
+			(2, Some(amount), _, None) => TransactionPayload::Withdrawal(amount),
+			(3, amount, None, None) => TransactionPayload::Dispute(amount),
+			(4, amount, None, None) => TransactionPayload::Resolve(amount),
+			(5, amount, None, None) => TransactionPayload::ChargeBack(amount),
+			(6, None, None, None) => TransactionPayload::Representment,
+			(7, None, None, None) => TransactionPayload::PreArbitration,
+			(8, Some(amount), Some(_), Some(to)) => TransactionPayload::Convert(amount, to),
+			_ => return Err(ErrorKind::InvalidRecord),
+		};
+		Ok(PaymentsTransaction {
+			client: u16::from_le_bytes([record[1], record[2]]),
+			tx: u32::from_le_bytes([record[3], record[4], record[5], record[6]]),
+			payload,
+			currency,
+			idempotency_key: None,
+		})
+	}
+}
+
+/// A [Write] adapter that frames [PaymentsTransaction]s into a binary stream:
+/// a header of [MAGIC] and [VERSION] followed by [RECORD_LEN] bytes records,
+/// each one carrying its own CRC-32 checksum
+#[derive(Debug)]
+pub struct WireWriter<T: Write> {
+	#[doc(hidden)]
+	writer: T,
+}
+
+impl<T: Write> WireWriter<T> {
+	/// Writes the stream header
+	pub fn new(mut writer: T) -> Result<Self, ErrorKind> {
+		writer
+			.write_all(&MAGIC)
+			.and_then(|_| writer.write_all(&[VERSION]))
+			.map_err(|x| ErrorKind::IOError(x.kind()))?;
+		Ok(WireWriter { writer })
+	}
+
+	pub fn write(&mut self, tx: &PaymentsTransaction) -> Result<(), ErrorKind> {
+		self
+			.writer
+			.write_all(&tx.to_wire()?)
+			.map_err(|x| ErrorKind::IOError(x.kind()))
+	}
+
+	/// Flushes and returns the underlying writer
+	pub fn into_inner(mut self) -> Result<T, ErrorKind> {
+		self
+			.writer
+			.flush()
+			.map_err(|x| ErrorKind::IOError(x.kind()))?;
+		Ok(self.writer)
+	}
+}
+
+/// A [Read] adapter that decodes the binary stream written by [WireWriter].\
+/// A corrupt record is reported and skipped, while a stream ending in the middle of a record
+/// reports [ErrorKind::TruncatedRecord] once and then [ErrorKind::EOFReached] every time it is queried
+#[derive(Debug)]
+pub struct WireReader<T: Read> {
+	#[doc(hidden)]
+	reader: T,
+	record: [u8; RECORD_LEN],
+	//helps achieve fused iterator semantics
+	ended: bool,
+}
+
+impl<T: Read> WireReader<T> {
+	/// Reads and checks the stream header
+	pub fn new(mut reader: T) -> Result<Self, ErrorKind> {
+		let mut header = [0; 5];
+		match read_full(&mut reader, &mut header)? {
+			n if n < header.len() || header[..4] != MAGIC => Err(ErrorKind::BadMagic),
+			_ if header[4] != VERSION => Err(ErrorKind::UnsupportedVersion(header[4])),
+			_ => Ok(WireReader {
+				reader,
+				record: [0; RECORD_LEN],
+				ended: false,
+			}),
+		}
+	}
+
+	/// This function will return the next decoded transaction,\
+	/// an [ErrorKind::EOFReached] once the stream ends,\
+	/// or the error that made the record unreadable
+	pub fn get_next(&mut self) -> Result<PaymentsTransaction, ErrorKind> {
+		PaymentsTransaction::from_wire(self.get_next_raw()?)
+	}
+
+	/// Same as [WireReader::get_next] but returns the record as it was read, without decoding it
+	pub fn get_next_raw(&mut self) -> Result<&[u8; RECORD_LEN], ErrorKind> {
+		if self.ended {
+			return Err(ErrorKind::EOFReached);
+		}
+		let read = read_full(&mut self.reader, &mut self.record).inspect_err(|_| self.ended = true)?;
+		match read {
+			RECORD_LEN => Ok(&self.record),
+			0 => {
+				self.ended = true;
+				Err(ErrorKind::EOFReached)
+			}
+			_ => {
+				self.ended = true;
+				Err(ErrorKind::TruncatedRecord)
+			}
+		}
+	}
+}
+
+//reads until the buffer is full or the reader ends, returning the number of bytes read
+#[doc(hidden)]
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> Result<usize, ErrorKind> {
+	let mut read = 0;
+	while read < buf.len() {
+		match reader.read(&mut buf[read..]) {
+			Ok(0) => break,
+			Ok(n) => read += n,
+			//retry according to https://doc.rust-lang.org/std/io/trait.Read.html#tymethod.read
+			Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+			Err(e) => return Err(ErrorKind::IOError(e.kind())),
+		}
+	}
+	Ok(read)
+}
@@ -1,58 +1,683 @@
 #![doc(html_no_source)]
 
 use std::env;
-use std::num::NonZeroUsize;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::ControlFlow;
+use std::process::ExitCode;
 use std::str::FromStr;
+use std::time::Instant;
 extern crate lib;
 
+mod admin;
+mod cli;
+mod http;
+mod input;
+mod repl;
+mod server;
+mod status;
+mod summary;
+
+use cli::{Command, Options, OutputFormat};
+use input::{Input, Rejects};
 use lib::{
-	FixedDecimalMAXDISPLEN, PaymentsProcessor, PaymentsTransaction, SpamReaderError,
-	SpamTolerantReader,
+	AccountStatement, CsvReport, FeeSchedule, JsonLinesReport, JsonReport, PaymentsProcessor,
+	RateTable, ReportWriter, Rounding, WireError, WireWriter,
 };
+use status::{Failure, Status};
+use summary::{describe_histogram, Outcome};
+
+#[doc(hidden)]
+fn load_rates(rates_file: Option<&str>, rounding: Option<&str>) -> Result<RateTable, Failure> {
+	let rates = match rates_file {
+		Some(rates_file) => {
+			let f = File::open(rates_file)
+				.map_err(|e| Failure::io(format!("Could not open rate table file: [{:?}]", e.kind())))?;
+			RateTable::from_reader(std::io::BufReader::new(f))
+				.map_err(|e| format!("Invalid rate table: [{:?}]", e))?
+		}
+		None => RateTable::default(),
+	};
+	match rounding {
+		Some(rounding) => {
+			Ok(rates.with_rounding(Rounding::from_str(rounding).map_err(|_| "Unknown rounding policy")?))
+		}
+		None => Ok(rates),
+	}
+}
+
+#[doc(hidden)]
+fn load_fees(fees_file: Option<&str>) -> Result<FeeSchedule, Failure> {
+	match fees_file {
+		Some(fees_file) => {
+			let f = File::open(fees_file).map_err(|e| {
+				Failure::io(format!(
+					"Could not open fee schedule file: [{:?}]",
+					e.kind()
+				))
+			})?;
+			Ok(
+				FeeSchedule::from_reader(std::io::BufReader::new(f))
+					.map_err(|e| format!("Invalid fee schedule: [{:?}]", e))?,
+			)
+		}
+		None => Ok(FeeSchedule::default()),
+	}
+}
+
+#[doc(hidden)]
+fn processor(options: &Options) -> Result<PaymentsProcessor, Failure> {
+	let mut pr = PaymentsProcessor::default()
+		.with_rates(load_rates(
+			options.rates_file.as_deref(),
+			options.rounding.as_deref(),
+		)?)
+		.with_fees(load_fees(options.fees_file.as_deref())?);
+	if let Some(max_balance) = options.max_balance {
+		pr = pr.with_max_balance(max_balance);
+	}
+	if let Some(key_retention) = options.key_retention {
+		pr = pr.with_idempotency_retention(key_retention);
+	}
+	Ok(pr)
+}
+
+#[doc(hidden)]
+fn output(options: &Options) -> Result<Box<dyn Write>, Failure> {
+	match &options.output_file {
+		Some(output_file) => {
+			let f = File::create(output_file).map_err(|e| {
+				Failure::io(format!(
+					"Could not create the output file: [{:?}]",
+					e.kind()
+				))
+			})?;
+			Ok(Box::new(BufWriter::new(f)))
+		}
+		None => Ok(Box::new(BufWriter::new(std::io::stdout().lock()))),
+	}
+}
+
+#[doc(hidden)]
+fn write_out(out: &mut dyn Write, txt: &str) -> Result<(), Failure> {
+	out
+		.write_all(txt.as_bytes())
+		.map_err(|e| Failure::io(format!("Could not write the output: [{}]", e)))
+}
+
+#[doc(hidden)]
+fn flush_out(out: &mut dyn Write) -> Result<(), Failure> {
+	out
+		.flush()
+		.map_err(|e| Failure::io(format!("Could not write the output: [{}]", e)))
+}
+
+//renders into a String, which writing to does not fail
+#[doc(hidden)]
+fn render(f: impl FnOnce(&mut String) -> std::fmt::Result) -> String {
+	let mut txt = String::new();
+	let _ = f(&mut txt);
+	txt
+}
+
+#[doc(hidden)]
+fn report_writer(format: OutputFormat) -> &'static dyn ReportWriter {
+	match format {
+		OutputFormat::Json => &JsonReport,
+		OutputFormat::JsonLines => &JsonLinesReport,
+		_ => &CsvReport,
+	}
+}
+
+//lets a report be written straight to the output, fmt::Error telling nothing the io error is kept
+#[doc(hidden)]
+struct FmtSink<'a> {
+	out: &'a mut dyn Write,
+	error: Option<std::io::Error>,
+}
+
+impl std::fmt::Write for FmtSink<'_> {
+	fn write_str(&mut self, s: &str) -> std::fmt::Result {
+		self.out.write_all(s.as_bytes()).map_err(|e| {
+			self.error = Some(e);
+			std::fmt::Error
+		})
+	}
+}
+
+#[doc(hidden)]
+fn write_report(
+	pr: &PaymentsProcessor,
+	options: &Options,
+	out: &mut dyn Write,
+) -> Result<(), Failure> {
+	let mut sink = FmtSink { out, error: None };
+	let res = pr.write_report(report_writer(options.output_format), &mut sink);
+	match (res, sink.error) {
+		(_, Some(e)) => Err(Failure::io(format!("Could not write the output: [{}]", e))),
+		(Err(_), None) => Err("Could not write the report".into()),
+		(Ok(()), None) => Ok(()),
+	}
+}
+
+#[doc(hidden)]
+fn describe_statement(statement: &AccountStatement) -> String {
+	format!(
+		"  client {}{}: available {}, held {}, total {}, locked {}\n",
+		statement.client,
+		statement
+			.currency
+			.map_or(String::new(), |x| format!(" {}", x)),
+		statement.available,
+		statement.held,
+		statement.total(),
+		statement.locked
+	)
+}
+
+//opens every input file before any is read, so that a wrong path does not leave the work half done
+#[doc(hidden)]
+fn open_inputs(options: &Options) -> Result<Vec<Input>, Failure> {
+	options
+		.input_files
+		.iter()
+		.map(|path| Input::open(options, path))
+		.collect()
+}
 
-const MAX_TRANSACTION_LEN: usize =
-	"withdrawal".len() + 5 /*u16 max digits*/ + 10 /*u32 max digits*/+ FixedDecimalMAXDISPLEN + 1 /*\n*/;
+//prints the summary of an input file, when there are several of them
+#[doc(hidden)]
+fn summarize(options: &Options, path: &str, summary: &str) {
+	if options.input_files.len() > 1 {
+		eprintln!("{}: {}", if path == "-" { "stdin" } else { path }, summary);
+	}
+}
 
-macro_rules! skip_fail {
-	($res:expr, $id:ident) => {
-		match $res {
-			Ok(val) => val,
+//feeds an input file to the processor, reporting every transaction on the trace if any.\
+//**seen** counts the transactions of all the input files, for `--until`
+#[doc(hidden)]
+fn apply(
+	pr: &mut PaymentsProcessor,
+	input: &mut Input,
+	mut rejects: Option<&mut Rejects>,
+	options: &Options,
+	mut trace: Option<&mut dyn Write>,
+	seen: &mut usize,
+	outcome: &mut Outcome,
+) -> Result<(), Failure> {
+	//in atomic mode the whole input file is a single batch, rolled back as soon as any line fails
+	if options.atomic {
+		pr.begin()
+			.map_err(|e| format!("Could not begin batch: [{:?}]", e))?;
+	}
+	input.for_each(|mut item| {
+		if options.until.is_some_and(|until| *seen >= until) {
+			return Ok(ControlFlow::Break(()));
+		}
+		*seen += 1;
+		outcome.lines += 1;
+		outcome.transactions += !item.header as usize;
+		let res = match std::mem::replace(&mut item.tx, Err(String::new())) {
+			Ok(tr) => {
+				let client = tr.client;
+				pr.process_transaction(tr).map(|_| client).map_err(|e| {
+					*outcome.failed.entry(input::kind(&e)).or_default() += 1;
+					item.describe(e)
+				})
+			}
 			Err(e) => {
-				eprintln!("Skipping transaction [{:?}]. Error [{:?}]", $id, e);
-				continue;
+				if let (Some(kind), false) = (item.error.take(), item.header) {
+					*outcome.invalid.entry(kind).or_default() += 1;
+				}
+				Err(e)
+			}
+		};
+		if let Some(trace) = trace.as_deref_mut() {
+			match &res {
+				Ok(client) => {
+					write_out(trace, &format!("#{} [{}] applied\n", seen, item.id()))?;
+					for statement in pr.client_statements(*client) {
+						write_out(trace, &describe_statement(&statement))?;
+					}
+				}
+				Err(e) => write_out(trace, &format!("#{} {}\n", seen, e))?,
 			}
 		}
+		let Err(e) = res else {
+			outcome.applied += 1;
+			return Ok(ControlFlow::Continue(()));
+		};
+		if let (Some(rejects), false) = (rejects.as_deref_mut(), item.header) {
+			rejects.reject(&item)?;
+		}
+		match () {
+			//the processor is poisoned, the last consistent balances are still reported
+			_ if pr.fault().is_some() => {
+				eprintln!("Processor fault at transaction {}", e);
+				outcome.rejected = options.atomic;
+			}
+			//a header line is tolerated even in atomic and strict modes
+			_ if options.atomic && !item.header => {
+				eprintln!("Rejecting input file at transaction {}", e);
+				outcome.rejected = true;
+			}
+			_ if options.strict && !item.header => {
+				eprintln!("Stopping at transaction {}", e);
+				outcome.stopped = true;
+			}
+			_ => {
+				eprintln!("Skipping transaction {}", e);
+				return Ok(ControlFlow::Continue(()));
+			}
+		}
+		Ok(ControlFlow::Break(()))
+	})?;
+	if outcome.rejected {
+		outcome.applied = 0;
+		pr.rollback()
+			.map_err(|e| format!("Could not roll back batch: [{:?}]", e))?;
+	} else if options.atomic {
+		pr.commit()
+			.map_err(|e| format!("Could not commit batch: [{:?}]", e))?;
+	}
+	//a batch leaving the books unbalanced means money was created or lost by the processor itself
+	if !pr.is_balanced() {
+		return Err(Failure::new(
+			Status::Fault,
+			"Terminating... trial balance failed, the books do not balance!",
+		));
+	}
+	Ok(())
+}
+
+//the failures of processing, reported once the output is written.\
+//**outcome** sums up all the input files
+#[doc(hidden)]
+fn check(pr: &PaymentsProcessor, outcome: &Outcome) -> Result<(), Failure> {
+	if pr.fault().is_some() {
+		return Err(Failure::new(
+			Status::Fault,
+			"Terminating... processor fault, the reported balances are the last consistent ones!",
+		));
+	}
+	check_rejects(outcome)
+}
+
+//the transactions that could not be read or applied make a partial success
+#[doc(hidden)]
+fn check_rejects(outcome: &Outcome) -> Result<(), Failure> {
+	let message = match () {
+		_ if outcome.rejected => {
+			"Terminating... input file rejected, none of its transactions were applied!".to_owned()
+		}
+		_ if outcome.stopped => {
+			"Terminating... strict mode stopped at the first failing transaction!".to_owned()
+		}
+		_ => match outcome.invalid() + outcome.failed() {
+			0 => return Ok(()),
+			n => format!("Completed with {} rejected transactions", n),
+		},
 	};
+	Err(Failure::new(Status::Rejects, message))
 }
 
 #[doc(hidden)]
-fn main() -> Result<(), String> {
-	let input_file = env::args().nth(1).ok_or("No input file detected")?;
-	let f = std::fs::File::open(input_file).map_err(|_| "File not found!")?;
-	let tolerance =
-		NonZeroUsize::new(MAX_TRANSACTION_LEN * 5).ok_or("Zero bytes spam tolerance is not allowed")?;
-	let mut sp = SpamTolerantReader::new(f, b'\n', tolerance);
-	let mut pr = PaymentsProcessor::default();
-	loop {
-		let n = sp.get_next();
-		match n {
-			Err(SpamReaderError::ToleranceExceeded) => {
-				return Err(
-					"Terminating... incorrect buffer beyond Tolerance threshold detected!".to_owned(),
-				)
+fn audit(pr: &PaymentsProcessor, out: Option<&mut dyn Write>) -> Result<(), Failure> {
+	let discrepancies = pr.verify();
+	match out {
+		Some(out) => {
+			for discrepancy in &discrepancies {
+				write_out(out, &format!("{:?}\n", discrepancy))?;
 			}
-			Err(SpamReaderError::IOError(e)) => match e {
-				std::io::ErrorKind::Interrupted => continue, //retry according to https://doc.rust-lang.org/std/io/trait.Read.html#tymethod.read
-				_ => return Err(format!("Terminating... Irrecoverable IO error: [{}]", e)),
-			},
-			Err(SpamReaderError::EOFReached) => break,
-			Ok(buf) => {
-				let tr_str = skip_fail!(std::str::from_utf8(buf), buf);
-				let tr = skip_fail!(PaymentsTransaction::from_str(tr_str), tr_str);
-				skip_fail!(pr.process_transaction(tr), tr_str);
+		}
+		None => {
+			for discrepancy in &discrepancies {
+				eprintln!("Audit discrepancy [{:?}]", discrepancy);
 			}
 		}
 	}
-	print!("{}", pr);
+	match discrepancies.len() {
+		0 => Ok(()),
+		n => Err(Failure::new(
+			Status::Fault,
+			format!("Audit failed with {} discrepancies!", n),
+		)),
+	}
+}
+
+#[doc(hidden)]
+fn process(options: &Options, outcome: &mut Outcome) -> Result<(), Failure> {
+	let inputs = open_inputs(options)?;
+	let mut pr = processor(options)?;
+	let mut rejects = Rejects::create(options)?;
+	let mut out = output(options)?;
+	let mut seen = 0;
+	for (path, mut input) in options.input_files.iter().zip(inputs) {
+		if options.until.is_some_and(|until| seen >= until) {
+			break;
+		}
+		let trace = match options.command {
+			Command::Replay => Some(out.as_mut() as &mut dyn Write),
+			_ => None,
+		};
+		let mut file = Outcome::default();
+		let res = apply(
+			&mut pr,
+			&mut input,
+			rejects.as_mut(),
+			options,
+			trace,
+			&mut seen,
+			&mut file,
+		);
+		file.bytes = input.bytes();
+		summarize(
+			options,
+			path,
+			&format!(
+				"{} transactions, {} applied, {} failed{}",
+				file.transactions,
+				file.applied,
+				file.transactions - file.applied,
+				if file.rejected {
+					", input file rejected"
+				} else {
+					""
+				}
+			),
+		);
+		let stopped = file.stopped;
+		outcome.add(file);
+		res?;
+		if stopped || pr.fault().is_some() {
+			break;
+		}
+	}
+	if let Some(rejects) = rejects {
+		rejects.finish()?;
+	}
+	match options.command {
+		Command::Audit => {}
+		_ => write_report(&pr, options, out.as_mut())?,
+	}
+	let res = match options.command {
+		Command::Audit => audit(&pr, Some(out.as_mut())),
+		_ => Ok(()),
+	};
+	flush_out(out.as_mut())?;
+	if pr.fault().is_none() {
+		res?;
+		if options.audit {
+			audit(&pr, None)?;
+		}
+	}
+	check(&pr, outcome)
+}
+
+//checks the input, processing it against throwaway books with `--dry-run`
+#[doc(hidden)]
+fn validate(options: &Options, outcome: &mut Outcome) -> Result<(), Failure> {
+	let inputs = open_inputs(options)?;
+	let mut pr = match options.dry_run {
+		true => Some(processor(options)?),
+		false => None,
+	};
+	let mut rejects = Rejects::create(options)?;
+	let mut out = output(options)?;
+	let mut seen = 0;
+	for (path, mut input) in options.input_files.iter().zip(inputs) {
+		let mut file = Outcome::default();
+		let res = match pr.as_mut() {
+			Some(pr) => apply(
+				pr,
+				&mut input,
+				rejects.as_mut(),
+				options,
+				None,
+				&mut seen,
+				&mut file,
+			),
+			None => check_input(&mut input, rejects.as_mut(), options, &mut file),
+		};
+		file.bytes = input.bytes();
+		let invalid = file.invalid();
+		summarize(
+			options,
+			path,
+			&match pr {
+				Some(_) => format!(
+					"{} valid, {} invalid, {} applied, {} failed transactions",
+					file.transactions - invalid,
+					invalid,
+					file.applied,
+					file.failed()
+				),
+				None => format!(
+					"{} valid, {} invalid transactions",
+					file.transactions - invalid,
+					invalid
+				),
+			},
+		);
+		let stopped = file.stopped;
+		outcome.add(file);
+		res?;
+		if stopped || pr.as_ref().is_some_and(|pr| pr.fault().is_some()) {
+			break;
+		}
+	}
+	if let Some(rejects) = rejects {
+		rejects.finish()?;
+	}
+	let invalid = outcome.invalid();
+	let failed = outcome.failed();
+	let mut report = format!(
+		"{} valid, {} invalid transactions\n{}",
+		outcome.transactions - invalid,
+		invalid,
+		describe_histogram(&outcome.invalid)
+	);
+	if pr.is_some() {
+		report += &format!(
+			"{} applied, {} failed transactions\n{}",
+			outcome.applied,
+			failed,
+			describe_histogram(&outcome.failed)
+		);
+	}
+	write_out(out.as_mut(), &report)?;
+	flush_out(out.as_mut())?;
+	if pr.as_ref().is_some_and(|pr| pr.fault().is_some()) {
+		return Err(Failure::new(
+			Status::Fault,
+			"Validation failed with a processor fault!",
+		));
+	}
+	if invalid > options.max_invalid {
+		return Err(Failure::new(
+			Status::Rejects,
+			format!(
+				"Validation failed with {} invalid transactions, at most {} allowed!",
+				invalid, options.max_invalid
+			),
+		));
+	}
+	if failed > options.max_failed {
+		return Err(Failure::new(
+			Status::Rejects,
+			format!(
+				"Validation failed with {} failed transactions, at most {} allowed!",
+				failed, options.max_failed
+			),
+		));
+	}
 	Ok(())
 }
+
+//reads an input file without processing it
+#[doc(hidden)]
+fn check_input(
+	input: &mut Input,
+	mut rejects: Option<&mut Rejects>,
+	options: &Options,
+	outcome: &mut Outcome,
+) -> Result<(), Failure> {
+	input.for_each(|mut item| {
+		outcome.lines += 1;
+		if item.header && item.tx.is_err() {
+			return Ok(ControlFlow::Continue(()));
+		}
+		outcome.transactions += 1;
+		let (Err(e), Some(kind)) = (&item.tx, item.error.take()) else {
+			return Ok(ControlFlow::Continue(()));
+		};
+		*outcome.invalid.entry(kind).or_default() += 1;
+		eprintln!("Invalid transaction {}", e);
+		if let Some(rejects) = rejects.as_deref_mut() {
+			rejects.reject(&item)?;
+		}
+		if options.strict {
+			outcome.stopped = true;
+			return Ok(ControlFlow::Break(()));
+		}
+		Ok(ControlFlow::Continue(()))
+	})
+}
+
+//where converted transactions are written
+#[doc(hidden)]
+enum Sink {
+	Csv(Box<dyn Write>),
+	JsonLines(Box<dyn Write>),
+	Binary(WireWriter<Box<dyn Write>>),
+}
+
+//converts the input into another format instead of processing it, skipping what cannot be converted
+#[doc(hidden)]
+fn convert(options: &Options, outcome: &mut Outcome) -> Result<(), Failure> {
+	let inputs = open_inputs(options)?;
+	let mut rejects = Rejects::create(options)?;
+	let out = output(options)?;
+	let mut sink = match options.output_format {
+		OutputFormat::Binary => Sink::Binary(
+			WireWriter::new(out)
+				.map_err(|e| Failure::io(format!("Could not write the output: [{:?}]", e)))?,
+		),
+		OutputFormat::Csv => {
+			let mut out = out;
+			write_out(
+				out.as_mut(),
+				"type,client,tx,amount,currency,target_currency,idempotency_key\n",
+			)?;
+			Sink::Csv(out)
+		}
+		_ => Sink::JsonLines(out),
+	};
+	for (path, mut input) in options.input_files.iter().zip(inputs) {
+		let mut file = Outcome::default();
+		let res = input.for_each(|mut item| {
+			file.lines += 1;
+			let res = match (&item.tx, &mut sink) {
+				(Err(e), _) => Err(e.clone()),
+				(Ok(tr), Sink::Binary(writer)) => match writer.write(tr) {
+					Err(WireError::IOError(e)) => {
+						return Err(Failure::io(format!(
+							"Could not write the output: [{:?}]",
+							e
+						)))
+					}
+					Err(e) => {
+						*file.failed.entry(input::kind(&e)).or_default() += 1;
+						Err(item.describe(e))
+					}
+					Ok(()) => Ok(()),
+				},
+				(Ok(tr), Sink::Csv(out)) => write_out(out.as_mut(), &format!("{}\n", tr)).map(Ok)?,
+				(Ok(tr), Sink::JsonLines(out)) => {
+					write_out(out.as_mut(), &format!("{}\n", tr.to_json())).map(Ok)?
+				}
+			};
+			if item.header && res.is_err() {
+				return Ok(ControlFlow::Continue(()));
+			}
+			file.transactions += 1;
+			let Err(e) = res else {
+				file.applied += 1;
+				return Ok(ControlFlow::Continue(()));
+			};
+			if let Some(kind) = item.error.take() {
+				*file.invalid.entry(kind).or_default() += 1;
+			}
+			if let Some(rejects) = rejects.as_mut() {
+				rejects.reject(&item)?;
+			}
+			if options.strict {
+				eprintln!("Stopping at transaction {}", e);
+				file.stopped = true;
+				return Ok(ControlFlow::Break(()));
+			}
+			eprintln!("Skipping transaction {}", e);
+			Ok(ControlFlow::Continue(()))
+		});
+		file.bytes = input.bytes();
+		summarize(
+			options,
+			path,
+			&format!(
+				"{} converted, {} skipped transactions",
+				file.applied,
+				file.transactions - file.applied
+			),
+		);
+		let stopped = file.stopped;
+		outcome.add(file);
+		res?;
+		if stopped {
+			break;
+		}
+	}
+	if let Some(rejects) = rejects {
+		rejects.finish()?;
+	}
+	let mut out = match sink {
+		Sink::Binary(writer) => writer
+			.into_inner()
+			.map_err(|e| Failure::io(format!("Could not write the output: [{:?}]", e)))?,
+		Sink::Csv(out) | Sink::JsonLines(out) => out,
+	};
+	flush_out(out.as_mut())?;
+	check_rejects(outcome)
+}
+
+#[doc(hidden)]
+fn run(args: impl IntoIterator<Item = String>) -> Result<(), Failure> {
+	let Some(options) = cli::parse(args).map_err(|e| Failure::new(Status::Usage, e))? else {
+		print!("{}", cli::USAGE);
+		return Ok(());
+	};
+	let start = Instant::now();
+	let mut outcome = Outcome::default();
+	let res = match options.command {
+		Command::Process | Command::Audit | Command::Replay => process(&options, &mut outcome),
+		Command::Validate => validate(&options, &mut outcome),
+		Command::Convert => convert(&options, &mut outcome),
+		Command::Serve => server::serve(&options),
+		Command::Http => http::serve(&options),
+		Command::Repl => repl::run(&options),
+	};
+	let status = res.as_ref().map_or_else(|e| e.status, |_| Status::Success);
+	//the summary is written whatever the outcome, a failure to write it only shows if the run succeeded
+	let summary = summary::write(&options, &outcome, status, start.elapsed());
+	res.and(summary)
+}
+
+#[doc(hidden)]
+fn main() -> ExitCode {
+	match run(env::args().skip(1)) {
+		Ok(()) => ExitCode::SUCCESS,
+		Err(e) => {
+			eprintln!("{}", e.message);
+			ExitCode::from(e.status.code())
+		}
+	}
+}
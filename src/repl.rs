@@ -0,0 +1,292 @@
+// An interactive session over the processor, for exploring its behaviour while writing test cases
+//
+// Every line read from stdin is either a transaction (CSV, parsed with `PaymentsTransaction::from_str`),
+// answered by `ok` or `err <kind>`, or one of the commands below. `.export` turns the transactions of
+// the session (the ones undone aside) into a test in the style of tests/processor_tests.rs
+
+use crate::cli::Options;
+use crate::input::kind;
+use crate::status::{Failure, Status};
+use lib::{
+	CsvReport, PaymentsProcessor, PaymentsTransaction, ProcessTransactionError, ReportWriter,
+	SpamReaderError, SpamTolerantReader, TransactionPayload, TransactionState,
+};
+use std::io::{IsTerminal, Write};
+use std::str::FromStr;
+
+//transactions that can be undone, far more than anyone types in a session
+#[doc(hidden)]
+const UNDO_DEPTH: usize = 100_000;
+
+#[doc(hidden)]
+const HELP: &str = "\
+<transaction>     Applies a CSV transaction, e.g. `deposit, 1, 1, 10.0`
+.show <client>    Prints the balances of a client
+.tx <tx>          Prints the dispute state, fee and conversion of a transaction
+.report           Prints the balances of every client
+.snapshot         Marks the current state, see .restore
+.restore          Restores the state marked by the last snapshot, forgetting it
+.undo [n]         Undoes the last n applied transactions (default 1)
+.reset            Starts over with empty books
+.export [name]    Prints the session as a test for tests/processor_tests.rs
+.help             Prints this message
+.quit             Ends the session (as does the end of the input)
+";
+
+//a transaction typed in the session, along with its outcome
+#[doc(hidden)]
+struct Step {
+	line: String,
+	tx: PaymentsTransaction,
+	res: Result<(), ProcessTransactionError>,
+}
+
+#[doc(hidden)]
+struct Session<'a> {
+	options: &'a Options,
+	pr: PaymentsProcessor,
+	steps: Vec<Step>,
+	//savepoint name and number of steps of each snapshot, the last one first to be restored
+	snapshots: Vec<(String, usize)>,
+	taken: usize,
+}
+
+/// Reads lines from stdin until its end or `.quit`, printing the reply to each one
+pub(crate) fn run(options: &Options) -> Result<(), Failure> {
+	let mut session = Session {
+		options,
+		pr: processor(options)?,
+		steps: Vec::new(),
+		snapshots: Vec::new(),
+		taken: 0,
+	};
+	let stdin = std::io::stdin();
+	//prompts would only clutter the output of a scripted session
+	let prompt = stdin.is_terminal();
+	let mut reader = SpamTolerantReader::new(stdin, b'\n', options.tolerance);
+	let mut out = std::io::stdout();
+	loop {
+		if prompt {
+			print!("> ");
+			let _ = out.flush();
+		}
+		let reply = match reader.get_next() {
+			Err(SpamReaderError::EOFReached) => return Ok(()),
+			//retry according to https://doc.rust-lang.org/std/io/trait.Read.html#tymethod.read
+			Err(SpamReaderError::IOError(std::io::ErrorKind::Interrupted)) => continue,
+			Err(SpamReaderError::IOError(e)) => {
+				return Err(Failure::io(format!("Could not read stdin: [{:?}]", e)))
+			}
+			Err(SpamReaderError::ToleranceExceeded) => {
+				return Err(Failure::new(
+					Status::ToleranceExceeded,
+					"A line longer than the tolerance was typed",
+				))
+			}
+			Ok(line) => match String::from_utf8_lossy(line).trim() {
+				"" => continue,
+				".quit" | ".exit" => return Ok(()),
+				line => session.respond(line)?,
+			},
+		};
+		crate::write_out(&mut out, &reply)?;
+		crate::flush_out(&mut out)?;
+	}
+}
+
+#[doc(hidden)]
+fn processor(options: &Options) -> Result<PaymentsProcessor, Failure> {
+	Ok(crate::processor(options)?.with_undo_depth(UNDO_DEPTH))
+}
+
+//a name the exported test function can take, keywords aside
+#[doc(hidden)]
+fn is_identifier(name: &str) -> bool {
+	const KEYWORDS: &[&str] = &[
+		"_", "abstract", "as", "async", "await", "become", "box", "break", "const", "continue",
+		"crate", "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if",
+		"impl", "in", "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub",
+		"ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "try", "type",
+		"typeof", "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+	];
+	let mut chars = name.chars();
+	chars
+		.next()
+		.is_some_and(|x| x.is_ascii_alphabetic() || x == '_')
+		&& chars.all(|x| x.is_ascii_alphanumeric() || x == '_')
+		&& !KEYWORDS.contains(&name)
+}
+
+impl Session<'_> {
+	//the reply to a line, newline included
+	fn respond(&mut self, line: &str) -> Result<String, Failure> {
+		let Some(command) = line.strip_prefix('.') else {
+			return Ok(self.apply(line));
+		};
+		let words: Vec<_> = command.split_whitespace().collect();
+		let reply = match words[..] {
+			["show", client] => match u16::from_str(client) {
+				Ok(client) => self.show(client),
+				Err(_) => "err InvalidClient\n".to_owned(),
+			},
+			["tx", tx] => match u32::from_str(tx) {
+				Ok(tx) => self.tx(tx),
+				Err(_) => "err InvalidTransaction\n".to_owned(),
+			},
+			["report"] => self.pr.to_string(),
+			["snapshot"] => {
+				self.taken += 1;
+				let name = self.taken.to_string();
+				match self.pr.savepoint(&name) {
+					Ok(()) => {
+						self.snapshots.push((name, self.steps.len()));
+						format!("snapshot {}\n", self.taken)
+					}
+					Err(e) => format!("err {}\n", kind(&e)),
+				}
+			}
+			["restore"] => self.restore(),
+			["undo"] => self.undo(1),
+			["undo", n] => match usize::from_str(n) {
+				Ok(n) => self.undo(n),
+				Err(_) => "err InvalidCount\n".to_owned(),
+			},
+			["reset"] => {
+				self.pr = processor(self.options)?;
+				self.steps.clear();
+				self.snapshots.clear();
+				"ok\n".to_owned()
+			}
+			["export"] => self.export("repl_session"),
+			["export", name] => self.export(name),
+			["help"] => HELP.to_owned(),
+			_ => "err UnknownCommand, see .help\n".to_owned(),
+		};
+		Ok(reply)
+	}
+
+	fn apply(&mut self, line: &str) -> String {
+		let tx = match PaymentsTransaction::from_str(line) {
+			Ok(tx) => tx,
+			//not part of the session, an export has nothing to replay
+			Err(e) => return format!("err {}\n", kind(&e)),
+		};
+		let res = self.pr.process_transaction(tx.clone());
+		let reply = match &res {
+			Ok(()) => "ok\n".to_owned(),
+			Err(e) => format!("err {}\n", kind(e)),
+		};
+		self.steps.push(Step {
+			line: line.to_owned(),
+			tx,
+			res,
+		});
+		reply
+	}
+
+	fn show(&self, client: u16) -> String {
+		let statements = self.pr.client_statements(client);
+		if statements.is_empty() {
+			return "err ClientNotFound\n".to_owned();
+		}
+		crate::render(|x| CsvReport.write_report(x, &statements))
+	}
+
+	//transaction IDs are per client in the books, the session tells which client applied one
+	fn tx(&self, tx: u32) -> String {
+		let client = self.steps.iter().rev().find_map(|x| {
+			let recorded = matches!(
+				x.tx.payload,
+				TransactionPayload::Deposit(_)
+					| TransactionPayload::Withdrawal(_)
+					| TransactionPayload::Convert(..)
+			);
+			(x.tx.tx == tx && recorded && x.res.is_ok()).then_some(x.tx.client)
+		});
+		let Some((client, state)) =
+			client.and_then(|cl| Some((cl, self.pr.transaction_state(cl, tx)?)))
+		else {
+			return "err TransactionNotFound\n".to_owned();
+		};
+		let mut reply = format!("client={} tx={} state={}", client, tx, kind(&state));
+		match state {
+			TransactionState::UnderDispute(held) | TransactionState::PreArbitration(held) => {
+				reply += &format!(" held={}", held)
+			}
+			TransactionState::Represented(amount) => reply += &format!(" represented={}", amount),
+			_ => (),
+		}
+		if let Some(fee) = self.pr.transaction_fee(client, tx) {
+			reply += &format!(" fee={}", fee);
+		}
+		if let Some(audit) = self.pr.conversion_audit(client, tx) {
+			reply += &format!(" conversion={:?}", audit);
+		}
+		reply + "\n"
+	}
+
+	fn restore(&mut self) -> String {
+		//undoing past a snapshot forgets it in the books, restoring it fails with UnknownSavepoint
+		let Some((name, steps)) = self.snapshots.pop() else {
+			return "err NoSnapshot\n".to_owned();
+		};
+		let res = self
+			.pr
+			.undo_to_savepoint(&name)
+			.and_then(|undone| self.pr.release_savepoint(&name).map(|_| undone));
+		match res {
+			Ok(undone) => {
+				self.steps.truncate(steps);
+				format!("ok {} undone\n", undone)
+			}
+			Err(e) => format!("err {}\n", kind(&e)),
+		}
+	}
+
+	fn undo(&mut self, n: usize) -> String {
+		if let Err(e) = self.pr.undo_last(n) {
+			return format!("err {}\n", kind(&e));
+		}
+		//the steps from the n-th last applied transaction on are gone, failed ones included
+		let applied = self.steps.iter().enumerate().filter(|(_, x)| x.res.is_ok());
+		if let Some((i, _)) = applied.rev().nth(n.saturating_sub(1)).filter(|_| n > 0) {
+			self.steps.truncate(i);
+		}
+		"ok\n".to_owned()
+	}
+
+	fn export(&self, name: &str) -> String {
+		if !is_identifier(name) {
+			return "err InvalidTestName\n".to_owned();
+		}
+		//the test replays on a default processor, it would not tell what the session saw
+		if self.options.rates_file.is_some()
+			|| self.options.fees_file.is_some()
+			|| self.options.max_balance.is_some()
+			|| self.options.key_retention.is_some()
+		{
+			return "err ExportUnsupported\n".to_owned();
+		}
+		let mut test = format!("#[test]\nfn {}() {{\n", name);
+		test += "\tlet mut proc: PaymentsProcessor = Default::default();\n";
+		for step in &self.steps {
+			test += &format!(
+				"\tlet res =\n\t\tproc.process_transaction(PaymentsTransaction::from_str({:?}).unwrap());\n",
+				step.line
+			);
+			test += &match &step.res {
+				Ok(()) => "\tassert!(res.is_ok());\n".to_owned(),
+				//faults carry details that are no plain variant
+				Err(e) if format!("{:?}", e) == kind(e) => {
+					format!("\tassert_eq!(res, Err(PTErr::{:?}));\n", e)
+				}
+				Err(_) => "\tassert!(res.is_err());\n".to_owned(),
+			};
+		}
+		test += &format!(
+			"\tlet output = format!(\"{{}}\", proc);\n\tassert_eq!(output, {:?});\n}}\n",
+			self.pr.to_string()
+		);
+		test
+	}
+}
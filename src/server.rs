@@ -0,0 +1,147 @@
+// A local TCP server applying the transactions of every connection to the same books
+//
+// One request per line, answered by one reply each, in order:
+//   a transaction (CSV, or JSON with `--input-format jsonl`)  ->  `ok`, or `err <kind>` naming why it was not applied
+//   `report`                                                  ->  the account report, followed by an empty line
+// A line longer than the tolerance is answered with `err ToleranceExceeded` and closes the connection,
+// as nothing can be told about where the next line starts. Transactions wait while ingestion is paused
+// through the control interface (see admin.rs)
+
+use crate::admin::{self, Control};
+use crate::cli::{InputFormat, Options, OutputFormat};
+use crate::input::kind;
+use crate::status::{Failure, Status};
+use lib::{
+	PaymentsProcessor, PaymentsTransaction, SpamReaderError, SpamTolerantReader, UnknownFields,
+};
+use std::io::{BufWriter, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::num::NonZeroUsize;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+//what every connection needs out of the options
+#[doc(hidden)]
+#[derive(Clone, Copy)]
+struct Session {
+	json: Option<UnknownFields>,
+	tolerance: NonZeroUsize,
+	report: OutputFormat,
+}
+
+/// Serves until the process is killed, each connection on its own thread
+pub(crate) fn serve(options: &Options) -> Result<(), Failure> {
+	let session = Session {
+		json: match options.input_format {
+			InputFormat::JsonLines => Some(options.unknown_fields),
+			_ => None,
+		},
+		tolerance: options.tolerance,
+		report: options.output_format,
+	};
+	listen(options, "", move |stream, pr, control| {
+		connection(stream, pr, control, session)
+	})
+}
+
+/// Sets up the books (and their admin socket) and listens on the port of the options until the process
+/// is killed, handing each connection to **connection** on its own thread. `scheme` prefixes the address
+/// printed once listening
+pub(crate) fn listen<F>(options: &Options, scheme: &str, connection: F) -> Result<(), Failure>
+where
+	F: Fn(TcpStream, &Mutex<PaymentsProcessor>, &Control) -> std::io::Result<()>
+		+ Copy
+		+ Send
+		+ 'static,
+{
+	let pr = Arc::new(Mutex::new(crate::processor(options)?));
+	let control = Arc::new(Control::new());
+	if let Some(path) = &options.admin_socket {
+		admin::listen(path, pr.clone(), control.clone())?;
+	}
+	let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, options.port))
+		.map_err(|e| Failure::io(format!("Could not listen: [{}]", e)))?;
+	let addr = listener
+		.local_addr()
+		.map_err(|e| Failure::io(format!("Could not listen: [{}]", e)))?;
+	eprintln!("Listening on {}{}", scheme, addr);
+	for stream in listener.incoming() {
+		match stream {
+			Ok(stream) => {
+				let (pr, control) = (pr.clone(), control.clone());
+				std::thread::spawn(move || {
+					let peer = stream.peer_addr();
+					if let Err(e) = connection(stream, &pr, &control) {
+						eprintln!("Connection {:?} dropped: [{}]", peer, e);
+					}
+				});
+			}
+			Err(e) => eprintln!("Could not accept a connection: [{}]", e),
+		}
+	}
+	Err(Failure::new(Status::Failure, "The listener stopped"))
+}
+
+#[doc(hidden)]
+fn connection(
+	stream: TcpStream,
+	pr: &Mutex<PaymentsProcessor>,
+	control: &Control,
+	session: Session,
+) -> std::io::Result<()> {
+	let mut reader = SpamTolerantReader::new(stream.try_clone()?, b'\n', session.tolerance);
+	let mut writer = BufWriter::new(stream);
+	loop {
+		let reply = match reader.get_next() {
+			Err(SpamReaderError::EOFReached) => return Ok(()),
+			//retry according to https://doc.rust-lang.org/std/io/trait.Read.html#tymethod.read
+			Err(SpamReaderError::IOError(std::io::ErrorKind::Interrupted)) => continue,
+			Err(SpamReaderError::IOError(e)) => return Err(e.into()),
+			Err(SpamReaderError::ToleranceExceeded) => {
+				writer.write_all(b"err ToleranceExceeded\n")?;
+				return writer.flush();
+			}
+			Ok(line) => respond(line, pr, control, session),
+		};
+		writer.write_all(reply.as_bytes())?;
+		writer.flush()?;
+	}
+}
+
+//the reply to a single line, newline included
+#[doc(hidden)]
+fn respond(
+	line: &[u8],
+	pr: &Mutex<PaymentsProcessor>,
+	control: &Control,
+	session: Session,
+) -> String {
+	let poisoned = || format!("err {}\n", admin::POISONED);
+	let line = match std::str::from_utf8(line) {
+		Ok(line) => line,
+		Err(e) => return format!("err {}\n", kind(&e)),
+	};
+	if line.trim() == "report" {
+		let Ok(pr) = pr.lock() else {
+			return poisoned();
+		};
+		let report = crate::render(|x| pr.write_report(crate::report_writer(session.report), x));
+		return report + "\n";
+	}
+	let tx = match session.json {
+		None => PaymentsTransaction::from_str(line),
+		Some(unknown_fields) => PaymentsTransaction::from_json(line, unknown_fields),
+	};
+	let res = match tx {
+		Ok(tx) => match control.ingest(pr, |pr| pr.process_transaction(tx)) {
+			Some(res) => res.map_err(|e| kind(&e)),
+			None => return poisoned(),
+		},
+		Err(e) => Err(kind(&e)),
+	};
+	control.record(&res);
+	match res {
+		Ok(()) => "ok\n".to_owned(),
+		Err(kind) => format!("err {}\n", kind),
+	}
+}
@@ -0,0 +1,56 @@
+// Exit statuses of the binary, so that a scheduler can tell failures apart without parsing messages
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Status {
+	Success = 0,
+	//any failure not covered by the statuses below (an invalid rate table, an unreadable binary header...)
+	Failure = 1,
+	//the command line could not be parsed
+	Usage = 2,
+	//an input, output, reject or summary file could not be opened, read or written
+	Io = 3,
+	//a text record is longer than the tolerance, the input is considered spam
+	ToleranceExceeded = 4,
+	//the run completed but some transactions could not be read or applied
+	Rejects = 5,
+	//the books are inconsistent: processor fault, failed trial balance or audit discrepancies
+	Fault = 6,
+}
+
+impl Status {
+	pub(crate) fn code(self) -> u8 {
+		self as u8
+	}
+}
+
+//why a run did not succeed
+#[derive(Debug)]
+pub(crate) struct Failure {
+	pub(crate) status: Status,
+	pub(crate) message: String,
+}
+
+impl Failure {
+	pub(crate) fn new(status: Status, message: impl Into<String>) -> Self {
+		Failure {
+			status,
+			message: message.into(),
+		}
+	}
+
+	pub(crate) fn io(message: impl Into<String>) -> Self {
+		Failure::new(Status::Io, message)
+	}
+}
+
+impl From<String> for Failure {
+	fn from(message: String) -> Self {
+		Failure::new(Status::Failure, message)
+	}
+}
+
+impl From<&str> for Failure {
+	fn from(message: &str) -> Self {
+		Failure::new(Status::Failure, message)
+	}
+}
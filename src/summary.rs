@@ -0,0 +1,125 @@
+// The counts of a run, summed up per input file, and the machine readable summary written from them
+
+use crate::cli::{Options, SummaryFormat};
+use crate::status::{Failure, Status};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+//number of failed transactions by kind of error
+pub(crate) type Histogram = BTreeMap<String, usize>;
+
+//lists the kinds of error, the most frequent first
+pub(crate) fn describe_histogram(histogram: &Histogram) -> String {
+	let mut kinds: Vec<_> = histogram.iter().collect();
+	kinds.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+	kinds
+		.into_iter()
+		.map(|(kind, n)| format!("  {} {}\n", n, kind))
+		.collect()
+}
+
+//how processing (or converting, or validating) an input file ended
+#[derive(Default)]
+pub(crate) struct Outcome {
+	//every record read, header lines included
+	pub(crate) lines: usize,
+	//header lines aside
+	pub(crate) transactions: usize,
+	//applied by the processor, or converted
+	pub(crate) applied: usize,
+	//the transactions that could not be read
+	pub(crate) invalid: Histogram,
+	//the transactions the processor did not apply, or that could not be converted
+	pub(crate) failed: Histogram,
+	pub(crate) bytes: u64,
+	//the whole input file was rolled back (atomic mode)
+	pub(crate) rejected: bool,
+	//processing stopped at the first failing transaction (strict mode)
+	pub(crate) stopped: bool,
+}
+
+impl Outcome {
+	pub(crate) fn add(&mut self, file: Outcome) {
+		self.lines += file.lines;
+		self.transactions += file.transactions;
+		self.applied += file.applied;
+		for (histogram, file) in [
+			(&mut self.invalid, file.invalid),
+			(&mut self.failed, file.failed),
+		] {
+			for (kind, n) in file {
+				*histogram.entry(kind).or_default() += n;
+			}
+		}
+		self.bytes += file.bytes;
+		self.rejected |= file.rejected;
+		self.stopped |= file.stopped;
+	}
+
+	pub(crate) fn invalid(&self) -> usize {
+		self.invalid.values().sum()
+	}
+
+	pub(crate) fn failed(&self) -> usize {
+		self.failed.values().sum()
+	}
+}
+
+/// Writes the summary of the run to the `--summary` file, if any
+pub(crate) fn write(
+	options: &Options,
+	outcome: &Outcome,
+	status: Status,
+	elapsed: Duration,
+) -> Result<(), Failure> {
+	let Some(summary_file) = &options.summary_file else {
+		return Ok(());
+	};
+	let mut rejected = outcome.invalid.clone();
+	for (kind, n) in &outcome.failed {
+		*rejected.entry(kind.clone()).or_default() += n;
+	}
+	let fields = [
+		("command", format!("\"{}\"", options.command.name())),
+		("exit_code", status.code().to_string()),
+		("lines_read", outcome.lines.to_string()),
+		(
+			"parsed",
+			(outcome.transactions - outcome.invalid()).to_string(),
+		),
+		("applied", outcome.applied.to_string()),
+		("rejected", rejected.values().sum::<usize>().to_string()),
+		("bytes_processed", outcome.bytes.to_string()),
+		(
+			"elapsed_ms",
+			format!("{:.3}", elapsed.as_secs_f64() * 1000.0),
+		),
+	];
+	//error kinds are variant names, nothing to escape
+	let summary = match options.summary_format {
+		SummaryFormat::Json => format!(
+			"{{{},\"rejected_by_kind\":{{{}}}}}\n",
+			fields
+				.iter()
+				.map(|(key, value)| format!("\"{}\":{}", key, value))
+				.collect::<Vec<_>>()
+				.join(","),
+			rejected
+				.iter()
+				.map(|(kind, n)| format!("\"{}\":{}", kind, n))
+				.collect::<Vec<_>>()
+				.join(",")
+		),
+		SummaryFormat::KeyValue => fields
+			.iter()
+			.map(|(key, value)| format!("{}={}\n", key, value.trim_matches('"')))
+			.chain(
+				rejected
+					.iter()
+					.map(|(kind, n)| format!("rejected.{}={}\n", kind, n)),
+			)
+			.collect(),
+	};
+	std::fs::write(summary_file, summary)
+		.map_err(|e| Failure::io(format!("Could not write the summary file: [{}]", e)))
+}
@@ -0,0 +1,167 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::{Client, Scratch, Server};
+use std::io::BufRead;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+//the path of the socket in a directory of its own, removed after the server it is declared before
+fn socket(test: &str) -> (Scratch, PathBuf) {
+	let dir = Scratch::new(&format!("admin-{}", test));
+	let socket = dir.file("admin.sock");
+	(dir, socket)
+}
+
+//starts the line server on a free port along with its control interface, returning its address
+fn serve(socket: &Path) -> (Server, String) {
+	common::serve("serve", &["--admin-socket", socket.to_str().unwrap()])
+}
+
+impl Client<UnixStream> {
+	fn admin(socket: &PathBuf) -> Self {
+		let writer = UnixStream::connect(socket).unwrap();
+		let reader = std::io::BufReader::new(writer.try_clone().unwrap());
+		Client { writer, reader }
+	}
+
+	//the metrics lines, up to the empty line ending them
+	fn metrics(&mut self) -> Vec<String> {
+		self.send("metrics");
+		self
+			.block()
+			.iter()
+			.map(|x| x.trim_end().to_owned())
+			.collect()
+	}
+}
+
+#[test]
+fn paused_ingestion_holds_transactions_until_resumed() {
+	let (_dir, socket) = socket("pause");
+	let (_server, addr) = serve(&socket);
+	let mut admin = Client::admin(&socket);
+	let mut client = Client::connect(&addr);
+	assert_eq!(admin.command("pause"), "ok\n");
+	client
+		.writer
+		.set_read_timeout(Some(Duration::from_millis(200)))
+		.unwrap();
+	client.send("deposit, 1, 1, 10.0");
+	let mut line = String::new();
+	assert!(client.reader.read_line(&mut line).is_err());
+	assert!(admin.metrics().contains(&"paused=true".to_owned()));
+	assert_eq!(admin.command("resume"), "ok\n");
+	client.writer.set_read_timeout(None).unwrap();
+	assert_eq!(client.reply(), "ok\n");
+	assert!(admin.metrics().contains(&"paused=false".to_owned()));
+}
+
+#[test]
+fn clients_are_locked_and_unlocked_by_the_operator() {
+	let (_dir, socket) = socket("lock");
+	let (_server, addr) = serve(&socket);
+	let mut admin = Client::admin(&socket);
+	let mut client = Client::connect(&addr);
+	assert_eq!(admin.command("lock 1"), "err ClientNotFound\n");
+	assert_eq!(admin.command("lock one"), "err InvalidClient\n");
+	assert_eq!(client.command("deposit, 1, 1, 10.0"), "ok\n");
+	assert_eq!(admin.command("lock 1"), "ok\n");
+	assert_eq!(
+		client.command("withdrawal, 1, 2, 1.0"),
+		"err AccountFrozen\n"
+	);
+	assert_eq!(admin.command("unlock 1"), "ok\n");
+	assert_eq!(client.command("withdrawal, 1, 2, 1.0"), "ok\n");
+	assert_eq!(client.command("bogus"), "err UnknownTransactionType\n");
+	let metrics = admin.metrics();
+	for expected in [
+		"applied=2",
+		"rejected=2",
+		"clients=1",
+		"locked_clients=0",
+		"balanced=true",
+		"fault=none",
+		"rejected.AccountFrozen=1",
+		"rejected.UnknownTransactionType=1",
+	] {
+		assert!(metrics.contains(&expected.to_owned()), "{:?}", metrics);
+	}
+}
+
+#[test]
+fn snapshots_restore_the_books() {
+	let (_dir, socket) = socket("snapshot");
+	let (_server, addr) = serve(&socket);
+	let mut admin = Client::admin(&socket);
+	let mut client = Client::connect(&addr);
+	assert_eq!(client.command("deposit, 1, 1, 10.0"), "ok\n");
+	assert_eq!(admin.command("snapshot before"), "ok\n");
+	assert_eq!(admin.command("snapshot before"), "err DuplicateSavepoint\n");
+	assert_eq!(client.command("withdrawal, 1, 2, 4.0"), "ok\n");
+	assert_eq!(client.command("deposit, 2, 3, 1.0"), "ok\n");
+	assert_eq!(admin.command("restore before"), "ok 2\n");
+	assert_eq!(admin.command("release before"), "ok\n");
+	assert_eq!(admin.command("restore before"), "err UnknownSavepoint\n");
+	client.send("report");
+	assert_eq!(client.reply(), "client,available,held,total,locked\n");
+	assert_eq!(client.reply(), "1,10.0,0.0,10.0,false\n");
+}
+
+#[test]
+fn snapshots_are_capped_and_report_what_they_pin() {
+	let (_dir, socket) = socket("cap");
+	let (_server, addr) = serve(&socket);
+	let mut admin = Client::admin(&socket);
+	let mut client = Client::connect(&addr);
+	for i in 0..16 {
+		assert_eq!(admin.command(&format!("snapshot s{}", i)), "ok\n");
+	}
+	assert_eq!(admin.command("snapshot s16"), "err TooManySnapshots\n");
+	assert_eq!(client.command("deposit, 1, 1, 10.0"), "ok\n");
+	assert_eq!(client.command("deposit, 1, 2, 10.0"), "ok\n");
+	let metrics = admin.metrics();
+	for expected in ["snapshots=16", "pinned=2"] {
+		assert!(metrics.contains(&expected.to_owned()), "{:?}", metrics);
+	}
+	assert_eq!(admin.command("release s0"), "ok\n");
+	assert_eq!(admin.command("snapshot again"), "ok\n");
+	let metrics = admin.metrics();
+	for expected in ["snapshots=1", "pinned=0"] {
+		assert!(metrics.contains(&expected.to_owned()), "{:?}", metrics);
+	}
+}
+
+#[test]
+fn the_socket_is_private_and_replaces_a_stale_one() {
+	let (_dir, socket) = socket("stale");
+	//a socket nobody listens to anymore
+	drop(std::os::unix::net::UnixListener::bind(&socket).unwrap());
+	let (_server, _) = serve(&socket);
+	let mode = std::fs::metadata(&socket).unwrap().permissions().mode();
+	assert_eq!(mode & 0o777, 0o600);
+	let mut admin = Client::admin(&socket);
+	assert_eq!(admin.command("reboot"), "err UnknownCommand\n");
+	admin.send(&"x".repeat(300));
+	assert_eq!(admin.reply(), "err ToleranceExceeded\n");
+	assert_eq!(admin.reply(), "");
+}
+
+#[test]
+fn a_file_in_the_way_of_the_socket_is_never_replaced() {
+	let (_dir, socket) = socket("file");
+	std::fs::write(&socket, "operator data").unwrap();
+	let output = Command::new(env!("CARGO_BIN_EXE_mini-payments"))
+		.args(["serve", "--port", "0", "--admin-socket"])
+		.arg(&socket)
+		.output()
+		.unwrap();
+	assert_eq!(output.status.code(), Some(3));
+	let err = String::from_utf8(output.stderr).unwrap();
+	assert!(err.contains("is not a socket"), "{}", err);
+	assert_eq!(std::fs::read_to_string(&socket).unwrap(), "operator data");
+}
@@ -0,0 +1,504 @@
+mod common;
+
+use common::Scratch;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+const INPUT: &str = "type, client, tx, amount
+deposit, 1, 1, 10.0
+withdrawal, 1, 2, 20.0
+bogus
+deposit, 2, 3, 5.0
+";
+
+const REPORT: &str = "client,available,held,total,locked
+1,10.0,0.0,10.0,false
+2,5.0,0.0,5.0,false
+";
+
+//runs the binary, returning its exit status, its stdout and its stderr
+fn run(args: &[&str]) -> (i32, String, String) {
+	let output = Command::new(env!("CARGO_BIN_EXE_mini-payments"))
+		.args(args)
+		.output()
+		.unwrap();
+	(
+		output.status.code().unwrap(),
+		String::from_utf8(output.stdout).unwrap(),
+		String::from_utf8(output.stderr).unwrap(),
+	)
+}
+
+//same as run, writing **stdin** to the standard input of the binary
+fn run_with_stdin(args: &[&str], stdin: &str) -> (i32, String, String) {
+	let mut child = Command::new(env!("CARGO_BIN_EXE_mini-payments"))
+		.args(args)
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.spawn()
+		.unwrap();
+	child
+		.stdin
+		.take()
+		.unwrap()
+		.write_all(stdin.as_bytes())
+		.unwrap();
+	let output = child.wait_with_output().unwrap();
+	(
+		output.status.code().unwrap(),
+		String::from_utf8(output.stdout).unwrap(),
+		String::from_utf8(output.stderr).unwrap(),
+	)
+}
+
+#[test]
+fn help_is_printed_on_request_or_without_arguments() {
+	for args in [
+		&[][..],
+		&["help"],
+		&["--help"],
+		&["validate", "x.csv", "-h"],
+	] {
+		let (code, out, _) = run(args);
+		assert_eq!(code, 0, "{:?}", args);
+		assert!(out.starts_with("mini-payments"), "{:?}", args);
+		assert!(out.contains("Commands:"), "{:?}", args);
+	}
+}
+
+#[test]
+fn argument_mistakes_are_reported_clearly() {
+	let dir = Scratch::new("mistakes");
+	let file = dir.input(INPUT);
+	for (args, expected) in [
+		(
+			vec!["--frobnicate"],
+			"Unknown option `--frobnicate` for the process command",
+		),
+		(
+			vec![&file, "--tolerance"],
+			"Missing value for option `--tolerance`",
+		),
+		(
+			vec![&file, "--reject-file", "--strict"],
+			"Missing value for option `--reject-file`",
+		),
+		(
+			vec![&file, "--tolerance", "lots"],
+			"Invalid value `lots` for option `--tolerance`",
+		),
+		(
+			vec![&file, "--delimiter", "ab"],
+			"Invalid value `ab` for option `--delimiter`",
+		),
+		(
+			vec!["validate", &file, "--atomic"],
+			"Option `--atomic` does not apply to the validate command",
+		),
+		(
+			vec!["convert", &file, "--output-format", "json"],
+			"Unknown output format `json` for the convert command",
+		),
+		(vec!["process", "--strict"], "No input file detected"),
+		(
+			vec!["-", &file, "-"],
+			"The standard input `-` can only be read once",
+		),
+		(vec![&file, "--atomic", "--strict"], "cannot be combined"),
+		(
+			vec!["http", "--input-format", "jsonl"],
+			"Option `--input-format` does not apply to the http command",
+		),
+		(
+			vec!["serve", "--max-body", "10"],
+			"Option `--max-body` does not apply to the serve command",
+		),
+		(vec!["http", &file], "the http command takes no input file"),
+	] {
+		let (code, out, err) = run(&args);
+		assert_eq!(code, 2, "{:?}", args);
+		assert!(out.is_empty(), "{:?}", args);
+		assert!(err.contains(expected), "{:?} {}", args, err);
+	}
+}
+
+#[test]
+fn process_is_the_default_command() {
+	let dir = Scratch::new("default");
+	let file = dir.input(INPUT);
+	let (code, out, err) = run(&[&file]);
+	assert_eq!(code, 5);
+	assert_eq!(out, REPORT);
+	assert!(err.contains("Skipping transaction [\"bogus\"]"));
+	assert_eq!(run(&["process", &file]).1, REPORT);
+}
+
+#[test]
+fn rejected_transactions_are_written_as_they_were_read() {
+	let dir = Scratch::new("rejects");
+	let file = dir.input(INPUT);
+	let rejects = dir.file("rejects");
+	let (code, out, _) = run(&[&file, "--reject-file", rejects.to_str().unwrap()]);
+	assert_eq!(code, 5);
+	assert_eq!(out, REPORT);
+	assert_eq!(
+		std::fs::read_to_string(&rejects).unwrap(),
+		"withdrawal, 1, 2, 20.0\nbogus\n"
+	);
+}
+
+#[test]
+fn strict_mode_stops_at_the_first_failure() {
+	let dir = Scratch::new("strict");
+	let file = dir.input(INPUT);
+	let (code, out, err) = run(&[&file, "--strict"]);
+	assert_eq!(code, 5);
+	assert_eq!(
+		out,
+		"client,available,held,total,locked\n1,10.0,0.0,10.0,false\n"
+	);
+	assert!(err.contains("Stopping at transaction [\"withdrawal, 1, 2, 20.0\"]"));
+}
+
+#[test]
+fn delimiter_and_tolerance_apply_to_the_input() {
+	let dir = Scratch::new("delimiter");
+	let file = dir.input("deposit, 1, 1, 1.0;deposit, 1, 2, 2.0;");
+	let (code, out, _) = run(&[&file, "--delimiter", ";"]);
+	assert_eq!(code, 0);
+	assert_eq!(
+		out,
+		"client,available,held,total,locked\n1,3.0,0.0,3.0,false\n"
+	);
+	let (code, _, err) = run(&[&file, "--tolerance", "8"]);
+	assert_eq!(code, 4);
+	assert!(err.contains("Tolerance threshold"));
+}
+
+#[test]
+fn validate_counts_the_invalid_transactions() {
+	let dir = Scratch::new("validate");
+	let file = dir.input(INPUT);
+	let (code, out, err) = run(&["validate", &file]);
+	assert_eq!(code, 5);
+	assert_eq!(
+		out,
+		"3 valid, 1 invalid transactions\n  1 UnknownTransactionType\n"
+	);
+	assert!(err.contains("Invalid transaction [\"bogus\"]"));
+	let file = dir.input("deposit, 1, 1, 1.0\n");
+	assert_eq!(
+		run(&["validate", &file]),
+		(
+			0,
+			"1 valid, 0 invalid transactions\n".to_owned(),
+			String::new()
+		)
+	);
+}
+
+#[test]
+fn dry_run_counts_the_failures_by_kind_against_thresholds() {
+	let dir = Scratch::new("dry-run");
+	let file = dir.input(
+		"deposit, 1, 1, 10.0\nwithdrawal, 1, 2, 20.0\nbogus\nwithdrawal, 1, 3, 30.0\ndispute, 2, 9\n",
+	);
+	let report = "4 valid, 1 invalid transactions
+  1 UnknownTransactionType
+1 applied, 3 failed transactions
+  2 NoAvailableFunds
+  1 ClientNotFound
+";
+	let (code, out, err) = run(&["validate", &file, "--dry-run"]);
+	assert_eq!(code, 5);
+	assert_eq!(out, report);
+	assert!(err.ends_with("Validation failed with 1 invalid transactions, at most 0 allowed!\n"));
+	let (code, out, err) = run(&["validate", &file, "--dry-run", "--max-invalid", "1"]);
+	assert_eq!(code, 5);
+	assert_eq!(out, report);
+	assert!(err.ends_with("Validation failed with 3 failed transactions, at most 0 allowed!\n"));
+	let (code, out, _) = run(&[
+		"validate",
+		&file,
+		"--dry-run",
+		"--max-invalid",
+		"1",
+		"--max-failed",
+		"3",
+	]);
+	assert_eq!(code, 0);
+	assert_eq!(out, report);
+	let (code, _, err) = run(&["validate", &file, "--max-failed", "3"]);
+	assert_eq!(code, 2);
+	assert!(
+		err.contains("Option `--max-failed` applies to the validate command only with `--dry-run`")
+	);
+}
+
+#[test]
+fn converted_input_processes_to_the_same_report() {
+	let dir = Scratch::new("convert");
+	let file = dir.input(INPUT);
+	for format in ["csv", "jsonl", "bin"] {
+		let converted = dir.file(format);
+		let converted = converted.to_str().unwrap();
+		let (code, _, err) = run(&[
+			"convert",
+			&file,
+			"--output-format",
+			format,
+			"--output",
+			converted,
+		]);
+		assert_eq!(code, 5, "{}", format);
+		assert!(
+			err.contains("Skipping transaction [\"bogus\"]"),
+			"{}",
+			format
+		);
+		let (code, out, _) = run(&[converted, "--input-format", format]);
+		assert_eq!(code, 5, "{}", format);
+		assert_eq!(out, REPORT, "{}", format);
+	}
+}
+
+#[test]
+fn converted_keys_parse_back() {
+	let dir = Scratch::new("convert_keys");
+	let file = dir.input(concat!(
+		r#"{"type":"deposit","client":1,"tx":1,"amount":"1.0","idempotency_key":"a;b"}"#,
+		"\n",
+		r#"{"type":"deposit","client":1,"tx":2,"amount":"1.0","idempotency_key":"a,b"}"#,
+		"\n"
+	));
+	let converted = dir.file("csv");
+	let converted = converted.to_str().unwrap();
+	let (code, _, err) = run(&[
+		"convert",
+		&file,
+		"--input-format",
+		"jsonl",
+		"--output",
+		converted,
+	]);
+	assert_eq!(code, 5);
+	assert!(err.contains("InvalidIdempotencyKey"), "{}", err);
+	let (code, out, err) = run(&[converted]);
+	assert_eq!(code, 0);
+	assert!(!err.contains("UnexpectedTrailingSection"), "{}", err);
+	assert_eq!(
+		out,
+		"client,available,held,total,locked\n1,1.0,0.0,1.0,false\n"
+	);
+}
+
+#[test]
+fn audit_prints_nothing_for_consistent_books() {
+	let dir = Scratch::new("audit");
+	let file = dir.input(INPUT);
+	let (code, out, _) = run(&["audit", &file]);
+	assert_eq!(code, 5);
+	assert!(out.is_empty());
+}
+
+#[test]
+fn replay_traces_every_transaction() {
+	let dir = Scratch::new("replay");
+	let file = dir.input(INPUT);
+	let (code, out, _) = run(&["replay", &file, "--until", "3", "--output-format", "jsonl"]);
+	assert_eq!(code, 5);
+	assert_eq!(
+		out,
+		"#1 [\"type, client, tx, amount\"]. Error [UnknownTransactionType]
+#2 [\"deposit, 1, 1, 10.0\"] applied
+  client 1: available 10.0, held 0.0, total 10.0, locked false
+#3 [\"withdrawal, 1, 2, 20.0\"]. Error [NoAvailableFunds]
+{\"client\":1,\"currency\":null,\"available\":\"10.0\",\"held\":\"0.0\",\"total\":\"10.0\",\"locked\":false}
+"
+	);
+}
+
+#[test]
+fn stdin_and_several_input_files_feed_the_same_books() {
+	let dir = Scratch::new("several");
+	let file = dir.input(INPUT);
+	let (code, out, err) = run_with_stdin(&[&file, "-"], "withdrawal, 2, 4, 1.5\n");
+	assert_eq!(code, 5);
+	assert_eq!(
+		out,
+		"client,available,held,total,locked\n1,10.0,0.0,10.0,false\n2,3.5,0.0,3.5,false\n"
+	);
+	assert!(err.contains(&format!("{}: 4 transactions, 2 applied, 2 failed", file)));
+	assert!(err.contains("stdin: 1 transactions, 1 applied, 0 failed"));
+	let (code, out, _) = run_with_stdin(&["validate", "-"], INPUT);
+	assert_eq!(code, 5);
+	assert_eq!(
+		out,
+		"3 valid, 1 invalid transactions\n  1 UnknownTransactionType\n"
+	);
+}
+
+#[test]
+fn open_failures_keep_their_cause_and_apply_nothing() {
+	let dir = Scratch::new("open");
+	let file = dir.input(INPUT);
+	let missing = dir.file("missing");
+	let missing = missing.to_str().unwrap();
+	let (code, out, err) = run(&[&file, missing]);
+	assert_eq!(code, 3);
+	assert!(out.is_empty());
+	assert_eq!(
+		err,
+		format!("Could not open input file `{}`: [NotFound]\n", missing)
+	);
+}
+
+#[test]
+fn the_summary_file_counts_the_run_whatever_its_outcome() {
+	let dir = Scratch::new("summary");
+	let file = dir.input(INPUT);
+	let summary = dir.file("summary");
+	let summary = summary.to_str().unwrap();
+	let (code, out, _) = run(&[&file, "--summary", summary]);
+	assert_eq!(code, 5);
+	assert_eq!(out, REPORT);
+	let json = std::fs::read_to_string(summary).unwrap();
+	assert!(json.starts_with(&format!(
+		"{{\"command\":\"process\",\"exit_code\":5,\"lines_read\":5,\"parsed\":3,\"applied\":2,\"rejected\":2,\"bytes_processed\":{},\"elapsed_ms\":",
+		INPUT.len()
+	)));
+	assert!(json
+		.ends_with(",\"rejected_by_kind\":{\"NoAvailableFunds\":1,\"UnknownTransactionType\":1}}\n"));
+	let (code, _, _) = run(&[
+		&file,
+		"--tolerance",
+		"8",
+		"--summary",
+		summary,
+		"--summary-format",
+		"kv",
+	]);
+	assert_eq!(code, 4);
+	let kv = std::fs::read_to_string(summary).unwrap();
+	assert!(
+		kv.starts_with("command=process\nexit_code=4\nlines_read=0\nparsed=0\napplied=0\nrejected=0\n")
+	);
+	let file = dir.input("deposit, 1, 1, 1.0\n");
+	let (code, _, _) = run(&[
+		"convert",
+		&file,
+		"--summary",
+		summary,
+		"--summary-format",
+		"kv",
+	]);
+	assert_eq!(code, 0);
+	let kv = std::fs::read_to_string(summary).unwrap();
+	assert!(kv.starts_with("command=convert\nexit_code=0\nlines_read=1\nparsed=1\napplied=1\nrejected=0\nbytes_processed=19\nelapsed_ms="));
+}
+
+#[test]
+fn repl_applies_typed_transactions_and_exports_the_session() {
+	let session = "\
+deposit, 1, 1, 10.0
+withdrawal, 1, 2, 40.0
+bogus
+withdrawal, 1, 3, 4.0
+dispute, 1, 3
+.tx 3
+.show 1
+.show 2
+.export disputed_withdrawal_holds_funds
+.quit
+deposit, 1, 4, 1.0
+";
+	let (code, out, err) = run_with_stdin(&["repl"], session);
+	assert_eq!((code, err.as_str()), (0, ""));
+	assert_eq!(
+		out,
+		r#"ok
+err NoAvailableFunds
+err UnknownTransactionType
+ok
+ok
+client=1 tx=3 state=UnderDispute held=4.0 fee=0.0
+client,available,held,total,locked
+1,6.0,4.0,10.0,false
+err ClientNotFound
+#[test]
+fn disputed_withdrawal_holds_funds() {
+	let mut proc: PaymentsProcessor = Default::default();
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("deposit, 1, 1, 10.0").unwrap());
+	assert!(res.is_ok());
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("withdrawal, 1, 2, 40.0").unwrap());
+	assert_eq!(res, Err(PTErr::NoAvailableFunds));
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("withdrawal, 1, 3, 4.0").unwrap());
+	assert!(res.is_ok());
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("dispute, 1, 3").unwrap());
+	assert!(res.is_ok());
+	let output = format!("{}", proc);
+	assert_eq!(output, "client,available,held,total,locked\n1,6.0,4.0,10.0,false\n");
+}
+"#
+	);
+}
+
+#[test]
+fn repl_snapshots_undo_and_reset_the_books() {
+	let session = "\
+deposit, 1, 1, 10.0
+.snapshot
+withdrawal, 1, 2, 4.0
+withdrawal, 1, 3, 1.0
+.restore
+.restore
+withdrawal, 1, 2, 2.0
+withdrawal, 1, 3, 20.0
+.undo
+.undo 5
+.report
+.reset
+.report
+.export
+";
+	let (code, out, _) = run_with_stdin(&["repl"], session);
+	assert_eq!(code, 0);
+	assert_eq!(
+		out,
+		"ok\nsnapshot 1\nok\nok\nok 2 undone\nerr NoSnapshot\nok\nerr NoAvailableFunds\nok\nerr NotEnoughHistory\n\
+client,available,held,total,locked\n1,10.0,0.0,10.0,false\nok\nclient,available,held,total,locked\n\
+#[test]\nfn repl_session() {\n\tlet mut proc: PaymentsProcessor = Default::default();\n\
+\tlet output = format!(\"{}\", proc);\n\tassert_eq!(output, \"client,available,held,total,locked\\n\");\n}\n"
+	);
+	let (code, out, _) = run_with_stdin(&["repl"], ".export 1st\n.export fn\n.export with-dash\n");
+	assert_eq!(code, 0);
+	assert_eq!(out, "err InvalidTestName\n".repeat(3));
+	let (code, out, _) = run_with_stdin(
+		&["repl", "--max-balance", "100.0"],
+		"deposit, 1, 1, 10.0\n.export\n",
+	);
+	assert_eq!(code, 0);
+	assert_eq!(out, "ok\nerr ExportUnsupported\n");
+	let (code, _, err) = run(&["repl", "--input-format", "jsonl"]);
+	assert_eq!(code, 2);
+	assert!(err.contains("Option `--input-format` does not apply to the repl command"));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn a_report_the_output_cannot_take_fails_with_an_io_status() {
+	let dir = Scratch::new("full");
+	//large enough for the report to outgrow the output buffer while it is written
+	let input: String = std::iter::once("type,client,tx,amount\n".to_owned())
+		.chain((1..=1000).map(|i| format!("deposit,{},{},1.0\n", i, i)))
+		.collect();
+	let file = dir.input(&input);
+	let (code, out, err) = run(&[&file, "--output", "/dev/full"]);
+	assert_eq!((code, out.as_str()), (3, ""));
+	assert!(err.contains("Could not write the output"), "{}", err);
+}
@@ -0,0 +1,109 @@
+// Fixtures shared by the tests running the binary, each test file using only some of them
+#![allow(dead_code)]
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+
+//the server runs until killed, even when the test fails
+pub struct Server(Child);
+
+impl Drop for Server {
+	fn drop(&mut self) {
+		let _ = self.0.kill();
+		let _ = self.0.wait();
+	}
+}
+
+//starts the **command** server on a free port, returning it along with its address (the scheme left out)
+pub fn serve(command: &str, args: &[&str]) -> (Server, String) {
+	let mut child = Command::new(env!("CARGO_BIN_EXE_mini-payments"))
+		.arg(command)
+		.args(["--port", "0"])
+		.args(args)
+		.stderr(Stdio::piped())
+		.spawn()
+		.unwrap();
+	let mut line = String::new();
+	BufReader::new(child.stderr.take().unwrap())
+		.read_line(&mut line)
+		.unwrap();
+	let addr = line.trim().strip_prefix("Listening on ").unwrap();
+	let addr = addr.strip_prefix("http://").unwrap_or(addr).to_owned();
+	(Server(child), addr)
+}
+
+//a scratch directory per test, the tests running in parallel, removed with everything in it once the test
+//ends (after the server it is declared before)
+pub struct Scratch(PathBuf);
+
+impl Scratch {
+	pub fn new(test: &str) -> Self {
+		let dir = std::env::temp_dir().join(format!("mini-payments-{}-{}", std::process::id(), test));
+		std::fs::create_dir_all(&dir).unwrap();
+		Scratch(dir)
+	}
+
+	pub fn file(&self, name: &str) -> PathBuf {
+		self.0.join(name)
+	}
+
+	pub fn input(&self, content: &str) -> String {
+		let path = self.file("input");
+		std::fs::write(&path, content).unwrap();
+		path.to_str().unwrap().to_owned()
+	}
+}
+
+impl Drop for Scratch {
+	fn drop(&mut self) {
+		let _ = std::fs::remove_dir_all(&self.0);
+	}
+}
+
+//a connection to a line based server, either the TCP one or the control interface
+pub struct Client<T: Read + Write> {
+	pub writer: T,
+	pub reader: BufReader<T>,
+}
+
+impl Client<TcpStream> {
+	pub fn connect(addr: &str) -> Self {
+		let writer = TcpStream::connect(addr).unwrap();
+		let reader = BufReader::new(writer.try_clone().unwrap());
+		Client { writer, reader }
+	}
+}
+
+impl<T: Read + Write> Client<T> {
+	//in a single write, the server may close the connection as soon as it has read the line
+	pub fn send(&mut self, line: &str) {
+		self
+			.writer
+			.write_all(format!("{}\n", line).as_bytes())
+			.unwrap();
+	}
+
+	pub fn reply(&mut self) -> String {
+		let mut line = String::new();
+		self.reader.read_line(&mut line).unwrap();
+		line
+	}
+
+	pub fn command(&mut self, line: &str) -> String {
+		self.send(line);
+		self.reply()
+	}
+
+	//the lines up to the empty line ending them (or the end of the connection)
+	pub fn block(&mut self) -> Vec<String> {
+		let mut lines = Vec::new();
+		loop {
+			match self.reply() {
+				line if line == "\n" || line.is_empty() => return lines,
+				line => lines.push(line),
+			}
+		}
+	}
+}
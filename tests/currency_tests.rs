@@ -0,0 +1,49 @@
+extern crate lib;
+
+use lib::{Currency, CurrencyError};
+use std::str::FromStr;
+
+#[test]
+fn three_uppercase_letters_is_ok() {
+	let res = Currency::from_str("USD");
+	assert!(res.is_ok());
+	assert_eq!(res.unwrap().as_str(), "USD");
+}
+
+#[test]
+fn lowercase_code_is_err() {
+	assert_eq!(
+		Currency::from_str("usd"),
+		Err(CurrencyError::InvalidCharacter)
+	);
+}
+
+#[test]
+fn numeric_code_is_err() {
+	assert_eq!(
+		Currency::from_str("978"),
+		Err(CurrencyError::InvalidCharacter)
+	);
+}
+
+#[test]
+fn short_or_long_code_is_err() {
+	assert_eq!(Currency::from_str("EU"), Err(CurrencyError::InvalidLength));
+	assert_eq!(
+		Currency::from_str("EURO"),
+		Err(CurrencyError::InvalidLength)
+	);
+	assert_eq!(Currency::from_str(""), Err(CurrencyError::InvalidLength));
+}
+
+#[test]
+fn multibyte_code_is_err() {
+	assert!(Currency::from_str("€").is_err());
+}
+
+#[test]
+fn codes_are_ordered_alphabetically() {
+	let eur = Currency::from_str("EUR").unwrap();
+	let usd = Currency::from_str("USD").unwrap();
+	assert!(eur < usd);
+}
@@ -0,0 +1,162 @@
+extern crate lib;
+
+use lib::{Fee, FeeKind, FeeReversal, FeeSchedule, FeeScheduleError, FixedDecimal, Rounding};
+use std::str::FromStr;
+
+fn dec(txt: &str) -> FixedDecimal {
+	FixedDecimal::from_str(txt).unwrap()
+}
+
+#[test]
+fn fee_is_flat_plus_percentage() {
+	let fee = Fee {
+		flat: dec("0.5"),
+		percent: dec("2.0"),
+		..Default::default()
+	};
+	assert_eq!(
+		fee.calculate(dec("10.0"), Rounding::HalfEven),
+		Some(dec("0.7"))
+	);
+}
+
+#[test]
+fn fee_is_bound_by_minimum_and_cap() {
+	let fee = Fee {
+		flat: dec("0.0"),
+		percent: dec("1.0"),
+		min: dec("1.0"),
+		max: Some(dec("5.0")),
+	};
+	assert_eq!(
+		fee.calculate(dec("10.0"), Rounding::HalfEven),
+		Some(dec("1.0"))
+	);
+	assert_eq!(
+		fee.calculate(dec("300.0"), Rounding::HalfEven),
+		Some(dec("3.0"))
+	);
+	assert_eq!(
+		fee.calculate(dec("1000.0"), Rounding::HalfEven),
+		Some(dec("5.0"))
+	);
+}
+
+#[test]
+fn fee_percentage_is_rounded_per_policy() {
+	let fee = Fee {
+		percent: dec("1.0"),
+		..Default::default()
+	};
+	//exact fee is 0.00015
+	assert_eq!(
+		fee.calculate(dec("0.015"), Rounding::Down),
+		Some(dec("0.0001"))
+	);
+	assert_eq!(
+		fee.calculate(dec("0.015"), Rounding::HalfEven),
+		Some(dec("0.0002"))
+	);
+}
+
+#[test]
+fn fee_depends_on_client_tier() {
+	let schedule = FeeSchedule::default()
+		.with_fee(
+			FeeKind::Deposit,
+			0,
+			Fee {
+				flat: dec("1.0"),
+				..Default::default()
+			},
+		)
+		.with_fee(
+			FeeKind::Deposit,
+			1,
+			Fee {
+				flat: dec("0.5"),
+				..Default::default()
+			},
+		)
+		.with_client_tier(7, 1)
+		.with_client_tier(8, 2);
+	assert_eq!(schedule.client_tier(1), 0);
+	assert_eq!(
+		schedule.fee_for(FeeKind::Deposit, 1, dec("10.0")),
+		Some(dec("1.0"))
+	);
+	assert_eq!(
+		schedule.fee_for(FeeKind::Deposit, 7, dec("10.0")),
+		Some(dec("0.5"))
+	);
+	assert_eq!(
+		schedule.fee_for(FeeKind::Deposit, 8, dec("10.0")),
+		Some(dec("0.0"))
+	);
+	assert_eq!(
+		schedule.fee_for(FeeKind::Withdrawal, 1, dec("10.0")),
+		Some(dec("0.0"))
+	);
+}
+
+#[test]
+fn schedule_file_is_parsed() {
+	let schedule = FeeSchedule::from_reader(
+		"# kind,tier,flat,percent,min,max
+		fee, deposit, 0, 0.0, 1.0, 0.0, 2.0
+		fee, withdrawal, 1, 0.25, 0.0, 0.0
+		tier, 42, 1
+		rounding, down
+		reversal, chargeback"
+			.as_bytes(),
+	)
+	.unwrap();
+	assert_eq!(schedule.reversal(), FeeReversal::OnChargeback);
+	assert_eq!(schedule.client_tier(42), 1);
+	assert_eq!(
+		schedule.fee_for(FeeKind::Deposit, 1, dec("150.5")),
+		Some(dec("1.505"))
+	);
+	assert_eq!(
+		schedule.fee_for(FeeKind::Deposit, 1, dec("1000.0")),
+		Some(dec("2.0"))
+	);
+	assert_eq!(
+		schedule.fee_for(FeeKind::Withdrawal, 42, dec("5.0")),
+		Some(dec("0.25"))
+	);
+	assert_eq!(
+		schedule.fee_for(FeeKind::Withdrawal, 1, dec("5.0")),
+		Some(dec("0.0"))
+	);
+}
+
+#[test]
+fn invalid_schedule_lines_are_err() {
+	let res = FeeSchedule::from_reader("fee, transfer, 0, 0.0, 1.0, 0.0".as_bytes());
+	assert_eq!(res.unwrap_err(), FeeScheduleError::InvalidSection(1));
+	let res = FeeSchedule::from_reader("\nfee, deposit, 0, 0.0, 1.0".as_bytes());
+	assert_eq!(res.unwrap_err(), FeeScheduleError::MalformedLine(2));
+	let res = FeeSchedule::from_reader("tier, 70000, 1".as_bytes());
+	assert_eq!(res.unwrap_err(), FeeScheduleError::InvalidSection(1));
+	let res = FeeSchedule::from_reader("discount, 1, 1".as_bytes());
+	assert_eq!(res.unwrap_err(), FeeScheduleError::UnknownDirective(1));
+	let res = FeeSchedule::from_reader("reversal, sometimes".as_bytes());
+	assert_eq!(res.unwrap_err(), FeeScheduleError::InvalidSection(1));
+}
+
+#[test]
+fn minimum_above_cap_is_err() {
+	let res = FeeSchedule::from_reader("fee, deposit, 0, 0.0, 1.0, 3.0, 2.0".as_bytes());
+	assert_eq!(res.unwrap_err(), FeeScheduleError::MinimumExceedsCap(1));
+}
+
+#[test]
+fn duplicate_schedule_entries_are_err() {
+	let res = FeeSchedule::from_reader("tier, 1, 1\ntier, 1, 2".as_bytes());
+	assert_eq!(res.unwrap_err(), FeeScheduleError::DuplicateEntry(2));
+	let res = FeeSchedule::from_reader(
+		"fee, deposit, 0, 1.0, 0.0, 0.0\nfee, deposit, 0, 2.0, 0.0, 0.0".as_bytes(),
+	);
+	assert_eq!(res.unwrap_err(), FeeScheduleError::DuplicateEntry(2));
+}
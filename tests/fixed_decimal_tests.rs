@@ -2,7 +2,7 @@ extern crate lib;
 
 use lib::FixedDecimalMAX;
 use lib::FixedDecimalMAXDISPLEN;
-use lib::{FixedDecimal, FixedDecimalError};
+use lib::{FixedDecimal, FixedDecimalError, Rounding};
 use std::str::FromStr;
 
 #[test]
@@ -223,3 +223,81 @@ fn nonzero_whole_with_2rd_decimal_nonzero_only_is_ok_invariants_hold() {
 	assert_eq!(res.get_whole_part(), 321);
 	assert_eq!(res.to_string(), "321.01");
 }
+
+#[test]
+fn exact_multiplication_has_no_remainder() {
+	let res1 = FixedDecimal::from_str("12.5").unwrap();
+	let res2 = FixedDecimal::from_str("2.0").unwrap();
+	let res = res1.checked_mul(res2, Rounding::Up);
+	assert_eq!(res, Some((FixedDecimal::from_str("25.0").unwrap(), 0)));
+}
+
+#[test]
+fn multiplication_rounding_policies_work_as_expected() {
+	let res1 = FixedDecimal::from_str("1.0001").unwrap();
+	let res2 = FixedDecimal::from_str("0.5").unwrap();
+	//exact product is 0.50005
+	let expected = [
+		(Rounding::Down, "0.5", 5000),
+		(Rounding::Up, "0.5001", -5000),
+		(Rounding::HalfUp, "0.5001", -5000),
+		(Rounding::HalfEven, "0.5", 5000),
+	];
+	for (rounding, res, remainder) in expected {
+		let (prod, rem) = res1.checked_mul(res2, rounding).unwrap();
+		assert_eq!(prod.to_string(), res, "{:?}", rounding);
+		assert_eq!(rem, remainder, "{:?}", rounding);
+	}
+}
+
+#[test]
+fn half_even_rounds_ties_to_even_neighbour() {
+	let res1 = FixedDecimal::from_str("1.0003").unwrap();
+	let res2 = FixedDecimal::from_str("0.5").unwrap();
+	//exact product is 0.50015
+	let (prod, rem) = res1.checked_mul(res2, Rounding::HalfEven).unwrap();
+	assert_eq!(prod.to_string(), "0.5002");
+	assert_eq!(rem, -5000);
+}
+
+#[test]
+fn half_rounding_below_and_above_the_tie_works_as_expected() {
+	let res = FixedDecimal::from_str("0.0001").unwrap();
+	let below = FixedDecimal::from_str("0.4999").unwrap();
+	let above = FixedDecimal::from_str("0.5001").unwrap();
+	for rounding in [Rounding::HalfUp, Rounding::HalfEven] {
+		let (prod, rem) = res.checked_mul(below, rounding).unwrap();
+		assert_eq!((prod.to_string().as_str(), rem), ("0.0", 4999));
+		let (prod, rem) = res.checked_mul(above, rounding).unwrap();
+		assert_eq!((prod.to_string().as_str(), rem), ("0.0001", -4999));
+	}
+}
+
+#[test]
+fn overflowing_multiplication_is_err() {
+	let res1 = FixedDecimalMAX;
+	let res2 = FixedDecimal::from_str("1.0001").unwrap();
+	assert!(res1.checked_mul(res2, Rounding::Down).is_none());
+}
+
+#[test]
+fn max_times_one_is_ok() {
+	let res1 = FixedDecimalMAX;
+	let res2 = FixedDecimal::from_str("1.0").unwrap();
+	assert_eq!(
+		res1.checked_mul(res2, Rounding::Up),
+		Some((FixedDecimalMAX, 0))
+	);
+}
+
+#[test]
+fn rounding_from_str_works_as_expected() {
+	assert_eq!(Rounding::from_str("half-even"), Ok(Rounding::HalfEven));
+	assert_eq!(Rounding::from_str("half-up"), Ok(Rounding::HalfUp));
+	assert_eq!(Rounding::from_str("up"), Ok(Rounding::Up));
+	assert_eq!(Rounding::from_str("down"), Ok(Rounding::Down));
+	assert_eq!(
+		Rounding::from_str("nearest"),
+		Err(FixedDecimalError::InvalidFormat)
+	);
+}
@@ -0,0 +1,344 @@
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+
+//a persistent connection
+struct Client {
+	writer: TcpStream,
+	reader: BufReader<TcpStream>,
+}
+
+//status, headers (names lowercased) and body
+struct Reply {
+	status: u16,
+	headers: Vec<(String, String)>,
+	body: String,
+}
+
+impl Reply {
+	fn header(&self, name: &str) -> Option<&str> {
+		self
+			.headers
+			.iter()
+			.find(|(x, _)| x == name)
+			.map(|(_, value)| value.as_str())
+	}
+}
+
+impl Client {
+	fn connect(addr: &str) -> Self {
+		let writer = TcpStream::connect(addr).unwrap();
+		let reader = BufReader::new(writer.try_clone().unwrap());
+		Client { writer, reader }
+	}
+
+	fn send(&mut self, raw: &str) {
+		self.writer.write_all(raw.as_bytes()).unwrap();
+	}
+
+	fn get(&mut self, path: &str, accept: &str) -> Reply {
+		self.send(&format!(
+			"GET {} HTTP/1.1\r\nHost: localhost\r\nAccept: {}\r\n\r\n",
+			path, accept
+		));
+		self.reply()
+	}
+
+	fn post(&mut self, path: &str, content_type: &str, body: &str) -> Reply {
+		self.send(&format!(
+			"POST {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
+			path,
+			content_type,
+			body.len(),
+			body
+		));
+		self.reply()
+	}
+
+	fn reply(&mut self) -> Reply {
+		let mut line = String::new();
+		self.reader.read_line(&mut line).unwrap();
+		let status = line.split(' ').nth(1).unwrap().parse().unwrap();
+		let mut headers = Vec::new();
+		loop {
+			let mut line = String::new();
+			self.reader.read_line(&mut line).unwrap();
+			match line.trim_end().split_once(": ") {
+				Some((name, value)) => headers.push((name.to_ascii_lowercase(), value.to_owned())),
+				None => break,
+			}
+		}
+		let mut reply = Reply {
+			status,
+			headers,
+			body: String::new(),
+		};
+		let length = reply.header("content-length").unwrap().parse().unwrap();
+		let mut body = vec![0; length];
+		self.reader.read_exact(&mut body).unwrap();
+		reply.body = String::from_utf8(body).unwrap();
+		reply
+	}
+
+	//true once the server closed the connection
+	fn closed(&mut self) -> bool {
+		let mut rest = String::new();
+		self.reader.read_to_string(&mut rest).unwrap() == 0
+	}
+}
+
+#[test]
+fn single_transactions_are_acknowledged_or_rejected_with_their_error() {
+	let (_server, addr) = common::serve("http", &[]);
+	let mut client = Client::connect(&addr);
+	for (content_type, body, status, reply) in [
+		("text/csv", "deposit, 1, 1, 10.0", 200, r#"{"status":"ok"}"#),
+		(
+			"application/json",
+			r#"{"type":"withdrawal","client":1,"tx":2,"amount":"20.0"}"#,
+			422,
+			r#"{"status":"error","error":"NoAvailableFunds"}"#,
+		),
+		(
+			"text/csv; charset=utf-8",
+			"bogus\n",
+			422,
+			r#"{"status":"error","error":"UnknownTransactionType"}"#,
+		),
+		(
+			"application/json",
+			r#"{"type":"withdrawal","client":1,"tx":3,"amount":"2.5"}"#,
+			200,
+			r#"{"status":"ok"}"#,
+		),
+	] {
+		let res = client.post("/transactions", content_type, body);
+		assert_eq!(
+			(res.status, res.body.trim_end()),
+			(status, reply),
+			"{}",
+			body
+		);
+		assert_eq!(res.header("content-type"), Some("application/json"));
+	}
+	let res = client.post(
+		"/transactions",
+		"text/csv",
+		"deposit, 1, 4, 1.0\ndeposit, 1, 5, 1.0",
+	);
+	assert_eq!(res.status, 400);
+	let res = client.post("/transactions", "application/xml", "<deposit/>");
+	assert_eq!(res.status, 415);
+	let res = client.get("/report", "text/csv");
+	assert_eq!(res.status, 200);
+	assert_eq!(res.header("content-type"), Some("text/csv"));
+	assert_eq!(
+		res.body,
+		"client,available,held,total,locked\n1,7.5,0.0,7.5,false\n"
+	);
+}
+
+#[test]
+fn batches_apply_every_transaction_on_its_own() {
+	let (_server, addr) = common::serve("http", &[]);
+	let mut client = Client::connect(&addr);
+	let csv = "type,client,tx,amount\ndeposit, 1, 1, 10.0\r\nwithdrawal, 1, 2, 20.0\n\nbogus\n";
+	let res = client.post("/transactions/batch", "text/csv", csv);
+	assert_eq!(res.status, 200);
+	assert_eq!(
+		res.body,
+		concat!(
+			r#"{"applied":1,"failed":2,"results":[{"status":"ok"},"#,
+			r#"{"status":"error","error":"NoAvailableFunds"},"#,
+			r#"{"status":"error","error":"UnknownTransactionType"}]}"#,
+			"\n"
+		)
+	);
+	let json = r#"[{"type":"deposit","client":2,"tx":3,"amount":"5.0"},{"type":"deposit"}]"#;
+	let res = client.post("/transactions/batch", "application/json", json);
+	assert_eq!(res.status, 200);
+	assert_eq!(
+		res.body,
+		concat!(
+			r#"{"applied":1,"failed":1,"results":[{"status":"ok"},"#,
+			r#"{"status":"error","error":"MissingTransactionAmount"}]}"#,
+			"\n"
+		)
+	);
+	let res = client.post("/transactions/batch", "application/json", "[{}");
+	assert_eq!(
+		(res.status, res.body.as_str()),
+		(400, "{\"error\":\"MalformedJson\"}\n")
+	);
+	let res = client.get("/report", "application/json");
+	assert_eq!(
+		res.body,
+		concat!(
+			"[\n",
+			r#"{"client":1,"currency":null,"available":"10.0","held":"0.0","total":"10.0","locked":false},"#,
+			"\n",
+			r#"{"client":2,"currency":null,"available":"5.0","held":"0.0","total":"5.0","locked":false}"#,
+			"\n]\n"
+		)
+	);
+}
+
+#[test]
+fn balances_and_dispute_states_are_queried_by_id() {
+	let (_server, addr) = common::serve("http", &[]);
+	let mut client = Client::connect(&addr);
+	let csv = "deposit, 1, 1, 10.0\nwithdrawal, 1, 2, 4.0\ndispute, 1, 2\n";
+	let res = client.post("/transactions/batch", "text/csv", csv);
+	assert!(res.body.starts_with("{\"applied\":3,"), "{}", res.body);
+	let res = client.get("/clients/1", "*/*");
+	assert_eq!(res.status, 200);
+	assert_eq!(
+		res.body,
+		concat!(
+			"[\n",
+			r#"{"client":1,"currency":null,"available":"6.0","held":"4.0","total":"10.0","locked":false}"#,
+			"\n]\n"
+		)
+	);
+	for (path, status, body) in [
+		(
+			"/transactions/1/2",
+			200,
+			r#"{"client":1,"tx":2,"state":"UnderDispute","held":"4.0"}"#,
+		),
+		(
+			"/transactions/1/1",
+			200,
+			r#"{"client":1,"tx":1,"state":"Executed"}"#,
+		),
+		(
+			"/transactions/1/3",
+			404,
+			r#"{"error":"TransactionNotFound"}"#,
+		),
+		("/clients/2", 404, r#"{"error":"ClientNotFound"}"#),
+		("/clients/x", 404, r#"{"error":"NotFound"}"#),
+		("/nowhere", 404, r#"{"error":"NotFound"}"#),
+	] {
+		let res = client.get(path, "*/*");
+		assert_eq!(
+			(res.status, res.body.trim_end()),
+			(status, body),
+			"{}",
+			path
+		);
+	}
+	let res = client.post("/report", "text/csv", "");
+	assert_eq!(res.status, 405);
+	let res = client.get("/transactions", "*/*");
+	assert_eq!(res.status, 405);
+}
+
+#[test]
+fn oversized_requests_are_rejected_and_close_the_connection() {
+	let (_server, addr) = common::serve(
+		"http",
+		&[
+			"--max-header",
+			"64",
+			"--max-body",
+			"32",
+			"--tolerance",
+			"24",
+		],
+	);
+	let mut client = Client::connect(&addr);
+	client.send(&format!("GET /{} HTTP/1.1\r\n\r\n", "x".repeat(64)));
+	assert_eq!(client.reply().status, 431);
+	assert!(client.closed());
+	let mut client = Client::connect(&addr);
+	let res = client.post(
+		"/transactions/batch",
+		"text/csv",
+		&"deposit, 1, 1, 1.0\n".repeat(2),
+	);
+	assert_eq!(res.status, 413);
+	assert_eq!(res.header("connection"), Some("close"));
+	assert!(client.closed());
+	//a body line beyond the tolerance only rejects its request
+	let mut client = Client::connect(&addr);
+	let res = client.post("/transactions", "text/csv", "deposit, 1, 1, 1.0          ");
+	assert_eq!(
+		(res.status, res.body.as_str()),
+		(413, "{\"error\":\"ToleranceExceeded\"}\n")
+	);
+	let res = client.post("/transactions", "text/csv", "deposit, 1, 1, 1.0");
+	assert_eq!(res.status, 200);
+	client.send("POST /transactions HTTP/1.1\r\nContent-Type: text/csv\r\n\r\n");
+	assert_eq!(client.reply().status, 411);
+	assert!(client.closed());
+}
+
+#[test]
+fn connections_share_the_same_books_and_may_close_after_a_request() {
+	let (_server, addr) = common::serve("http", &[]);
+	let mut first = Client::connect(&addr);
+	let res = first.post("/transactions", "text/csv", "deposit, 1, 1, 10.0");
+	assert_eq!(res.header("connection"), Some("keep-alive"));
+	let mut second = Client::connect(&addr);
+	second.send("GET /clients/1 HTTP/1.1\r\nConnection: close\r\n\r\n");
+	let res = second.reply();
+	assert_eq!(res.header("connection"), Some("close"));
+	assert!(res.body.contains("\"available\":\"10.0\""), "{}", res.body);
+	assert!(second.closed());
+	let mut old = Client::connect(&addr);
+	old.send("GET /report HTTP/1.0\r\n\r\n");
+	assert_eq!(old.reply().status, 200);
+	assert!(old.closed());
+}
+
+#[test]
+fn changes_are_listed_since_the_report_a_consumer_got_last() {
+	let (_server, addr) = common::serve("http", &[]);
+	let mut client = Client::connect(&addr);
+	client.post(
+		"/transactions/batch",
+		"text/csv",
+		"deposit, 1, 1, 10.0\ndeposit, 2, 2, 1.0\n",
+	);
+	//peeking leaves the cursor where it was
+	for _ in 0..2 {
+		let res = client.get("/changes/0", "*/*");
+		assert_eq!(res.status, 200);
+		assert!(res
+			.body
+			.starts_with(r#"{"report":0,"since":0,"changes":[{"client":1,"before":[],"#));
+	}
+	let res = client.post("/changes/0", "text/plain", "");
+	assert_eq!(res.status, 200);
+	assert!(res
+		.body
+		.starts_with(r#"{"report":1,"since":0,"changes":[{"client":1,"before":[],"#));
+	client.post("/transactions", "text/csv", "withdrawal, 2, 3, 0.5");
+	let res = client.post("/changes/1", "text/plain", "");
+	assert_eq!(
+		res.body,
+		concat!(
+			r#"{"report":2,"since":1,"changes":[{"client":2,"before":["#,
+			r#"{"client":2,"currency":null,"available":"1.0","held":"0.0","total":"1.0","locked":false}],"after":["#,
+			r#"{"client":2,"currency":null,"available":"0.5","held":"0.0","total":"0.5","locked":false}]}]}"#,
+			"\n"
+		)
+	);
+	for (path, status, body) in [
+		("/changes/3", 404, r#"{"error":"UnknownReport"}"#),
+		("/changes/x", 404, r#"{"error":"NotFound"}"#),
+	] {
+		let res = client.get(path, "*/*");
+		assert_eq!(
+			(res.status, res.body.trim_end()),
+			(status, body),
+			"{}",
+			path
+		);
+	}
+	let res = client.post("/report", "text/plain", "");
+	assert_eq!(res.status, 405);
+	assert_eq!(client.get("/changes/2", "*/*").status, 200);
+}
@@ -0,0 +1,150 @@
+extern crate lib;
+
+use lib::{FeeSchedule, FixedDecimal, PaymentsProcessor, PaymentsTransaction, RateTable};
+use std::str::FromStr;
+
+//xorshift64*, good enough to generate reproducible transaction streams without any dependency
+struct Prng(u64);
+
+impl Prng {
+	fn next(&mut self) -> u64 {
+		self.0 ^= self.0 >> 12;
+		self.0 ^= self.0 << 25;
+		self.0 ^= self.0 >> 27;
+		self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+	}
+
+	fn below(&mut self, n: u64) -> u64 {
+		self.next() % n
+	}
+}
+
+//amounts are mostly picked near the edges of FixedDecimal's range (u64::MAX units of 0.0001)
+fn amount(rng: &mut Prng) -> String {
+	let units = match rng.below(5) {
+		0 => u64::MAX - rng.below(1000),
+		1 => u64::MAX / 2 - 500 + rng.below(1000),
+		2 => rng.next(),
+		3 => rng.below(1_000_000),
+		_ => u64::MAX,
+	};
+	format!("{}.{:04}", units / 10000, units % 10000)
+}
+
+fn currency(rng: &mut Prng) -> &'static str {
+	["", "EUR", "USD"][rng.below(3) as usize]
+}
+
+fn transaction(rng: &mut Prng) -> String {
+	let (client, tx) = (rng.below(4), rng.below(64));
+	let optional_amount = |rng: &mut Prng| match rng.below(2) {
+		0 => amount(rng),
+		_ => String::new(),
+	};
+	match rng.below(9) {
+		0 | 1 => format!(
+			"deposit, {}, {}, {}, {}",
+			client,
+			tx,
+			amount(rng),
+			currency(rng)
+		),
+		2 => format!(
+			"withdrawal, {}, {}, {}, {}",
+			client,
+			tx,
+			amount(rng),
+			currency(rng)
+		),
+		3 => format!("dispute, {}, {}, {}", client, tx, optional_amount(rng)),
+		4 => format!("resolve, {}, {}, {}", client, tx, optional_amount(rng)),
+		5 => format!("chargeback, {}, {}, {}", client, tx, optional_amount(rng)),
+		6 => format!("representment, {}, {}", client, tx),
+		7 => format!("prearbitration, {}, {}", client, tx),
+		_ => {
+			let (from, to) = [("EUR", "USD"), ("USD", "EUR"), ("EUR", "GBP")][rng.below(3) as usize];
+			format!(
+				"convert, {}, {}, {}, {}, {}",
+				client,
+				tx,
+				amount(rng),
+				from,
+				to
+			)
+		}
+	}
+}
+
+fn fuzzed_processor(seed: u64) -> PaymentsProcessor {
+	let fees = FeeSchedule::from_reader(
+		"fee, deposit, 0, 0.0001, 0.5, 0.0\nfee, withdrawal, 0, 1.0, 99.9999, 0.0\ntier, 3, 1\nreversal, always"
+			.as_bytes(),
+	)
+	.unwrap();
+	let rates = RateTable::from_reader("EUR,USD,1000.0\nUSD,EUR,0.0001".as_bytes()).unwrap();
+	let proc = PaymentsProcessor::default()
+		.with_fees(fees)
+		.with_rates(rates);
+	match seed % 2 {
+		0 => proc,
+		_ => proc.with_max_balance(FixedDecimal::from_str("922337203685477.5807").unwrap()),
+	}
+}
+
+#[test]
+fn no_transaction_stream_near_u64_max_panics_or_unbalances_the_books() {
+	for seed in 1..=8u64 {
+		let mut rng = Prng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+		let mut proc = fuzzed_processor(seed);
+		for i in 0..2_000 {
+			let tx = transaction(&mut rng);
+			let _ = proc.process_transaction(PaymentsTransaction::from_str(&tx).unwrap());
+			let context = format!("seed {} transaction {} [{}]", seed, i, tx);
+			assert!(proc.is_balanced(), "{}", context);
+			assert_eq!(proc.fault(), None, "{}", context);
+			if i % 100 == 0 {
+				assert_eq!(proc.verify(), vec![], "{}", context);
+			}
+		}
+		assert_eq!(proc.verify(), vec![], "seed {}", seed);
+		assert!(!format!("{}", proc).is_empty());
+	}
+}
+
+#[test]
+fn applying_then_undoing_any_transaction_stream_leaves_the_state_unchanged() {
+	for seed in 1..=8u64 {
+		let mut rng = Prng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+		let mut proc = fuzzed_processor(seed);
+		for _ in 0..300 {
+			let _ =
+				proc.process_transaction(PaymentsTransaction::from_str(&transaction(&mut rng)).unwrap());
+		}
+		let (before, postings) = (format!("{}", proc), proc.postings());
+		let tail: Vec<_> = (0..200).map(|_| transaction(&mut rng)).collect();
+		proc.savepoint("tail").unwrap();
+		let applied: Vec<_> = tail
+			.iter()
+			.map(|tx| proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap()))
+			.collect();
+		let after = format!("{}", proc);
+		let undone = proc.undo_to_savepoint("tail").unwrap();
+		assert_eq!(
+			undone,
+			applied.iter().filter(|x| x.is_ok()).count(),
+			"seed {}",
+			seed
+		);
+		assert_eq!(format!("{}", proc), before, "seed {}", seed);
+		assert_eq!(proc.postings(), postings, "seed {}", seed);
+		assert_eq!(proc.verify(), vec![], "seed {}", seed);
+		//the transaction records and their dispute states are restored too,
+		//so the same tail is processed exactly the same way again
+		let reapplied: Vec<_> = tail
+			.iter()
+			.map(|tx| proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap()))
+			.collect();
+		assert_eq!(reapplied, applied, "seed {}", seed);
+		assert_eq!(format!("{}", proc), after, "seed {}", seed);
+	}
+}
@@ -1,6 +1,9 @@
 extern crate lib;
 
-use lib::{PaymentsProcessor, PaymentsTransaction, ProcessTransactionError as PTErr};
+use lib::{
+	BatchError, DeltaError, FeeReversal, FeeSchedule, FixedDecimal, PaymentsProcessor,
+	PaymentsTransaction, ProcessTransactionError as PTErr, RateTable, Rounding, TransactionState,
+};
 use std::str::FromStr;
 
 #[test]
@@ -249,6 +252,87 @@ fn withdrawal_on_locked_account_is_err_and_not_processed() {
 	assert_eq!(output_after, output_before);
 }
 
+#[test]
+fn operator_lock_freezes_account_until_unlocked() {
+	let mut proc: PaymentsProcessor = Default::default();
+	assert_eq!(proc.lock_client(321), Err(PTErr::ClientNotFound));
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("deposit, 321, 1, 150.0").unwrap());
+	assert!(res.is_ok());
+	assert_eq!(proc.lock_client(321), Ok(()));
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().nth(1), Some("321,150.0,0.0,150.0,true"));
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("withdrawal, 321, 2, 10.0").unwrap());
+	assert_eq!(res, Err(PTErr::AccountFrozen));
+	//an operator lock is no chargeback lock, the books stay consistent
+	assert_eq!(proc.verify(), vec![]);
+	assert_eq!(proc.unlock_client(321), Ok(()));
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("withdrawal, 321, 2, 10.0").unwrap());
+	assert!(res.is_ok());
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().nth(1), Some("321,140.0,0.0,140.0,false"));
+}
+
+#[test]
+fn unlock_lifts_a_chargeback_lock() {
+	let mut proc: PaymentsProcessor = Default::default();
+	for tx in [
+		"deposit, 321, 1, 150.0",
+		"withdrawal, 321, 2, 50.0",
+		"dispute, 321, 2",
+		"chargeback, 321, 2",
+	] {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert!(res.is_ok());
+	}
+	assert_eq!(proc.unlock_client(321), Ok(()));
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("withdrawal, 321, 3, 10.0").unwrap());
+	assert!(res.is_ok());
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().nth(1), Some("321,90.0,0.0,90.0,false"));
+	assert_eq!(proc.verify(), vec![]);
+}
+
+#[test]
+fn undoing_transactions_keeps_an_operator_unlock() {
+	let mut proc: PaymentsProcessor = Default::default();
+	for tx in [
+		"deposit, 321, 1, 150.0",
+		"withdrawal, 321, 2, 50.0",
+		"dispute, 321, 2",
+		"chargeback, 321, 2",
+	] {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert!(res.is_ok());
+	}
+	assert_eq!(proc.savepoint("before"), Ok(()));
+	assert_eq!(proc.begin(), Ok(()));
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("deposit, 321, 3, 5.0").unwrap());
+	assert!(res.is_ok());
+	assert_eq!(proc.unlock_client(321), Ok(()));
+	assert_eq!(proc.rollback(), Ok(()));
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().nth(1), Some("321,100.0,0.0,100.0,false"));
+	//a chargeback after the unlock locks the client again until it is undone
+	for tx in [
+		"withdrawal, 321, 4, 10.0",
+		"dispute, 321, 4",
+		"chargeback, 321, 4",
+	] {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert!(res.is_ok());
+	}
+	assert!(format!("{}", proc).ends_with(",true\n"));
+	assert_eq!(proc.undo_to_savepoint("before"), Ok(3));
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().nth(1), Some("321,100.0,0.0,100.0,false"));
+	assert_eq!(proc.verify(), vec![]);
+}
+
 #[test]
 fn dispute_on_invalid_transaction_id_is_err() {
 	let mut proc: PaymentsProcessor = Default::default();
@@ -296,3 +380,1509 @@ fn chargeback_on_invalid_transaction_id_is_err() {
 	assert_eq!(output.lines().count(), 2);
 	assert_eq!(output.lines().nth(1), Some("321,100.0,0.0,100.0,false"));
 }
+
+#[test]
+fn partial_dispute_holds_only_the_disputed_amount() {
+	let mut proc: PaymentsProcessor = Default::default();
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("deposit, 321, 1, 150.0").unwrap());
+	assert!(res.is_ok());
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("withdrawal, 321, 2, 50.0").unwrap());
+	assert!(res.is_ok());
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("dispute, 321, 2, 20.0").unwrap());
+	assert!(res.is_ok());
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().nth(1), Some("321,100.0,20.0,120.0,false"));
+}
+
+#[test]
+fn concurrent_partial_disputes_accumulate_held() {
+	let mut proc: PaymentsProcessor = Default::default();
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("deposit, 321, 1, 150.0").unwrap());
+	assert!(res.is_ok());
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("withdrawal, 321, 2, 50.0").unwrap());
+	assert!(res.is_ok());
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("dispute, 321, 2, 20.0").unwrap());
+	assert!(res.is_ok());
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("dispute, 321, 2, 30.0").unwrap());
+	assert!(res.is_ok());
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().nth(1), Some("321,100.0,50.0,150.0,false"));
+}
+
+#[test]
+fn partial_disputes_exceeding_transaction_amount_is_err_and_not_processed() {
+	let mut proc: PaymentsProcessor = Default::default();
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("deposit, 321, 1, 150.0").unwrap());
+	assert!(res.is_ok());
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("withdrawal, 321, 2, 50.0").unwrap());
+	assert!(res.is_ok());
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("dispute, 321, 2, 40.0").unwrap());
+	assert!(res.is_ok());
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("dispute, 321, 2, 10.0001").unwrap());
+	assert_eq!(res, Err(PTErr::DisputedAmountExceedsTransaction));
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().nth(1), Some("321,100.0,40.0,140.0,false"));
+}
+
+#[test]
+fn full_dispute_after_partial_dispute_holds_the_remainder() {
+	let mut proc: PaymentsProcessor = Default::default();
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("deposit, 321, 1, 150.0").unwrap());
+	assert!(res.is_ok());
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("withdrawal, 321, 2, 50.0").unwrap());
+	assert!(res.is_ok());
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("dispute, 321, 2, 15.0").unwrap());
+	assert!(res.is_ok());
+	let res = proc.process_transaction(PaymentsTransaction::from_str("dispute, 321, 2").unwrap());
+	assert!(res.is_ok());
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().nth(1), Some("321,100.0,50.0,150.0,false"));
+	let res = proc.process_transaction(PaymentsTransaction::from_str("dispute, 321, 2").unwrap());
+	assert_eq!(res, Err(PTErr::InvalidDisputeAmount));
+}
+
+#[test]
+fn zero_amount_dispute_is_err() {
+	let mut proc: PaymentsProcessor = Default::default();
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("deposit, 321, 1, 150.0").unwrap());
+	assert!(res.is_ok());
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("withdrawal, 321, 2, 50.0").unwrap());
+	assert!(res.is_ok());
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("dispute, 321, 2, 0.0").unwrap());
+	assert_eq!(res, Err(PTErr::InvalidDisputeAmount));
+}
+
+#[test]
+fn partial_resolve_releases_part_of_held_and_keeps_dispute_open() {
+	let mut proc: PaymentsProcessor = Default::default();
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("deposit, 321, 1, 150.0").unwrap());
+	assert!(res.is_ok());
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("withdrawal, 321, 2, 50.0").unwrap());
+	assert!(res.is_ok());
+	let res = proc.process_transaction(PaymentsTransaction::from_str("dispute, 321, 2").unwrap());
+	assert!(res.is_ok());
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("resolve, 321, 2, 20.0").unwrap());
+	assert!(res.is_ok());
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().nth(1), Some("321,120.0,30.0,150.0,false"));
+	let res = proc.process_transaction(PaymentsTransaction::from_str("resolve, 321, 2").unwrap());
+	assert!(res.is_ok());
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().nth(1), Some("321,150.0,0.0,150.0,false"));
+	let res = proc.process_transaction(PaymentsTransaction::from_str("resolve, 321, 2").unwrap());
+	assert_eq!(res, Err(PTErr::UndisputedTransactionCannotBeResolved));
+}
+
+#[test]
+fn resolve_exceeding_held_portion_is_err_and_not_processed() {
+	let mut proc: PaymentsProcessor = Default::default();
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("deposit, 321, 1, 150.0").unwrap());
+	assert!(res.is_ok());
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("withdrawal, 321, 2, 50.0").unwrap());
+	assert!(res.is_ok());
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("dispute, 321, 2, 10.0").unwrap());
+	assert!(res.is_ok());
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("resolve, 321, 2, 10.5").unwrap());
+	assert_eq!(res, Err(PTErr::AmountExceedsDisputedFunds));
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("chargeback, 321, 2, 10.5").unwrap());
+	assert_eq!(res, Err(PTErr::AmountExceedsDisputedFunds));
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().nth(1), Some("321,100.0,10.0,110.0,false"));
+}
+
+#[test]
+fn partial_chargeback_locks_account_and_keeps_remaining_dispute_open() {
+	let mut proc: PaymentsProcessor = Default::default();
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("deposit, 321, 1, 150.0").unwrap());
+	assert!(res.is_ok());
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("withdrawal, 321, 2, 50.0").unwrap());
+	assert!(res.is_ok());
+	let res = proc.process_transaction(PaymentsTransaction::from_str("dispute, 321, 2").unwrap());
+	assert!(res.is_ok());
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("chargeback, 321, 2, 20.0").unwrap());
+	assert!(res.is_ok());
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().nth(1), Some("321,100.0,30.0,130.0,true"));
+	let res = proc.process_transaction(PaymentsTransaction::from_str("resolve, 321, 2").unwrap());
+	assert!(res.is_ok());
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().nth(1), Some("321,130.0,0.0,130.0,true"));
+	let res = proc.process_transaction(PaymentsTransaction::from_str("dispute, 321, 2").unwrap());
+	assert_eq!(res, Err(PTErr::TransactionAlreadyDisputed));
+}
+
+// every reachable dispute lifecycle state of withdrawal 2, along with the transactions leading to it
+const LIFECYCLE_STATES: [(&str, &[&str]); 7] = [
+	("Executed", &[]),
+	("UnderDispute", &["dispute, 321, 2"]),
+	("Resolved", &["dispute, 321, 2", "resolve, 321, 2"]),
+	("ChargedBack", &["dispute, 321, 2", "chargeback, 321, 2"]),
+	(
+		"Represented",
+		&[
+			"dispute, 321, 2",
+			"chargeback, 321, 2",
+			"representment, 321, 2",
+		],
+	),
+	(
+		"PreArbitration",
+		&[
+			"dispute, 321, 2",
+			"chargeback, 321, 2",
+			"representment, 321, 2",
+			"prearbitration, 321, 2",
+		],
+	),
+	(
+		"ResolvedRoundsExhausted",
+		&[
+			"dispute, 321, 2",
+			"resolve, 321, 2",
+			"dispute, 321, 2",
+			"resolve, 321, 2",
+		],
+	),
+];
+
+const LIFECYCLE_EVENTS: [&str; 5] = [
+	"dispute, 321, 2",
+	"resolve, 321, 2",
+	"chargeback, 321, 2",
+	"representment, 321, 2",
+	"prearbitration, 321, 2",
+];
+
+#[test]
+fn dispute_lifecycle_transition_table_is_enforced() {
+	use PTErr::*;
+	let expected: [[Result<(), PTErr>; 5]; 7] = [
+		//dispute, resolve, chargeback, representment, prearbitration
+		[
+			Ok(()),
+			Err(UndisputedTransactionCannotBeResolved),
+			Err(UndisputedTransactionCannotBeChargedBack),
+			Err(TransactionNotChargedBack),
+			Err(TransactionNotRepresented),
+		],
+		[
+			Err(InvalidDisputeAmount),
+			Ok(()),
+			Ok(()),
+			Err(TransactionNotChargedBack),
+			Err(TransactionNotRepresented),
+		],
+		[
+			Ok(()),
+			Err(UndisputedTransactionCannotBeResolved),
+			Err(UndisputedTransactionCannotBeChargedBack),
+			Err(TransactionNotChargedBack),
+			Err(TransactionNotRepresented),
+		],
+		[
+			Err(TransactionAlreadyDisputed),
+			Err(UndisputedTransactionCannotBeResolved),
+			Err(UndisputedTransactionCannotBeChargedBack),
+			Ok(()),
+			Err(TransactionNotRepresented),
+		],
+		[
+			Err(TransactionAlreadyDisputed),
+			Err(UndisputedTransactionCannotBeResolved),
+			Err(RepresentmentNotPreArbitrated),
+			Err(TransactionNotChargedBack),
+			Ok(()),
+		],
+		[
+			Err(TransactionAlreadyDisputed),
+			Ok(()),
+			Ok(()),
+			Err(TransactionNotChargedBack),
+			Err(TransactionNotRepresented),
+		],
+		[
+			Err(DisputeRoundsExhausted),
+			Err(UndisputedTransactionCannotBeResolved),
+			Err(UndisputedTransactionCannotBeChargedBack),
+			Err(TransactionNotChargedBack),
+			Err(TransactionNotRepresented),
+		],
+	];
+	for ((state, setup), expected_row) in LIFECYCLE_STATES.iter().zip(expected) {
+		for (event, expected_res) in LIFECYCLE_EVENTS.iter().zip(expected_row) {
+			let mut proc: PaymentsProcessor = Default::default();
+			let setup_txs = ["deposit, 321, 1, 150.0", "withdrawal, 321, 2, 50.0"];
+			for tx in setup_txs.iter().chain(setup.iter()) {
+				let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+				assert!(res.is_ok(), "setup of {} failed at [{}]", state, tx);
+			}
+			let res = proc.process_transaction(PaymentsTransaction::from_str(event).unwrap());
+			assert_eq!(res, expected_res, "state {} event [{}]", state, event);
+		}
+	}
+}
+
+#[test]
+fn transaction_state_follows_the_dispute_lifecycle() {
+	use TransactionState::*;
+	let amount = |x| FixedDecimal::from_str(x).unwrap();
+	let expected = [
+		Executed,
+		UnderDispute(amount("50.0")),
+		Resolved,
+		ChargedBack,
+		Represented(amount("50.0")),
+		PreArbitration(amount("50.0")),
+		Resolved,
+	];
+	for ((state, setup), expected) in LIFECYCLE_STATES.iter().zip(expected) {
+		let mut proc: PaymentsProcessor = Default::default();
+		let setup_txs = ["deposit, 321, 1, 150.0", "withdrawal, 321, 2, 50.0"];
+		for tx in setup_txs.iter().chain(setup.iter()) {
+			let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+			assert!(res.is_ok(), "setup of {} failed at [{}]", state, tx);
+		}
+		assert_eq!(proc.transaction_state(321, 2), Some(expected), "{}", state);
+		assert_eq!(proc.transaction_state(321, 1), Some(Executed));
+	}
+	let proc: PaymentsProcessor = Default::default();
+	assert_eq!(proc.transaction_state(321, 2), None);
+}
+
+#[test]
+fn lifecycle_transactions_on_deposit_are_err() {
+	let mut proc: PaymentsProcessor = Default::default();
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("deposit, 321, 1, 150.0").unwrap());
+	assert!(res.is_ok());
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("representment, 321, 1").unwrap());
+	assert_eq!(res, Err(PTErr::TransactionCouldNotBeRepresented));
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("prearbitration, 321, 1").unwrap());
+	assert_eq!(res, Err(PTErr::TransactionCouldNotBePreArbitrated));
+}
+
+#[test]
+fn second_dispute_after_resolve_holds_funds_again() {
+	let mut proc: PaymentsProcessor = Default::default();
+	for tx in [
+		"deposit, 321, 1, 150.0",
+		"withdrawal, 321, 2, 50.0",
+		"dispute, 321, 2",
+		"resolve, 321, 2",
+	] {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert!(res.is_ok());
+	}
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("dispute, 321, 2, 10.0").unwrap());
+	assert!(res.is_ok());
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().nth(1), Some("321,140.0,10.0,150.0,false"));
+}
+
+#[test]
+fn resolved_amounts_cannot_be_disputed_again_in_the_same_round() {
+	let mut proc: PaymentsProcessor = Default::default();
+	apply(
+		&mut proc,
+		&[
+			"deposit, 321, 1, 100.0",
+			"withdrawal, 321, 2, 40.0",
+			"dispute, 321, 2, 30.0",
+			"resolve, 321, 2, 10.0",
+		],
+	);
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("dispute, 321, 2, 20.0").unwrap());
+	assert_eq!(res, Err(PTErr::DisputedAmountExceedsTransaction));
+	apply(&mut proc, &["dispute, 321, 2, 10.0"]);
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().nth(1), Some("321,70.0,30.0,100.0,false"));
+	assert_eq!(proc.verify(), vec![]);
+}
+
+#[test]
+fn redisputes_take_back_what_was_resolved() {
+	let mut proc: PaymentsProcessor = Default::default();
+	apply(
+		&mut proc,
+		&[
+			"deposit, 321, 1, 100.0",
+			"withdrawal, 321, 2, 40.0",
+			"dispute, 321, 2",
+			"resolve, 321, 2",
+			"dispute, 321, 2",
+		],
+	);
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().nth(1), Some("321,60.0,40.0,100.0,false"));
+	apply(&mut proc, &["resolve, 321, 2"]);
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().nth(1), Some("321,100.0,0.0,100.0,false"));
+	assert_eq!(proc.verify(), vec![]);
+	assert!(proc.is_balanced());
+}
+
+#[test]
+fn representment_reverses_chargeback_and_keeps_account_locked() {
+	let mut proc: PaymentsProcessor = Default::default();
+	for tx in [
+		"deposit, 321, 1, 150.0",
+		"withdrawal, 321, 2, 50.0",
+		"dispute, 321, 2",
+		"chargeback, 321, 2",
+	] {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert!(res.is_ok());
+	}
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("representment, 321, 2").unwrap());
+	assert!(res.is_ok());
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().nth(1), Some("321,150.0,0.0,150.0,true"));
+}
+
+#[test]
+fn prearbitration_holds_represented_amount_until_settled() {
+	let mut proc: PaymentsProcessor = Default::default();
+	for tx in [
+		"deposit, 321, 1, 150.0",
+		"withdrawal, 321, 2, 50.0",
+		"dispute, 321, 2, 30.0",
+		"chargeback, 321, 2",
+		"representment, 321, 2",
+		"prearbitration, 321, 2",
+	] {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert!(res.is_ok());
+	}
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().nth(1), Some("321,100.0,30.0,130.0,true"));
+	let res = proc.process_transaction(PaymentsTransaction::from_str("chargeback, 321, 2").unwrap());
+	assert!(res.is_ok());
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().nth(1), Some("321,100.0,0.0,100.0,true"));
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("representment, 321, 2").unwrap());
+	assert_eq!(res, Err(PTErr::ChargebackAlreadyRepresented));
+}
+
+#[test]
+fn represented_chargeback_is_charged_back_again_only_after_prearbitration() {
+	let mut proc: PaymentsProcessor = Default::default();
+	for tx in [
+		"deposit, 321, 1, 150.0",
+		"withdrawal, 321, 2, 50.0",
+		"dispute, 321, 2",
+		"chargeback, 321, 2",
+		"representment, 321, 2",
+	] {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert!(res.is_ok());
+	}
+	let res = proc.process_transaction(PaymentsTransaction::from_str("chargeback, 321, 2").unwrap());
+	assert_eq!(res, Err(PTErr::RepresentmentNotPreArbitrated));
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("chargeback, 321, 2, 10.0").unwrap());
+	assert_eq!(res, Err(PTErr::RepresentmentNotPreArbitrated));
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().nth(1), Some("321,150.0,0.0,150.0,true"));
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("prearbitration, 321, 2").unwrap());
+	assert!(res.is_ok());
+	//a partial chargeback leaves the rest under pre-arbitration
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("chargeback, 321, 2, 20.0").unwrap());
+	assert!(res.is_ok());
+	let amount = FixedDecimal::from_str("30.0").unwrap();
+	assert_eq!(
+		proc.transaction_state(321, 2),
+		Some(TransactionState::PreArbitration(amount))
+	);
+	let res = proc.process_transaction(PaymentsTransaction::from_str("chargeback, 321, 2").unwrap());
+	assert!(res.is_ok());
+	assert_eq!(
+		proc.transaction_state(321, 2),
+		Some(TransactionState::ChargedBack)
+	);
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().nth(1), Some("321,100.0,0.0,100.0,true"));
+	assert!(proc.is_balanced());
+}
+
+#[test]
+fn prearbitration_resolved_returns_the_held_amount_to_available() {
+	let mut proc: PaymentsProcessor = Default::default();
+	for tx in [
+		"deposit, 321, 1, 150.0",
+		"withdrawal, 321, 2, 50.0",
+		"dispute, 321, 2",
+		"chargeback, 321, 2",
+		"representment, 321, 2",
+		"prearbitration, 321, 2",
+		"resolve, 321, 2, 20.0",
+	] {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert!(res.is_ok());
+	}
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().nth(1), Some("321,120.0,30.0,150.0,true"));
+	let res = proc.process_transaction(PaymentsTransaction::from_str("resolve, 321, 2").unwrap());
+	assert!(res.is_ok());
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().nth(1), Some("321,150.0,0.0,150.0,true"));
+}
+
+#[test]
+fn balances_in_different_currencies_are_separate() {
+	let mut proc: PaymentsProcessor = Default::default();
+	for tx in [
+		"deposit, 4, 1, 100.0, EUR",
+		"deposit, 4, 2, 50.0, USD",
+		"deposit, 4, 3, 10.0",
+		"deposit, 3, 4, 5.0, USD",
+	] {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert!(res.is_ok());
+	}
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().count(), 5);
+	assert_eq!(
+		output.lines().next(),
+		Some("client,currency,available,held,total,locked")
+	);
+	assert_eq!(output.lines().nth(1), Some("3,USD,5.0,0.0,5.0,false"));
+	assert_eq!(output.lines().nth(2), Some("4,,10.0,0.0,10.0,false"));
+	assert_eq!(output.lines().nth(3), Some("4,EUR,100.0,0.0,100.0,false"));
+	assert_eq!(output.lines().nth(4), Some("4,USD,50.0,0.0,50.0,false"));
+}
+
+#[test]
+fn withdrawal_in_currency_without_funds_is_err_and_not_processed() {
+	let mut proc: PaymentsProcessor = Default::default();
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("deposit, 4, 1, 100.0, EUR").unwrap());
+	assert!(res.is_ok());
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("withdrawal, 4, 2, 10.0, USD").unwrap());
+	assert_eq!(res, Err(PTErr::NoAvailableFunds));
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("withdrawal, 4, 3, 10.0").unwrap());
+	assert_eq!(res, Err(PTErr::NoAvailableFunds));
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().count(), 2);
+	assert_eq!(output.lines().nth(1), Some("4,EUR,100.0,0.0,100.0,false"));
+}
+
+#[test]
+fn dispute_acts_on_the_currency_of_the_original_transaction() {
+	let mut proc: PaymentsProcessor = Default::default();
+	for tx in [
+		"deposit, 4, 1, 100.0, EUR",
+		"deposit, 4, 2, 100.0, USD",
+		"withdrawal, 4, 3, 40.0, USD",
+		"dispute, 4, 3, 15.0",
+	] {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert!(res.is_ok());
+	}
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().nth(1), Some("4,EUR,100.0,0.0,100.0,false"));
+	assert_eq!(output.lines().nth(2), Some("4,USD,60.0,15.0,75.0,false"));
+	let res = proc.process_transaction(PaymentsTransaction::from_str("chargeback, 4, 3").unwrap());
+	assert!(res.is_ok());
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().nth(1), Some("4,EUR,100.0,0.0,100.0,true"));
+	assert_eq!(output.lines().nth(2), Some("4,USD,60.0,0.0,60.0,true"));
+}
+
+#[test]
+fn failed_withdrawal_is_not_recorded() {
+	let mut proc: PaymentsProcessor = Default::default();
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("deposit, 4, 1, 100.0").unwrap());
+	assert!(res.is_ok());
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("withdrawal, 4, 2, 150.0").unwrap());
+	assert_eq!(res, Err(PTErr::NoAvailableFunds));
+	let res = proc.process_transaction(PaymentsTransaction::from_str("dispute, 4, 2").unwrap());
+	assert_eq!(res, Err(PTErr::AssociatedTransactionNoFound));
+}
+
+fn processor_with_rates(rounding: Rounding) -> PaymentsProcessor {
+	let rates = RateTable::from_reader("EUR,USD,1.0843\nUSD,EUR,0.9222".as_bytes()).unwrap();
+	PaymentsProcessor::default().with_rates(rates.with_rounding(rounding))
+}
+
+#[test]
+fn convert_debits_source_and_credits_target_currency() {
+	let mut proc = processor_with_rates(Rounding::HalfEven);
+	for tx in ["deposit, 4, 1, 100.0, EUR", "convert, 4, 2, 10.0, EUR, USD"] {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert!(res.is_ok());
+	}
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().count(), 3);
+	assert_eq!(output.lines().nth(1), Some("4,EUR,90.0,0.0,90.0,false"));
+	assert_eq!(output.lines().nth(2), Some("4,USD,10.843,0.0,10.843,false"));
+}
+
+#[test]
+fn convert_records_rate_and_rounding_remainder() {
+	let mut proc = processor_with_rates(Rounding::Down);
+	for tx in [
+		"deposit, 4, 1, 100.0, EUR",
+		"convert, 4, 2, 0.0005, EUR, USD",
+	] {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert!(res.is_ok());
+	}
+	let audit = proc.conversion_audit(4, 2).unwrap();
+	assert_eq!(audit.rate, FixedDecimal::from_str("1.0843").unwrap());
+	assert_eq!(audit.rounding, Rounding::Down);
+	//exact amount is 0.00054215
+	assert_eq!(audit.credited, FixedDecimal::from_str("0.0005").unwrap());
+	assert_eq!(audit.remainder, 4215);
+	assert_eq!(proc.conversion_audit(4, 1), None);
+}
+
+#[test]
+fn convert_without_rate_is_err_and_not_processed() {
+	let mut proc = processor_with_rates(Rounding::HalfEven);
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("deposit, 4, 1, 100.0, EUR").unwrap());
+	assert!(res.is_ok());
+	let res = proc
+		.process_transaction(PaymentsTransaction::from_str("convert, 4, 2, 10.0, EUR, GBP").unwrap());
+	assert_eq!(res, Err(PTErr::ConversionRateNotFound));
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().count(), 2);
+	assert_eq!(output.lines().nth(1), Some("4,EUR,100.0,0.0,100.0,false"));
+}
+
+#[test]
+fn convert_without_available_funds_is_err_and_not_processed() {
+	let mut proc = processor_with_rates(Rounding::HalfEven);
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("deposit, 4, 1, 100.0, EUR").unwrap());
+	assert!(res.is_ok());
+	let res = proc.process_transaction(
+		PaymentsTransaction::from_str("convert, 4, 2, 100.0001, EUR, USD").unwrap(),
+	);
+	assert_eq!(res, Err(PTErr::NoAvailableFunds));
+	let res = proc
+		.process_transaction(PaymentsTransaction::from_str("convert, 4, 3, 1.0, USD, EUR").unwrap());
+	assert_eq!(res, Err(PTErr::NoAvailableFunds));
+	let res = proc
+		.process_transaction(PaymentsTransaction::from_str("convert, 5, 4, 1.0, USD, EUR").unwrap());
+	assert_eq!(res, Err(PTErr::ClientNotFound));
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().count(), 2);
+	assert_eq!(output.lines().nth(1), Some("4,EUR,100.0,0.0,100.0,false"));
+}
+
+#[test]
+fn convert_is_not_disputable() {
+	let mut proc = processor_with_rates(Rounding::HalfEven);
+	for tx in ["deposit, 4, 1, 100.0, EUR", "convert, 4, 2, 10.0, EUR, USD"] {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert!(res.is_ok());
+	}
+	let res = proc.process_transaction(PaymentsTransaction::from_str("dispute, 4, 2").unwrap());
+	assert_eq!(res, Err(PTErr::TransactionCouldNotBeDisputed));
+}
+
+fn processor_with_fees(reversal: FeeReversal) -> PaymentsProcessor {
+	let fees = FeeSchedule::from_reader(
+		"fee, deposit, 0, 1.0, 0.0, 0.0\nfee, withdrawal, 0, 0.5, 1.0, 0.0, 2.0".as_bytes(),
+	)
+	.unwrap();
+	PaymentsProcessor::default().with_fees(fees.with_reversal(reversal))
+}
+
+#[test]
+fn deposit_fee_is_deducted_and_posted_to_house() {
+	let mut proc = processor_with_fees(FeeReversal::Never);
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("deposit, 4, 1, 100.0").unwrap());
+	assert!(res.is_ok());
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().nth(1), Some("4,99.0,0.0,99.0,false"));
+	assert_eq!(
+		proc.transaction_fee(4, 1),
+		FixedDecimal::from_str("1.0").ok()
+	);
+	assert_eq!(
+		proc.house_balance(None).unwrap(),
+		FixedDecimal::from_str("1.0").unwrap()
+	);
+}
+
+#[test]
+fn deposit_smaller_than_fee_is_err_and_not_processed() {
+	let mut proc = processor_with_fees(FeeReversal::Never);
+	let res = proc.process_transaction(PaymentsTransaction::from_str("deposit, 4, 1, 0.5").unwrap());
+	assert_eq!(res, Err(PTErr::FeeExceedsAmount));
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().count(), 1);
+	assert_eq!(proc.house_balance(None).unwrap(), FixedDecimal::default());
+}
+
+#[test]
+fn withdrawal_fee_is_charged_on_top_of_amount() {
+	let mut proc = processor_with_fees(FeeReversal::Never);
+	for tx in ["deposit, 4, 1, 101.0", "withdrawal, 4, 2, 50.0"] {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert!(res.is_ok());
+	}
+	let output = format!("{}", proc);
+	//1% of 50.0 plus 0.5 flat
+	assert_eq!(output.lines().nth(1), Some("4,49.0,0.0,49.0,false"));
+	assert_eq!(
+		proc.transaction_fee(4, 2),
+		FixedDecimal::from_str("1.0").ok()
+	);
+	assert_eq!(
+		proc.house_balance(None).unwrap(),
+		FixedDecimal::from_str("2.0").unwrap()
+	);
+}
+
+#[test]
+fn withdrawal_without_funds_for_the_fee_is_err_and_not_processed() {
+	let mut proc = processor_with_fees(FeeReversal::Never);
+	let res = proc.process_transaction(PaymentsTransaction::from_str("deposit, 4, 1, 51.0").unwrap());
+	assert!(res.is_ok());
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("withdrawal, 4, 2, 50.0").unwrap());
+	assert_eq!(res, Err(PTErr::NoAvailableFunds));
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().nth(1), Some("4,50.0,0.0,50.0,false"));
+	assert_eq!(
+		proc.house_balance(None).unwrap(),
+		FixedDecimal::from_str("1.0").unwrap()
+	);
+}
+
+#[test]
+fn fees_are_posted_per_currency() {
+	let mut proc = processor_with_fees(FeeReversal::Never);
+	for tx in ["deposit, 4, 1, 10.0, EUR", "deposit, 4, 2, 10.0, USD"] {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert!(res.is_ok());
+	}
+	let eur = lib::Currency::from_str("EUR").ok();
+	assert_eq!(
+		proc.house_balance(eur).unwrap(),
+		FixedDecimal::from_str("1.0").unwrap()
+	);
+	assert_eq!(proc.house_balance(None).unwrap(), FixedDecimal::default());
+}
+
+#[test]
+fn fee_is_reversed_on_resolve_when_policy_says_so() {
+	for (reversal, expected_available, expected_house) in [
+		(FeeReversal::Never, "99.0", "2.0"),
+		(FeeReversal::OnResolve, "100.0", "1.0"),
+		(FeeReversal::OnChargeback, "99.0", "2.0"),
+		(FeeReversal::Always, "100.0", "1.0"),
+	] {
+		let mut proc = processor_with_fees(reversal);
+		for tx in [
+			"deposit, 4, 1, 101.0",
+			"withdrawal, 4, 2, 50.0",
+			"dispute, 4, 2",
+			"resolve, 4, 2",
+		] {
+			let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+			assert!(res.is_ok());
+		}
+		let output = format!("{}", proc);
+		let expected = format!("4,{0},0.0,{0},false", expected_available);
+		assert_eq!(
+			output.lines().nth(1),
+			Some(expected.as_str()),
+			"{:?}",
+			reversal
+		);
+		assert_eq!(
+			proc.house_balance(None).unwrap(),
+			FixedDecimal::from_str(expected_house).unwrap()
+		);
+	}
+}
+
+#[test]
+fn fee_is_reversed_on_chargeback_when_policy_says_so() {
+	for (reversal, expected_available, expected_house) in [
+		(FeeReversal::Never, "49.0", "2.0"),
+		(FeeReversal::OnResolve, "49.0", "2.0"),
+		(FeeReversal::OnChargeback, "50.0", "1.0"),
+		(FeeReversal::Always, "50.0", "1.0"),
+	] {
+		let mut proc = processor_with_fees(reversal);
+		for tx in [
+			"deposit, 4, 1, 101.0",
+			"withdrawal, 4, 2, 50.0",
+			"dispute, 4, 2, 20.0",
+			"chargeback, 4, 2",
+		] {
+			let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+			assert!(res.is_ok());
+		}
+		let output = format!("{}", proc);
+		let expected = format!("4,{0},0.0,{0},true", expected_available);
+		assert_eq!(
+			output.lines().nth(1),
+			Some(expected.as_str()),
+			"{:?}",
+			reversal
+		);
+		assert_eq!(
+			proc.house_balance(None).unwrap(),
+			FixedDecimal::from_str(expected_house).unwrap()
+		);
+	}
+}
+
+#[test]
+fn fee_is_reversed_only_once() {
+	let mut proc = processor_with_fees(FeeReversal::Always);
+	for tx in [
+		"deposit, 4, 1, 101.0",
+		"withdrawal, 4, 2, 50.0",
+		"dispute, 4, 2",
+		"resolve, 4, 2",
+		"dispute, 4, 2",
+		"resolve, 4, 2",
+	] {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert!(res.is_ok());
+	}
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().nth(1), Some("4,100.0,0.0,100.0,false"));
+	assert_eq!(
+		proc.house_balance(None).unwrap(),
+		FixedDecimal::from_str("1.0").unwrap()
+	);
+}
+
+#[test]
+fn books_balance_after_every_transaction() {
+	let rates = RateTable::from_reader("EUR,USD,1.0843".as_bytes()).unwrap();
+	let mut proc = processor_with_fees(FeeReversal::Always).with_rates(rates);
+	assert!(proc.is_balanced());
+	for tx in [
+		"deposit, 4, 1, 101.0",
+		"deposit, 4, 2, 50.0, EUR",
+		"deposit, 5, 3, 20.0",
+		"withdrawal, 4, 4, 50.0",
+		"withdrawal, 5, 5, 500.0",
+		"convert, 4, 6, 10.0, EUR, USD",
+		"dispute, 4, 4, 20.0",
+		"resolve, 4, 4, 5.0",
+		"chargeback, 4, 4",
+		"representment, 4, 4",
+		"prearbitration, 4, 4",
+		"resolve, 4, 4",
+		"withdrawal, 4, 7, 1.0",
+	] {
+		let _ = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert!(proc.is_balanced(), "{}", tx);
+		assert_eq!(proc.verify(), vec![], "{}", tx);
+	}
+}
+
+#[test]
+fn every_transaction_posts_once_and_failures_post_nothing() {
+	let mut proc: PaymentsProcessor = Default::default();
+	for (tx, postings) in [
+		("deposit, 4, 1, 100.0", 1),
+		("withdrawal, 4, 2, 150.0", 1),
+		("withdrawal, 4, 3, 40.0", 2),
+		("resolve, 4, 3", 2),
+		("dispute, 4, 3", 3),
+		("dispute, 4, 3", 3),
+		("chargeback, 4, 3", 4),
+		("deposit, 4, 4, 10.0", 5),
+	] {
+		let _ = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert_eq!(proc.postings(), postings, "{}", tx);
+	}
+	assert!(proc.is_balanced());
+}
+
+#[test]
+fn verify_finds_no_discrepancy_through_the_dispute_lifecycle() {
+	for (state, setup) in LIFECYCLE_STATES {
+		for event in LIFECYCLE_EVENTS {
+			let mut proc = processor_with_fees(FeeReversal::Always);
+			let setup_txs = [
+				"deposit, 321, 1, 151.0",
+				"withdrawal, 321, 2, 50.0",
+				"dispute, 321, 2, 10.0",
+				"resolve, 321, 2, 10.0",
+			];
+			for tx in setup_txs.iter().chain(setup.iter()).chain([&event]) {
+				let _ = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+			}
+			assert_eq!(proc.verify(), vec![], "state {} event [{}]", state, event);
+		}
+	}
+}
+
+#[test]
+fn verify_finds_no_discrepancy_with_partial_disputes() {
+	let mut proc: PaymentsProcessor = Default::default();
+	for tx in [
+		"deposit, 4, 1, 100.0",
+		"withdrawal, 4, 2, 60.0",
+		"dispute, 4, 2, 20.0",
+		"dispute, 4, 2, 15.0",
+		"resolve, 4, 2, 5.0",
+		"chargeback, 4, 2, 10.0",
+		"resolve, 4, 2",
+		"representment, 4, 2",
+		"prearbitration, 4, 2",
+		"chargeback, 4, 2, 4.0",
+	] {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert!(res.is_ok(), "{}", tx);
+		assert_eq!(proc.verify(), vec![], "{}", tx);
+	}
+}
+
+#[test]
+fn dispute_overflowing_total_is_err_and_not_processed() {
+	let mut proc: PaymentsProcessor = Default::default();
+	for tx in [
+		"deposit, 4, 1, 1844674407370955.1615",
+		"withdrawal, 4, 2, 10.0",
+		"deposit, 4, 3, 10.0",
+	] {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert!(res.is_ok(), "{}", tx);
+	}
+	let res = proc.process_transaction(PaymentsTransaction::from_str("dispute, 4, 2").unwrap());
+	assert_eq!(res, Err(PTErr::BalanceOverflow));
+	let output = format!("{}", proc);
+	assert_eq!(
+		output.lines().nth(1),
+		Some("4,1844674407370955.1615,0.0,1844674407370955.1615,false")
+	);
+	assert_eq!(proc.verify(), vec![]);
+}
+
+#[test]
+fn deposit_overflowing_available_is_err_and_not_processed() {
+	let mut proc: PaymentsProcessor = Default::default();
+	let res = proc.process_transaction(
+		PaymentsTransaction::from_str("deposit, 4, 1, 1844674407370955.1615").unwrap(),
+	);
+	assert!(res.is_ok());
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("deposit, 4, 2, 0.0001").unwrap());
+	assert_eq!(res, Err(PTErr::BalanceOverflow));
+	assert_eq!(proc.fault(), None);
+	let res = proc.process_transaction(PaymentsTransaction::from_str("deposit, 5, 3, 1.0").unwrap());
+	assert!(res.is_ok());
+	let output = format!("{}", proc);
+	assert_eq!(
+		output.lines().nth(1),
+		Some("4,1844674407370955.1615,0.0,1844674407370955.1615,false")
+	);
+	assert!(proc.is_balanced());
+	assert_eq!(proc.verify(), vec![]);
+}
+
+#[test]
+fn max_balance_caps_the_total_of_every_balance() {
+	let mut proc =
+		PaymentsProcessor::default().with_max_balance(FixedDecimal::from_str("100.0").unwrap());
+	for (tx, expected) in [
+		("deposit, 4, 1, 90.0", Ok(())),
+		("deposit, 4, 2, 10.0001", Err(PTErr::BalanceOverflow)),
+		("deposit, 4, 3, 10.0", Ok(())),
+		("withdrawal, 4, 4, 30.0", Ok(())),
+		("deposit, 4, 5, 20.0", Ok(())),
+		("dispute, 4, 4", Err(PTErr::BalanceOverflow)),
+		("dispute, 4, 4, 10.0", Ok(())),
+		("deposit, 4, 6, 100.0, EUR", Ok(())),
+	] {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert_eq!(res, expected, "{}", tx);
+	}
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().nth(1), Some("4,,90.0,10.0,100.0,false"));
+	assert_eq!(output.lines().nth(2), Some("4,EUR,100.0,0.0,100.0,false"));
+}
+
+#[test]
+fn duplicate_transaction_is_err_and_not_processed() {
+	let mut proc: PaymentsProcessor = Default::default();
+	for (tx, expected) in [
+		("deposit, 4, 1, 100.0", Ok(())),
+		("deposit, 4, 1, 100.0", Err(PTErr::DuplicateTransaction)),
+		("withdrawal, 4, 1, 10.0", Err(PTErr::DuplicateTransaction)),
+		("withdrawal, 4, 2, 10.0", Ok(())),
+		("deposit, 4, 2, 10.0", Err(PTErr::DuplicateTransaction)),
+	] {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert_eq!(res, expected, "{}", tx);
+	}
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().nth(1), Some("4,90.0,0.0,90.0,false"));
+}
+
+fn processor_for_batches() -> PaymentsProcessor {
+	let rates = RateTable::from_reader("EUR,USD,1.0843".as_bytes()).unwrap();
+	processor_with_fees(FeeReversal::Always).with_rates(rates)
+}
+
+const BATCH_SETUP: [&str; 4] = [
+	"deposit, 4, 1, 101.0",
+	"deposit, 4, 2, 50.0, EUR",
+	"withdrawal, 4, 3, 20.0",
+	"dispute, 4, 3, 5.0",
+];
+
+const BATCH: [&str; 9] = [
+	"deposit, 5, 10, 30.0",
+	"deposit, 4, 11, 10.0, GBP",
+	"convert, 4, 12, 10.0, EUR, USD",
+	"dispute, 4, 3, 5.0",
+	"resolve, 4, 3, 5.0",
+	"chargeback, 4, 3",
+	"representment, 4, 3",
+	"withdrawal, 5, 13, 10.0",
+	"prearbitration, 4, 3",
+];
+
+#[test]
+fn rollback_restores_balances_locks_and_transactions_exactly() {
+	let mut proc = processor_for_batches();
+	for tx in BATCH_SETUP {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert!(res.is_ok(), "{}", tx);
+	}
+	let before = format!("{}", proc);
+	let postings = proc.postings();
+	assert_eq!(proc.begin(), Ok(()));
+	for tx in BATCH {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert!(res.is_ok(), "{}", tx);
+	}
+	let after = format!("{}", proc);
+	assert_ne!(before, after);
+	assert_eq!(proc.rollback(), Ok(()));
+	assert!(!proc.in_batch());
+	assert_eq!(format!("{}", proc), before);
+	assert_eq!(proc.postings(), postings);
+	assert_eq!(
+		proc.house_balance(None).unwrap(),
+		FixedDecimal::from_str("1.7").unwrap()
+	);
+	assert!(proc.is_balanced());
+	assert_eq!(proc.verify(), vec![]);
+	//the transaction records are restored too, so the very same batch applies once again
+	for tx in BATCH {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert!(res.is_ok(), "{}", tx);
+	}
+	assert_eq!(format!("{}", proc), after);
+}
+
+#[test]
+fn commit_keeps_the_batch() {
+	let mut proc = processor_for_batches();
+	assert_eq!(proc.begin(), Ok(()));
+	for tx in BATCH_SETUP {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert!(res.is_ok(), "{}", tx);
+	}
+	let committed = format!("{}", proc);
+	assert_eq!(proc.commit(), Ok(()));
+	assert_eq!(proc.rollback(), Err(BatchError::NoOpenBatch));
+	assert_eq!(format!("{}", proc), committed);
+}
+
+#[test]
+fn rejected_transactions_within_a_batch_are_not_undone() {
+	let mut proc = processor_for_batches();
+	for tx in BATCH_SETUP {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert!(res.is_ok(), "{}", tx);
+	}
+	let before = format!("{}", proc);
+	assert_eq!(proc.begin(), Ok(()));
+	for (tx, expected) in [
+		("withdrawal, 4, 20, 1000.0", Err(PTErr::NoAvailableFunds)),
+		("deposit, 4, 1, 10.0", Err(PTErr::DuplicateTransaction)),
+		("deposit, 4, 21, 10.0", Ok(())),
+	] {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert_eq!(res, expected, "{}", tx);
+	}
+	assert_eq!(proc.rollback(), Ok(()));
+	assert_eq!(format!("{}", proc), before);
+	assert_eq!(proc.verify(), vec![]);
+}
+
+#[test]
+fn batches_do_not_nest() {
+	let mut proc: PaymentsProcessor = Default::default();
+	assert_eq!(proc.commit(), Err(BatchError::NoOpenBatch));
+	assert_eq!(proc.rollback(), Err(BatchError::NoOpenBatch));
+	assert_eq!(proc.begin(), Ok(()));
+	assert!(proc.in_batch());
+	assert_eq!(proc.begin(), Err(BatchError::BatchAlreadyOpen));
+	assert_eq!(proc.commit(), Ok(()));
+	assert!(!proc.in_batch());
+}
+
+#[test]
+fn undo_to_savepoint_restores_the_state_at_the_savepoint() {
+	let mut proc = processor_for_batches();
+	for tx in BATCH_SETUP {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert!(res.is_ok(), "{}", tx);
+	}
+	let at_a = format!("{}", proc);
+	assert_eq!(proc.savepoint("a"), Ok(()));
+	for tx in &BATCH[..4] {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert!(res.is_ok(), "{}", tx);
+	}
+	let at_b = format!("{}", proc);
+	assert_eq!(proc.savepoint("b"), Ok(()));
+	for tx in &BATCH[4..] {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert!(res.is_ok(), "{}", tx);
+	}
+	assert_eq!(proc.undo_to_savepoint("b"), Ok(5));
+	assert_eq!(format!("{}", proc), at_b);
+	assert_eq!(proc.undo_to_savepoint("a"), Ok(4));
+	assert_eq!(format!("{}", proc), at_a);
+	assert_eq!(proc.verify(), vec![]);
+	assert_eq!(
+		proc.undo_to_savepoint("b"),
+		Err(BatchError::UnknownSavepoint)
+	);
+	assert_eq!(proc.savepoint("a"), Err(BatchError::DuplicateSavepoint));
+	assert_eq!(proc.release_savepoint("a"), Ok(()));
+	assert_eq!(
+		proc.undo_to_savepoint("a"),
+		Err(BatchError::UnknownSavepoint)
+	);
+}
+
+#[test]
+fn undo_last_reverts_dispute_transitions() {
+	let mut proc = PaymentsProcessor::default().with_undo_depth(3);
+	for tx in [
+		"deposit, 4, 1, 100.0",
+		"withdrawal, 4, 2, 40.0",
+		"dispute, 4, 2",
+		"chargeback, 4, 2",
+	] {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert!(res.is_ok(), "{}", tx);
+	}
+	assert_eq!(
+		format!("{}", proc).lines().nth(1),
+		Some("4,60.0,0.0,60.0,true")
+	);
+	assert_eq!(proc.undo_last(1), Ok(()));
+	assert_eq!(
+		format!("{}", proc).lines().nth(1),
+		Some("4,60.0,40.0,100.0,false")
+	);
+	let res = proc.process_transaction(PaymentsTransaction::from_str("resolve, 4, 2").unwrap());
+	assert!(res.is_ok());
+	assert_eq!(
+		format!("{}", proc).lines().nth(1),
+		Some("4,100.0,0.0,100.0,false")
+	);
+	//only the last 3 transactions are kept: withdrawal, dispute and resolve
+	assert_eq!(proc.undo_last(4), Err(BatchError::NotEnoughHistory));
+	assert_eq!(proc.undo_last(3), Ok(()));
+	assert_eq!(
+		format!("{}", proc).lines().nth(1),
+		Some("4,100.0,0.0,100.0,false")
+	);
+	let res = proc.process_transaction(PaymentsTransaction::from_str("dispute, 4, 2").unwrap());
+	assert_eq!(res, Err(PTErr::AssociatedTransactionNoFound));
+	assert_eq!(proc.verify(), vec![]);
+}
+
+#[test]
+fn rollback_stops_at_the_batch_start_when_older_transactions_were_trimmed() {
+	let mut proc = PaymentsProcessor::default().with_undo_depth(1);
+	for tx in [
+		"deposit, 4, 1, 100.0",
+		"deposit, 4, 2, 10.0",
+		"deposit, 4, 3, 1.0",
+	] {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert!(res.is_ok(), "{}", tx);
+	}
+	let before = format!("{}", proc);
+	assert_eq!(proc.begin(), Ok(()));
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("withdrawal, 4, 4, 50.0").unwrap());
+	assert!(res.is_ok());
+	assert_eq!(proc.rollback(), Ok(()));
+	assert_eq!(format!("{}", proc), before);
+	assert_eq!(proc.postings(), 3);
+}
+
+#[test]
+fn undo_cannot_reach_past_the_open_batch() {
+	let mut proc = PaymentsProcessor::default();
+	assert_eq!(proc.savepoint("before"), Ok(()));
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("deposit, 4, 1, 100.0").unwrap());
+	assert!(res.is_ok());
+	assert_eq!(proc.begin(), Ok(()));
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("deposit, 4, 2, 100.0").unwrap());
+	assert!(res.is_ok());
+	assert_eq!(proc.undo_last(2), Err(BatchError::UndoBeyondBatch));
+	assert_eq!(
+		proc.undo_to_savepoint("before"),
+		Err(BatchError::UndoBeyondBatch)
+	);
+	assert_eq!(proc.undo_last(1), Ok(()));
+	assert_eq!(proc.commit(), Ok(()));
+	assert_eq!(proc.undo_to_savepoint("before"), Ok(1));
+	assert_eq!(format!("{}", proc).lines().count(), 1);
+}
+
+#[test]
+fn retries_with_the_same_idempotency_key_return_the_original_outcome() {
+	let mut proc = PaymentsProcessor::default();
+	for (tx, expected) in [
+		("deposit, 4, 1, 100.0, , , dep-1", Ok(())),
+		("deposit, 4, 1, 100.0, , , dep-1", Ok(())),
+		(
+			"withdrawal, 4, 2, 150.0, , , wd-2",
+			Err(PTErr::NoAvailableFunds),
+		),
+		("deposit, 4, 3, 100.0", Ok(())),
+		//the withdrawal would now succeed but its retry is not processed again
+		(
+			"withdrawal, 4, 2, 150.0, , , wd-2",
+			Err(PTErr::NoAvailableFunds),
+		),
+		("deposit, 4, 1, 100.0, , , dep-1", Ok(())),
+	] {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert_eq!(res, expected, "{}", tx);
+	}
+	assert_eq!(
+		format!("{}", proc).lines().nth(1),
+		Some("4,200.0,0.0,200.0,false")
+	);
+	assert_eq!(proc.postings(), 2);
+	//without the key the same transaction is a duplicate
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("deposit, 4, 1, 100.0").unwrap());
+	assert_eq!(res, Err(PTErr::DuplicateTransaction));
+}
+
+#[test]
+fn reusing_an_idempotency_key_for_a_different_transaction_is_err() {
+	let mut proc = PaymentsProcessor::default();
+	for (tx, expected) in [
+		("deposit, 4, 1, 100.0, , , key", Ok(())),
+		(
+			"deposit, 4, 1, 100.1, , , key",
+			Err(PTErr::IdempotencyKeyReused),
+		),
+		(
+			"deposit, 4, 1, 100.0, EUR, , key",
+			Err(PTErr::IdempotencyKeyReused),
+		),
+		(
+			"deposit, 5, 2, 100.0, , , key",
+			Err(PTErr::IdempotencyKeyReused),
+		),
+		(
+			"withdrawal, 4, 2, 10.0, , , key",
+			Err(PTErr::IdempotencyKeyReused),
+		),
+		("withdrawal, 4, 2, 10.0, , , other", Ok(())),
+	] {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert_eq!(res, expected, "{}", tx);
+	}
+	assert_eq!(format!("{}", proc).lines().count(), 2);
+	assert_eq!(
+		format!("{}", proc).lines().nth(1),
+		Some("4,90.0,0.0,90.0,false")
+	);
+}
+
+#[test]
+fn idempotency_keys_beyond_the_retention_are_forgotten() {
+	let mut proc = PaymentsProcessor::default().with_idempotency_retention(2);
+	for (tx, expected) in [
+		("deposit, 4, 1, 100.0, , , k1", Ok(())),
+		("deposit, 4, 2, 100.0, , , k2", Ok(())),
+		("deposit, 4, 1, 100.0, , , k1", Ok(())),
+		("deposit, 4, 3, 100.0, , , k3", Ok(())),
+		//k1 is forgotten so its retry is processed as a new transaction
+		(
+			"deposit, 4, 1, 100.0, , , k1",
+			Err(PTErr::DuplicateTransaction),
+		),
+		("deposit, 4, 3, 100.0, , , k3", Ok(())),
+		(
+			"deposit, 4, 2, 100.0, , , k2",
+			Err(PTErr::DuplicateTransaction),
+		),
+	] {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert_eq!(res, expected, "{}", tx);
+	}
+	let mut proc = PaymentsProcessor::default().with_idempotency_retention(0);
+	let tx = "deposit, 4, 1, 100.0, , , k1";
+	assert!(proc
+		.process_transaction(PaymentsTransaction::from_str(tx).unwrap())
+		.is_ok());
+	assert_eq!(
+		proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap()),
+		Err(PTErr::DuplicateTransaction)
+	);
+}
+
+#[test]
+fn undone_transactions_forget_their_idempotency_keys() {
+	let mut proc = PaymentsProcessor::default().with_undo_depth(1);
+	let deposit = "deposit, 4, 1, 100.0, , , dep-1";
+	let withdrawal = "withdrawal, 4, 2, 150.0, , , wd-2";
+	assert_eq!(proc.begin(), Ok(()));
+	for tx in [deposit, withdrawal] {
+		let _ = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+	}
+	assert_eq!(proc.rollback(), Ok(()));
+	//the retries are processed anew, as if the rolled back batch never happened
+	let res = proc.process_transaction(PaymentsTransaction::from_str(deposit).unwrap());
+	assert_eq!(res, Ok(()));
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("deposit, 4, 3, 100.0").unwrap());
+	assert_eq!(res, Ok(()));
+	let res = proc.process_transaction(PaymentsTransaction::from_str(withdrawal).unwrap());
+	assert_eq!(res, Ok(()));
+	assert_eq!(proc.undo_last(1), Ok(()));
+	let res = proc.process_transaction(PaymentsTransaction::from_str(withdrawal).unwrap());
+	assert_eq!(res, Ok(()));
+	let res = proc.process_transaction(PaymentsTransaction::from_str(deposit).unwrap());
+	assert_eq!(res, Ok(()));
+	assert_eq!(
+		format!("{}", proc).lines().nth(1),
+		Some("4,50.0,0.0,50.0,false")
+	);
+	assert_eq!(proc.postings(), 3);
+}
+
+fn apply(proc: &mut PaymentsProcessor, transactions: &[&str]) {
+	for tx in transactions {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert!(res.is_ok(), "{}", tx);
+	}
+}
+
+//client, (available, held, locked) before and after, in the default currency
+fn delta(proc: &mut PaymentsProcessor, since: u64) -> (u64, Vec<(u16, String, String)>) {
+	let report = proc.delta_report(since).unwrap();
+	assert_eq!(report.since, since);
+	let side = |x: &[lib::AccountStatement]| {
+		x.iter()
+			.map(|x| format!("{}/{}/{}", x.available, x.held, x.locked))
+			.collect()
+	};
+	let changes = report
+		.changes
+		.iter()
+		.map(|x| (x.client, side(&x.before), side(&x.after)))
+		.collect();
+	(report.report, changes)
+}
+
+#[test]
+fn delta_reports_list_the_clients_changed_since_a_cursor() {
+	let mut proc: PaymentsProcessor = Default::default();
+	assert_eq!(proc.last_report(), 0);
+	apply(&mut proc, &["deposit, 2, 1, 5.0", "deposit, 1, 2, 10.0"]);
+	assert_eq!(
+		delta(&mut proc, 0),
+		(
+			1,
+			vec![
+				(1, "".to_owned(), "10.0/0.0/false".to_owned()),
+				(2, "".to_owned(), "5.0/0.0/false".to_owned()),
+			]
+		)
+	);
+	apply(&mut proc, &["withdrawal, 1, 3, 4.0", "dispute, 1, 3"]);
+	assert_eq!(
+		delta(&mut proc, 1),
+		(
+			2,
+			vec![(1, "10.0/0.0/false".to_owned(), "6.0/4.0/false".to_owned())]
+		)
+	);
+	apply(&mut proc, &["chargeback, 1, 3", "deposit, 3, 4, 1.0"]);
+	//another consumer, still at the first report
+	assert_eq!(
+		delta(&mut proc, 1),
+		(
+			3,
+			vec![
+				(1, "10.0/0.0/false".to_owned(), "6.0/0.0/true".to_owned()),
+				(3, "".to_owned(), "1.0/0.0/false".to_owned()),
+			]
+		)
+	);
+	assert_eq!(delta(&mut proc, 3), (4, vec![]));
+	assert_eq!(proc.delta_report(5), Err(DeltaError::UnknownReport));
+	assert_eq!(proc.last_report(), 4);
+}
+
+#[test]
+fn delta_reports_leave_out_clients_changed_back() {
+	let mut proc = PaymentsProcessor::default().with_undo_depth(2);
+	apply(&mut proc, &["deposit, 1, 1, 10.0", "deposit, 2, 2, 1.0"]);
+	assert_eq!(delta(&mut proc, 0).0, 1);
+	apply(&mut proc, &["withdrawal, 1, 3, 4.0", "deposit, 2, 4, 1.0"]);
+	assert_eq!(proc.undo_last(2), Ok(()));
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("withdrawal, 2, 5, 9.0").unwrap());
+	assert_eq!(res, Err(PTErr::NoAvailableFunds));
+	assert_eq!(proc.lock_client(1), Ok(()));
+	assert_eq!(proc.unlock_client(1), Ok(()));
+	assert_eq!(delta(&mut proc, 1), (2, vec![]));
+	//a client created and undone since the report was never seen by it
+	apply(&mut proc, &["deposit, 3, 6, 1.0"]);
+	assert_eq!(proc.lock_client(2), Ok(()));
+	assert_eq!(proc.undo_last(1), Ok(()));
+	assert_eq!(
+		delta(&mut proc, 2),
+		(
+			3,
+			vec![(2, "1.0/0.0/false".to_owned(), "1.0/0.0/true".to_owned())]
+		)
+	);
+}
+
+#[test]
+fn delta_reports_leave_out_rejected_transactions() {
+	let mut proc: PaymentsProcessor = Default::default();
+	apply(&mut proc, &["deposit, 1, 1, 10.0"]);
+	for line in [
+		"dispute, 9, 1",
+		"withdrawal, 8, 2, 1.0",
+		"withdrawal, 1, 3, 40.0",
+	] {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(line).unwrap());
+		assert!(res.is_err(), "{}", line);
+	}
+	assert_eq!(proc.lock_client(7), Err(PTErr::ClientNotFound));
+	assert_eq!(
+		delta(&mut proc, 0),
+		(1, vec![(1, "".to_owned(), "10.0/0.0/false".to_owned())])
+	);
+	let res = proc.process_transaction(PaymentsTransaction::from_str("dispute, 1, 4").unwrap());
+	assert!(res.is_err());
+	assert_eq!(delta(&mut proc, 1), (2, vec![]));
+	assert_eq!(proc.statements().len(), 1);
+}
+
+#[test]
+fn changes_since_a_report_are_told_without_cutting_one() {
+	let mut proc: PaymentsProcessor = Default::default();
+	apply(&mut proc, &["deposit, 1, 1, 10.0"]);
+	let peeked = proc.changes_since(0).unwrap();
+	assert_eq!((peeked.since, peeked.report), (0, 0));
+	assert_eq!(peeked.changes.len(), 1);
+	assert_eq!(proc.last_report(), 0);
+	assert_eq!(proc.changes_since(1), Err(DeltaError::UnknownReport));
+	let cut = proc.delta_report(0).unwrap();
+	assert_eq!((cut.report, cut.changes), (1, peeked.changes));
+	apply(&mut proc, &["deposit, 2, 2, 1.0"]);
+	let clients: Vec<_> = proc
+		.changes_since(0)
+		.unwrap()
+		.changes
+		.iter()
+		.map(|x| x.client)
+		.collect();
+	assert_eq!(clients, vec![1, 2]);
+	assert_eq!(delta(&mut proc, 1).1.len(), 1);
+}
+
+#[test]
+fn delta_reports_older_than_the_history_are_expired() {
+	let mut proc = PaymentsProcessor::default().with_report_history(2);
+	for (i, since) in [0, 0, 1].into_iter().enumerate() {
+		apply(&mut proc, &[&format!("deposit, {}, {}, 1.0", i + 1, i + 1)]);
+		assert_eq!(delta(&mut proc, since).0, i as u64 + 1);
+	}
+	//the changes of reports 2 and 3 are kept, enough to tell those since report 1 but not since 0
+	assert_eq!(proc.delta_report(0), Err(DeltaError::ReportExpired));
+	apply(&mut proc, &["deposit, 4, 4, 1.0"]);
+	let clients: Vec<_> = delta(&mut proc, 1).1.iter().map(|x| x.0).collect();
+	assert_eq!(clients, vec![2, 3, 4]);
+	assert_eq!(proc.delta_report(1), Err(DeltaError::ReportExpired));
+}
+
+#[test]
+fn expiring_the_oldest_savepoint_keeps_the_later_ones() {
+	let mut proc: PaymentsProcessor = Default::default();
+	assert_eq!(proc.expire_oldest_savepoint(), None);
+	assert_eq!(proc.savepoint("first"), Ok(()));
+	apply(&mut proc, &["deposit, 1, 1, 10.0", "deposit, 1, 2, 10.0"]);
+	assert_eq!(proc.savepoint("second"), Ok(()));
+	apply(&mut proc, &["deposit, 1, 3, 10.0"]);
+	assert_eq!((proc.savepoints(), proc.undoable()), (2, 3));
+	assert_eq!(proc.expire_oldest_savepoint(), Some("first".to_owned()));
+	assert_eq!((proc.savepoints(), proc.undoable()), (1, 1));
+	assert_eq!(
+		proc.undo_to_savepoint("first"),
+		Err(BatchError::UnknownSavepoint)
+	);
+	assert_eq!(proc.undo_to_savepoint("second"), Ok(1));
+	let output = format!("{}", proc);
+	assert_eq!(output.lines().nth(1), Some("1,20.0,0.0,20.0,false"));
+}
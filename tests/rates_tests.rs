@@ -0,0 +1,72 @@
+extern crate lib;
+
+use lib::{Currency, FixedDecimal, RateTable, RateTableError, Rounding};
+use std::str::FromStr;
+
+#[test]
+fn empty_input_is_empty_table() {
+	let table = RateTable::from_reader("".as_bytes());
+	assert!(table.is_ok());
+	let eur = Currency::from_str("EUR").unwrap();
+	let usd = Currency::from_str("USD").unwrap();
+	assert_eq!(table.unwrap().get(eur, usd), None);
+}
+
+#[test]
+fn rates_are_directional() {
+	let table = RateTable::from_reader("EUR,USD,1.1\nUSD,EUR,0.9".as_bytes()).unwrap();
+	let eur = Currency::from_str("EUR").unwrap();
+	let usd = Currency::from_str("USD").unwrap();
+	let gbp = Currency::from_str("GBP").unwrap();
+	assert_eq!(table.get(eur, usd), FixedDecimal::from_str("1.1").ok());
+	assert_eq!(table.get(usd, eur), FixedDecimal::from_str("0.9").ok());
+	assert_eq!(table.get(eur, gbp), None);
+}
+
+#[test]
+fn comments_and_empty_lines_are_skipped() {
+	let table = RateTable::from_reader("# from,to,rate\n\n  EUR , GBP , 0.85  \n".as_bytes());
+	let eur = Currency::from_str("EUR").unwrap();
+	let gbp = Currency::from_str("GBP").unwrap();
+	assert_eq!(
+		table.unwrap().get(eur, gbp),
+		FixedDecimal::from_str("0.85").ok()
+	);
+}
+
+#[test]
+fn malformed_lines_are_err() {
+	let res = RateTable::from_reader("EUR,USD,1.1\nEUR,USD".as_bytes());
+	assert_eq!(res.unwrap_err(), RateTableError::MalformedLine(2));
+	let res = RateTable::from_reader("EUR,USD,1.1,1.2".as_bytes());
+	assert_eq!(res.unwrap_err(), RateTableError::MalformedLine(1));
+}
+
+#[test]
+fn invalid_sections_are_err() {
+	let res = RateTable::from_reader("EURO,USD,1.1".as_bytes());
+	assert_eq!(res.unwrap_err(), RateTableError::InvalidCurrency(1));
+	let res = RateTable::from_reader("EUR,USD,1".as_bytes());
+	assert_eq!(res.unwrap_err(), RateTableError::InvalidRate(1));
+	let res = RateTable::from_reader("EUR,USD,0.0".as_bytes());
+	assert_eq!(res.unwrap_err(), RateTableError::InvalidRate(1));
+	let res = RateTable::from_reader("EUR,EUR,1.0".as_bytes());
+	assert_eq!(res.unwrap_err(), RateTableError::SameCurrencyPair(1));
+}
+
+#[test]
+fn duplicate_pair_is_err() {
+	let res = RateTable::from_reader("EUR,USD,1.1\n#\nEUR,USD,1.2".as_bytes());
+	assert_eq!(res.unwrap_err(), RateTableError::DuplicatePair(3));
+}
+
+#[test]
+fn default_rounding_is_half_even() {
+	assert_eq!(RateTable::default().rounding(), Rounding::HalfEven);
+	assert_eq!(
+		RateTable::default()
+			.with_rounding(Rounding::Down)
+			.rounding(),
+		Rounding::Down
+	);
+}
@@ -0,0 +1,210 @@
+extern crate lib;
+
+use lib::{
+	AccountChange, AccountStatement, CsvReport, Currency, DeltaReport, FixedDecimal, FixedDecimalMAX,
+	JsonLinesReport, JsonReport, PaymentsProcessor, PaymentsTransaction, ReportWriter,
+};
+use std::str::FromStr;
+
+fn statement(client: u16, currency: &str, available: &str, held: &str) -> AccountStatement {
+	AccountStatement {
+		client,
+		currency: Currency::from_str(currency).ok(),
+		available: FixedDecimal::from_str(available).unwrap(),
+		held: FixedDecimal::from_str(held).unwrap(),
+		locked: false,
+	}
+}
+
+fn render(writer: &dyn ReportWriter, statements: &[AccountStatement]) -> String {
+	let mut out = String::new();
+	writer.write_report(&mut out, statements).unwrap();
+	out
+}
+
+#[test]
+fn csv_report_adds_the_currency_column_only_when_needed() {
+	let statements = [
+		statement(1, "", "1.5", "0.25"),
+		statement(2, "", "0.0", "0.0"),
+	];
+	assert_eq!(
+		render(&CsvReport, &statements),
+		"client,available,held,total,locked\n1,1.5,0.25,1.75,false\n2,0.0,0.0,0.0,false\n"
+	);
+	let statements = [
+		statement(1, "", "1.5", "0.0"),
+		statement(1, "EUR", "2.0", "0.0"),
+	];
+	assert_eq!(
+		render(&CsvReport, &statements),
+		"client,currency,available,held,total,locked\n1,,1.5,0.0,1.5,false\n1,EUR,2.0,0.0,2.0,false\n"
+	);
+	assert_eq!(
+		render(&CsvReport, &[]),
+		"client,available,held,total,locked\n"
+	);
+}
+
+#[test]
+fn json_report_emits_amounts_as_exact_strings() {
+	let mut locked = statement(7, "USD", "0.0001", "1844674407370955.1615");
+	locked.locked = true;
+	let statements = [statement(1, "", "100.0", "0.5"), locked];
+	assert_eq!(
+		render(&JsonReport, &statements),
+		concat!(
+			"[\n",
+			r#"{"client":1,"currency":null,"available":"100.0","held":"0.5","total":"100.5","locked":false},"#,
+			"\n",
+			r#"{"client":7,"currency":"USD","available":"0.0001","held":"1844674407370955.1615","total":"1844674407370955.1616","locked":true}"#,
+			"\n]\n"
+		)
+	);
+	assert_eq!(render(&JsonReport, &[]), "[]\n");
+}
+
+#[test]
+fn json_lines_report_emits_one_object_per_line() {
+	let statements = [
+		statement(1, "", "100.0", "0.0"),
+		statement(1, "EUR", "2.5", "0.0"),
+	];
+	assert_eq!(
+		render(&JsonLinesReport, &statements),
+		concat!(
+			r#"{"client":1,"currency":null,"available":"100.0","held":"0.0","total":"100.0","locked":false}"#,
+			"\n",
+			r#"{"client":1,"currency":"EUR","available":"2.5","held":"0.0","total":"2.5","locked":false}"#,
+			"\n"
+		)
+	);
+	assert_eq!(render(&JsonLinesReport, &[]), "");
+}
+
+#[test]
+fn total_beyond_fixed_decimal_range_is_displayed_exactly() {
+	let mut statement = statement(1, "", "0.0", "0.0");
+	statement.available = FixedDecimalMAX;
+	statement.held = FixedDecimalMAX;
+	assert_eq!(statement.total().to_string(), "3689348814741910.323");
+}
+
+#[test]
+fn processor_statements_are_sorted_by_client_and_currency() {
+	let mut proc = PaymentsProcessor::default();
+	for tx in [
+		"deposit, 9, 1, 1.0, USD",
+		"deposit, 2, 2, 2.0",
+		"deposit, 9, 3, 3.0",
+		"deposit, 9, 4, 4.0, EUR",
+		"withdrawal, 9, 5, 1.0",
+		"dispute, 9, 5",
+		"chargeback, 9, 5",
+	] {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert!(res.is_ok(), "{}", tx);
+	}
+	let statements = proc.statements();
+	let keys: Vec<_> = statements
+		.iter()
+		.map(|x| (x.client, x.currency.map(|c| c.to_string())))
+		.collect();
+	assert_eq!(
+		keys,
+		vec![
+			(2, None),
+			(9, None),
+			(9, Some("EUR".to_owned())),
+			(9, Some("USD".to_owned()))
+		]
+	);
+	assert!(statements.iter().all(|x| x.locked == (x.client == 9)));
+	let mut out = String::new();
+	proc.write_report(&CsvReport, &mut out).unwrap();
+	assert_eq!(out, format!("{}", proc));
+	let mut out = String::new();
+	proc.write_report(&JsonLinesReport, &mut out).unwrap();
+	assert_eq!(
+		out.lines().nth(1),
+		Some(
+			r#"{"client":9,"currency":null,"available":"2.0","held":"0.0","total":"2.0","locked":true}"#
+		)
+	);
+}
+
+#[test]
+fn processor_reports_stream_clients_in_id_order_across_the_whole_range() {
+	let mut proc = PaymentsProcessor::default().with_undo_depth(1);
+	for tx in [
+		"deposit, 65535, 1, 3.0",
+		"deposit, 0, 2, 1.0",
+		"deposit, 256, 3, 2.0",
+	] {
+		let res = proc.process_transaction(PaymentsTransaction::from_str(tx).unwrap());
+		assert!(res.is_ok(), "{}", tx);
+	}
+	assert_eq!(
+		format!("{}", proc),
+		"client,available,held,total,locked\n0,1.0,0.0,1.0,false\n256,2.0,0.0,2.0,false\n65535,3.0,0.0,3.0,false\n"
+	);
+	let res =
+		proc.process_transaction(PaymentsTransaction::from_str("deposit, 256, 4, 0.5, EUR").unwrap());
+	assert!(res.is_ok());
+	let mut out = String::new();
+	proc.write_report(&CsvReport, &mut out).unwrap();
+	assert_eq!(
+		out,
+		"client,currency,available,held,total,locked\n0,,1.0,0.0,1.0,false\n256,,2.0,0.0,2.0,false\n256,EUR,0.5,0.0,0.5,false\n65535,,3.0,0.0,3.0,false\n"
+	);
+	assert_eq!(out, render(&CsvReport, &proc.statements()));
+	//closing the only balance in a named currency drops the currency column again
+	assert_eq!(proc.undo_last(1), Ok(()));
+	assert!(format!("{}", proc).starts_with("client,available,held,total,locked\n"));
+}
+
+#[test]
+fn delta_reports_show_both_sides_of_every_changed_balance() {
+	let report = DeltaReport {
+		since: 2,
+		report: 5,
+		changes: vec![
+			AccountChange {
+				client: 1,
+				before: vec![statement(1, "", "1.0", "0.0")],
+				after: vec![
+					statement(1, "", "0.5", "0.5"),
+					statement(1, "EUR", "2.0", "0.0"),
+				],
+			},
+			AccountChange {
+				client: 7,
+				before: vec![],
+				after: vec![statement(7, "", "3.0", "0.0")],
+			},
+		],
+	};
+	assert_eq!(
+		report.to_string(),
+		concat!(
+			"client,currency,before_available,before_held,before_total,before_locked,available,held,total,locked\n",
+			"1,,1.0,0.0,1.0,false,0.5,0.5,1.0,false\n",
+			"1,EUR,,,,,2.0,0.0,2.0,false\n",
+			"7,,,,,,3.0,0.0,3.0,false\n"
+		)
+	);
+	let mut out = String::new();
+	report.write_json(&mut out).unwrap();
+	assert_eq!(
+		out,
+		concat!(
+			r#"{"report":5,"since":2,"changes":[{"client":1,"before":["#,
+			r#"{"client":1,"currency":null,"available":"1.0","held":"0.0","total":"1.0","locked":false}],"after":["#,
+			r#"{"client":1,"currency":null,"available":"0.5","held":"0.5","total":"1.0","locked":false},"#,
+			r#"{"client":1,"currency":"EUR","available":"2.0","held":"0.0","total":"2.0","locked":false}]},"#,
+			r#"{"client":7,"before":[],"after":["#,
+			r#"{"client":7,"currency":null,"available":"3.0","held":"0.0","total":"3.0","locked":false}]}]}"#,
+			"\n"
+		)
+	);
+}
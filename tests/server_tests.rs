@@ -0,0 +1,71 @@
+mod common;
+
+use common::Client;
+use std::net::TcpStream;
+
+impl Client<TcpStream> {
+	//the report lines, up to the empty line ending it
+	fn report(&mut self) -> String {
+		self.send("report");
+		self.block().concat()
+	}
+}
+
+#[test]
+fn every_line_is_acknowledged_or_rejected_with_its_error() {
+	let (_server, addr) = common::serve("serve", &[]);
+	let mut client = Client::connect(&addr);
+	for (line, reply) in [
+		("deposit, 1, 1, 10.0", "ok\n"),
+		("withdrawal, 1, 2, 20.0", "err NoAvailableFunds\n"),
+		("bogus", "err UnknownTransactionType\n"),
+		("deposit, 1, 1, 5.0", "err DuplicateTransaction\n"),
+		("withdrawal, 1, 3, 2.5", "ok\n"),
+	] {
+		client.send(line);
+		assert_eq!(client.reply(), reply, "{}", line);
+	}
+	assert_eq!(
+		client.report(),
+		"client,available,held,total,locked\n1,7.5,0.0,7.5,false\n"
+	);
+}
+
+#[test]
+fn connections_share_the_same_books() {
+	let (_server, addr) = common::serve("serve", &["--output-format", "jsonl"]);
+	let mut first = Client::connect(&addr);
+	let mut second = Client::connect(&addr);
+	first.send("deposit, 1, 1, 10.0");
+	assert_eq!(first.reply(), "ok\n");
+	first.send("withdrawal, 1, 2, 4.0");
+	assert_eq!(first.reply(), "ok\n");
+	second.send("dispute, 1, 2");
+	assert_eq!(second.reply(), "ok\n");
+	assert_eq!(
+		first.report(),
+		"{\"client\":1,\"currency\":null,\"available\":\"6.0\",\"held\":\"4.0\",\"total\":\"10.0\",\"locked\":false}\n"
+	);
+}
+
+#[test]
+fn json_lines_are_accepted_with_the_input_format() {
+	let (_server, addr) = common::serve("serve", &["--input-format", "jsonl"]);
+	let mut client = Client::connect(&addr);
+	client.send("{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"1.5\"}");
+	assert_eq!(client.reply(), "ok\n");
+	client.send("deposit, 1, 2, 1.5");
+	assert_eq!(client.reply(), "err MalformedJson\n");
+}
+
+#[test]
+fn an_overlong_line_closes_the_connection_only() {
+	let (_server, addr) = common::serve("serve", &["--tolerance", "32"]);
+	let mut client = Client::connect(&addr);
+	client.send(&"deposit, 1, 1, 1.0 ".repeat(10));
+	assert_eq!(client.reply(), "err ToleranceExceeded\n");
+	assert_eq!(client.reply(), "");
+	let mut client = Client::connect(&addr);
+	client.send("deposit, 1, 1, 1.0");
+	assert_eq!(client.reply(), "ok\n");
+}
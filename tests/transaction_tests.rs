@@ -1,6 +1,8 @@
 extern crate lib;
 
-use lib::{FixedDecimal, PaymentsTransaction, TransactionError, TransactionPayload};
+use lib::{
+	Currency, FixedDecimal, PaymentsTransaction, TransactionError, TransactionPayload, UnknownFields,
+};
 use std::str::FromStr;
 
 #[test]
@@ -20,6 +22,9 @@ fn parse_valid_deposit_pattern_is_ok() {
 fn parse_valid_deposit_without_amount_is_err() {
 	let tx = PaymentsTransaction::from_str("deposit, 321, 1");
 	assert_eq!(tx, Err(TransactionError::MissingTransactionAmount));
+	//an empty amount section is there, just not a number
+	let tx = PaymentsTransaction::from_str("deposit, 321, 1, ");
+	assert_eq!(tx, Err(TransactionError::CouldNotParseSection));
 }
 
 #[test]
@@ -48,7 +53,7 @@ fn parse_valid_dispute_pattern_is_ok() {
 	let tx = tx.unwrap();
 	assert_eq!(tx.client, 111);
 	assert_eq!(tx.tx, 321);
-	assert_eq!(tx.payload, TransactionPayload::Dispute);
+	assert_eq!(tx.payload, TransactionPayload::Dispute(None));
 }
 
 #[test]
@@ -58,7 +63,7 @@ fn parse_valid_resolve_pattern_is_ok() {
 	let tx = tx.unwrap();
 	assert_eq!(tx.client, 666);
 	assert_eq!(tx.tx, 777);
-	assert_eq!(tx.payload, TransactionPayload::Resolve);
+	assert_eq!(tx.payload, TransactionPayload::Resolve(None));
 }
 
 #[test]
@@ -68,7 +73,144 @@ fn parse_valid_chargeback_pattern_is_ok() {
 	let tx = tx.unwrap();
 	assert_eq!(tx.client, 111);
 	assert_eq!(tx.tx, 321);
-	assert_eq!(tx.payload, TransactionPayload::ChargeBack);
+	assert_eq!(tx.payload, TransactionPayload::ChargeBack(None));
+}
+
+#[test]
+fn parse_dispute_with_empty_amount_is_ok() {
+	let tx = PaymentsTransaction::from_str("dispute, 111, 321, ");
+	assert_eq!(tx.map(|x| x.payload), Ok(TransactionPayload::Dispute(None)));
+}
+
+#[test]
+fn parse_partial_dispute_pattern_is_ok() {
+	let tx = PaymentsTransaction::from_str("dispute, 111, 321, 12.5");
+	assert_eq!(
+		tx.map(|x| x.payload),
+		Ok(TransactionPayload::Dispute(Some(
+			FixedDecimal::from_str("12.5").unwrap()
+		)))
+	);
+}
+
+#[test]
+fn parse_partial_resolve_pattern_is_ok() {
+	let tx = PaymentsTransaction::from_str("resolve, 111, 321, 0.5");
+	assert_eq!(
+		tx.map(|x| x.payload),
+		Ok(TransactionPayload::Resolve(Some(
+			FixedDecimal::from_str("0.5").unwrap()
+		)))
+	);
+}
+
+#[test]
+fn parse_partial_chargeback_pattern_is_ok() {
+	let tx = PaymentsTransaction::from_str("chargeback, 111, 321, 7.0");
+	assert_eq!(
+		tx.map(|x| x.payload),
+		Ok(TransactionPayload::ChargeBack(Some(
+			FixedDecimal::from_str("7.0").unwrap()
+		)))
+	);
+}
+
+#[test]
+fn parse_dispute_with_incorrect_amount_is_err() {
+	let tx = PaymentsTransaction::from_str("dispute, 111, 321, 7");
+	assert_eq!(tx, Err(TransactionError::CouldNotParseSection));
+}
+
+#[test]
+fn parse_dispute_with_extra_trailing_sections_is_err() {
+	let tx = PaymentsTransaction::from_str("dispute, 111, 321, 7.0, 1.0");
+	assert_eq!(tx, Err(TransactionError::UnexpectedTrailingSection));
+}
+
+#[test]
+fn parse_valid_representment_pattern_is_ok() {
+	let tx = PaymentsTransaction::from_str("representment, 111, 321");
+	assert_eq!(tx.map(|x| x.payload), Ok(TransactionPayload::Representment));
+}
+
+#[test]
+fn parse_valid_prearbitration_pattern_is_ok() {
+	let tx = PaymentsTransaction::from_str("prearbitration, 111, 321,");
+	assert_eq!(
+		tx.map(|x| x.payload),
+		Ok(TransactionPayload::PreArbitration)
+	);
+}
+
+#[test]
+fn parse_representment_with_amount_is_err() {
+	let tx = PaymentsTransaction::from_str("representment, 111, 321, 1.0");
+	assert_eq!(tx, Err(TransactionError::UnexpectedTrailingSection));
+}
+
+#[test]
+fn parse_deposit_without_currency_has_default_currency() {
+	let tx = PaymentsTransaction::from_str("deposit, 321, 1, 100.0,");
+	assert_eq!(tx.map(|x| x.currency), Ok(None));
+}
+
+#[test]
+fn parse_deposit_with_currency_is_ok() {
+	let tx = PaymentsTransaction::from_str("deposit, 321, 1, 100.0, EUR");
+	assert_eq!(
+		tx.map(|x| x.currency),
+		Ok(Some(Currency::from_str("EUR").unwrap()))
+	);
+}
+
+#[test]
+fn parse_withdrawal_with_currency_is_ok() {
+	let tx = PaymentsTransaction::from_str("withdrawal, 321, 1, 100.0, USD");
+	assert_eq!(
+		tx.map(|x| x.currency),
+		Ok(Some(Currency::from_str("USD").unwrap()))
+	);
+}
+
+#[test]
+fn parse_deposit_with_invalid_currency_is_err() {
+	let tx = PaymentsTransaction::from_str("deposit, 321, 1, 100.0, qwerty");
+	assert_eq!(tx, Err(TransactionError::InvalidCurrency));
+}
+
+#[test]
+fn parse_dispute_with_currency_is_err() {
+	let tx = PaymentsTransaction::from_str("dispute, 321, 1, , EUR");
+	assert_eq!(tx, Err(TransactionError::UnexpectedTrailingSection));
+}
+
+#[test]
+fn parse_valid_convert_pattern_is_ok() {
+	let tx = PaymentsTransaction::from_str("convert, 321, 1, 100.0, EUR, USD").unwrap();
+	assert_eq!(tx.currency, Some(Currency::from_str("EUR").unwrap()));
+	assert_eq!(
+		tx.payload,
+		TransactionPayload::Convert(
+			FixedDecimal::from_str("100.0").unwrap(),
+			Currency::from_str("USD").unwrap()
+		)
+	);
+}
+
+#[test]
+fn parse_convert_without_currencies_is_err() {
+	let tx = PaymentsTransaction::from_str("convert, 321, 1, 100.0, EUR");
+	assert_eq!(tx, Err(TransactionError::MissingCurrency));
+	let tx = PaymentsTransaction::from_str("convert, 321, 1, 100.0, , USD");
+	assert_eq!(tx, Err(TransactionError::MissingCurrency));
+	let tx = PaymentsTransaction::from_str("convert, 321, 1, , EUR, USD");
+	assert_eq!(tx, Err(TransactionError::CouldNotParseSection));
+}
+
+#[test]
+fn parse_deposit_with_target_currency_is_err() {
+	let tx = PaymentsTransaction::from_str("deposit, 321, 1, 100.0, EUR, USD");
+	assert_eq!(tx, Err(TransactionError::UnexpectedTrailingSection));
 }
 
 #[test]
@@ -79,7 +221,7 @@ fn parse_unknown_transaction_type_is_err() {
 
 #[test]
 fn transaction_with_extra_trailing_sections_is_err() {
-	let tx = PaymentsTransaction::from_str("deposit, 111, 321, 456.456, qwerty");
+	let tx = PaymentsTransaction::from_str("deposit, 111, 321, 456.456, EUR, qwerty");
 	assert_eq!(tx, Err(TransactionError::UnexpectedTrailingSection));
 }
 
@@ -100,3 +242,264 @@ fn transaction_with_out_of_bounds_id_is_err() {
 	let tx = PaymentsTransaction::from_str("withdrawal, 0, 999999999999999999, 100.0");
 	assert_eq!(tx, Err(TransactionError::OutOfBoundsSection));
 }
+
+#[test]
+fn parse_transactions_with_idempotency_key_is_ok() {
+	for (tx, key) in [
+		("deposit, 321, 1, 100.0, , , retry-1", Some("retry-1")),
+		("withdrawal, 321, 1, 100.0, EUR, , retry-2", Some("retry-2")),
+		("convert, 321, 1, 100.0, EUR, USD, retry-3", Some("retry-3")),
+		("dispute, 321, 1, , , , retry-4", Some("retry-4")),
+		("representment, 321, 1, , , , retry-5", Some("retry-5")),
+		("deposit, 321, 1, 100.0, , , ", None),
+		("deposit, 321, 1, 100.0", None),
+	] {
+		let tx = PaymentsTransaction::from_str(tx).map(|x| x.idempotency_key);
+		assert_eq!(tx, Ok(key.map(|x| x.to_owned())));
+	}
+	let tx = PaymentsTransaction::from_str("deposit, 321, 1, 100.0, EUR, , k").unwrap();
+	assert_eq!(tx.currency, Some(Currency::from_str("EUR").unwrap()));
+}
+
+#[test]
+fn parse_transaction_with_invalid_idempotency_key_is_err() {
+	let too_long = format!("deposit, 321, 1, 100.0, , , {}", "k".repeat(65));
+	for tx in [
+		"deposit, 321, 1, 100.0, , , retry 1",
+		"deposit, 321, 1, 100.0, , , clé",
+		too_long.as_str(),
+	] {
+		let tx = PaymentsTransaction::from_str(tx);
+		assert_eq!(tx, Err(TransactionError::InvalidIdempotencyKey));
+	}
+	let max_len = format!("deposit, 321, 1, 100.0, , , {}", "k".repeat(64));
+	assert!(PaymentsTransaction::from_str(&max_len).is_ok());
+}
+
+#[test]
+fn json_transactions_round_trip_through_the_comma_separated_form() {
+	for json in [
+		r#"{"type":"deposit","client":1,"tx":1,"amount":"1.0","idempotency_key":"a;b"}"#,
+		r#"{"type":"dispute","client":1,"tx":1,"idempotency_key":"\"k\""}"#,
+		r#"{"type":"convert","client":1,"tx":2,"amount":"1.5","currency":"EUR","target_currency":"USD"}"#,
+	] {
+		let tx = PaymentsTransaction::from_json(json, UnknownFields::Reject).unwrap();
+		assert_eq!(
+			PaymentsTransaction::from_str(&tx.to_string()),
+			Ok(tx),
+			"{}",
+			json
+		);
+	}
+}
+
+#[test]
+fn idempotency_key_must_follow_empty_sections() {
+	for tx in [
+		"dispute, 321, 1, 1.0, EUR, , retry-1",
+		"representment, 321, 1, 1.0, , , retry-1",
+		"deposit, 321, 1, 100.0, EUR, USD, retry-1",
+		"deposit, 321, 1, 100.0, , , retry-1, ",
+	] {
+		let tx = PaymentsTransaction::from_str(tx);
+		assert_eq!(tx, Err(TransactionError::UnexpectedTrailingSection));
+	}
+}
+
+#[test]
+fn parse_json_transactions_matches_csv() {
+	for (json, csv) in [
+		(
+			r#"{"type":"deposit","client":1,"tx":5,"amount":"1.5"}"#,
+			"deposit, 1, 5, 1.5",
+		),
+		(
+			r#" { "tx" : 5 , "amount" : "1.5" , "client" : 1 , "type" : "withdrawal" , "currency" : "EUR" } "#,
+			"withdrawal, 1, 5, 1.5, EUR",
+		),
+		(
+			r#"{"type":"dispute","client":1,"tx":5,"amount":null,"currency":null}"#,
+			"dispute, 1, 5",
+		),
+		(
+			r#"{"type":"chargeback","client":1,"tx":5,"amount":"0.25"}"#,
+			"chargeback, 1, 5, 0.25",
+		),
+		(
+			r#"{"type":"representment","client":65535,"tx":4294967295}"#,
+			"representment, 65535, 4294967295",
+		),
+		(
+			r#"{"type":"convert","client":1,"tx":5,"amount":"1.5","currency":"EUR","target_currency":"USD"}"#,
+			"convert, 1, 5, 1.5, EUR, USD",
+		),
+		(
+			r#"{"type":"deposit","client":1,"tx":5,"amount":"1.5","idempotency_key":"k\"\\\u0041"}"#,
+			r#"deposit, 1, 5, 1.5, , , k"\A"#,
+		),
+	] {
+		let tx = PaymentsTransaction::from_json(json, UnknownFields::Reject);
+		assert_eq!(
+			tx,
+			Ok(PaymentsTransaction::from_str(csv).unwrap()),
+			"{}",
+			json
+		);
+	}
+}
+
+#[test]
+fn parse_json_transaction_keeps_csv_strictness() {
+	for (json, err) in [
+		(
+			r#"{"type":"deposit","client":1,"tx":5,"amount":1.5}"#,
+			TransactionError::CouldNotParseSection,
+		),
+		(
+			r#"{"type":"deposit","client":"1","tx":5,"amount":"1.5"}"#,
+			TransactionError::CouldNotParseSection,
+		),
+		(
+			r#"{"type":"deposit","client":-1,"tx":5,"amount":"1.5"}"#,
+			TransactionError::CouldNotParseSection,
+		),
+		(
+			r#"{"type":"deposit","client":1e2,"tx":5,"amount":"1.5"}"#,
+			TransactionError::CouldNotParseSection,
+		),
+		(
+			r#"{"type":"deposit","client":65536,"tx":5,"amount":"1.5"}"#,
+			TransactionError::OutOfBoundsSection,
+		),
+		(
+			r#"{"type":"deposit","client":1,"tx":5,"amount":" 1.5"}"#,
+			TransactionError::CouldNotParseSection,
+		),
+		(
+			r#"{"type":"deposit","client":1,"tx":5}"#,
+			TransactionError::MissingTransactionAmount,
+		),
+		(
+			r#"{"type":"deposit","tx":5,"amount":"1.5"}"#,
+			TransactionError::EmptySection,
+		),
+		(
+			r#"{"type":"Deposit","client":1,"tx":5,"amount":"1.5"}"#,
+			TransactionError::UnknownTransactionType,
+		),
+		(
+			r#"{"type":"deposit","client":1,"tx":5,"amount":"1.5","target_currency":"USD"}"#,
+			TransactionError::UnexpectedTrailingSection,
+		),
+		(
+			r#"{"type":"representment","client":1,"tx":5,"amount":"1.5"}"#,
+			TransactionError::UnexpectedTrailingSection,
+		),
+		(
+			r#"{"type":"deposit","client":1,"tx":5,"amount":"1.5","currency":"eur"}"#,
+			TransactionError::InvalidCurrency,
+		),
+		(
+			r#"{"type":"deposit","client":1,"tx":5,"amount":"1.5","idempotency_key":"a b"}"#,
+			TransactionError::InvalidIdempotencyKey,
+		),
+		(
+			r#"{"type":"deposit","client":1,"tx":5,"amount":"1.5","idempotency_key":"a,b"}"#,
+			TransactionError::InvalidIdempotencyKey,
+		),
+		(
+			r#"{"type":"deposit","client":1,"tx":5,"tx":6,"amount":"1.5"}"#,
+			TransactionError::DuplicateField,
+		),
+		(
+			r#"{"type":"deposit","client":1,"tx":5,"amount":"1.5","note":"x"}"#,
+			TransactionError::UnknownField,
+		),
+	] {
+		let tx = PaymentsTransaction::from_json(json, UnknownFields::Reject);
+		assert_eq!(tx, Err(err), "{}", json);
+	}
+}
+
+#[test]
+fn parse_malformed_json_is_err() {
+	let too_deep = format!(
+		r#"{{"type":"deposit","client":1,"tx":5,"amount":"1.5","x":{}{}}}"#,
+		"[".repeat(17),
+		"]".repeat(17)
+	);
+	for json in [
+		"",
+		"deposit, 1, 5, 1.5",
+		r#"["deposit",1,5,"1.5"]"#,
+		r#"{"type":"deposit","client":1,"tx":5,"amount":"1.5""#,
+		r#"{"type":"deposit","client":1,"tx":5,"amount":"1.5",}"#,
+		r#"{"type":"deposit","client":1,"tx":5,"amount":"1.5"} {}"#,
+		r#"{"type":"deposit","client":01,"tx":5,"amount":"1.5"}"#,
+		r#"{"type":"deposit","client":1,"tx":5,"amount":"1.5","x":tru}"#,
+		r#"{"type":"deposit","client":1,"tx":5,"amount":"1.5","x":[1,]}"#,
+		r#"{type:"deposit","client":1,"tx":5,"amount":"1.5"}"#,
+		r#"{"type":"deposit","client":1,"tx":5,"amount":"1.5","x":"\q"}"#,
+		r#"{"type":"deposit","client":1,"tx":5,"amount":"1.5","x":"\ud800"}"#,
+		"{\"type\":\"dep\tosit\",\"client\":1,\"tx\":5,\"amount\":\"1.5\"}",
+		too_deep.as_str(),
+	] {
+		let tx = PaymentsTransaction::from_json(json, UnknownFields::Ignore);
+		assert_eq!(tx, Err(TransactionError::MalformedJson), "{}", json);
+	}
+}
+
+#[test]
+fn parse_json_with_ignored_unknown_fields_is_ok() {
+	let json = concat!(
+		r#"{"source":"bus","seq":-1.5e3,"ok":true,"none":null,"#,
+		r#""meta":{"tags":["a",{"b":[]}],"x":{}},"type":"deposit","client":1,"tx":5,"amount":"1.5","#,
+		r#""note":"caf\u00e9 \ud83d\ude00 ünïcode"}"#
+	);
+	let tx = PaymentsTransaction::from_json(json, UnknownFields::Ignore);
+	assert_eq!(
+		tx,
+		Ok(PaymentsTransaction::from_str("deposit, 1, 5, 1.5").unwrap())
+	);
+	let tx = PaymentsTransaction::from_json(json, UnknownFields::Reject);
+	assert_eq!(tx, Err(TransactionError::UnknownField));
+	//known fields are still checked once unknown ones are ignored
+	let json = r#"{"type":"deposit","client":1,"tx":5,"amount":"1.5","currency":true,"x":1}"#;
+	let tx = PaymentsTransaction::from_json(json, UnknownFields::Ignore);
+	assert_eq!(tx, Err(TransactionError::CouldNotParseSection));
+}
+
+#[test]
+fn parse_json_array_keeps_every_transaction_apart() {
+	let json = concat!(
+		" [{\"type\":\"deposit\",\"client\":1,\"tx\":5,\"amount\":\"1.5\"},\n",
+		r#"{"type":"deposit","client":1,"tx":5,"amount":1.5}, 1, []"#,
+		r#",{"type":"withdrawal","client":1,"tx":6,"amount":"1.0","x":[{}]}] "#
+	);
+	let trs = PaymentsTransaction::from_json_array(json, UnknownFields::Ignore).unwrap();
+	assert_eq!(
+		trs,
+		[
+			PaymentsTransaction::from_str("deposit, 1, 5, 1.5"),
+			Err(TransactionError::CouldNotParseSection),
+			Err(TransactionError::MalformedJson),
+			Err(TransactionError::MalformedJson),
+			PaymentsTransaction::from_str("withdrawal, 1, 6, 1.0"),
+		]
+	);
+	assert_eq!(
+		PaymentsTransaction::from_json_array("[]", UnknownFields::Reject),
+		Ok(Vec::new())
+	);
+	for json in [
+		"",
+		"{}",
+		"[",
+		"[{},]",
+		"[{}] []",
+		r#"[{"type":"deposit","client":1,"tx":5,"amount":"1.5"}"#,
+	] {
+		let trs = PaymentsTransaction::from_json_array(json, UnknownFields::Reject);
+		assert_eq!(trs, Err(TransactionError::MalformedJson), "{}", json);
+	}
+}
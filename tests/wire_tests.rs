@@ -0,0 +1,185 @@
+extern crate lib;
+
+use lib::{
+	PaymentsTransaction, WireError, WireMAGIC, WireRECORDLEN, WireReader, WireVERSION, WireWriter,
+};
+use std::str::FromStr;
+
+//bitwise CRC-32 (IEEE 802.3), independent from the table driven one of the crate
+fn crc32(bytes: &[u8]) -> u32 {
+	let mut crc = !0u32;
+	for byte in bytes {
+		crc ^= *byte as u32;
+		for _ in 0..8 {
+			crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+		}
+	}
+	!crc
+}
+
+//rewrites a record field and fixes its checksum so that only the field itself is wrong
+fn tampered(tx: &str, offset: usize, bytes: &[u8]) -> [u8; WireRECORDLEN] {
+	let mut record = PaymentsTransaction::from_str(tx)
+		.unwrap()
+		.to_wire()
+		.unwrap();
+	record[offset..offset + bytes.len()].copy_from_slice(bytes);
+	let checksum = crc32(&record[..WireRECORDLEN - 4]);
+	record[WireRECORDLEN - 4..].copy_from_slice(&checksum.to_le_bytes());
+	record
+}
+
+const TRANSACTIONS: [&str; 12] = [
+	"deposit, 1, 1, 100.0",
+	"deposit, 65535, 4294967295, 1844674407370955.1615, EUR",
+	"withdrawal, 2, 3, 0.0001, USD",
+	"dispute, 1, 1",
+	"dispute, 1, 1, 0.5",
+	"resolve, 1, 1",
+	"resolve, 1, 1, 0.0",
+	"chargeback, 1, 1, 2.25",
+	"chargeback, 0, 0",
+	"representment, 1, 1",
+	"prearbitration, 1, 1",
+	"convert, 1, 7, 12.3456, EUR, USD",
+];
+
+#[test]
+fn every_transaction_round_trips_through_a_record() {
+	for tx in TRANSACTIONS {
+		let tx = PaymentsTransaction::from_str(tx).unwrap();
+		let record = tx.to_wire().unwrap();
+		assert_eq!(record.len(), WireRECORDLEN);
+		assert_eq!(
+			u32::from_le_bytes(record[WireRECORDLEN - 4..].try_into().unwrap()),
+			crc32(&record[..WireRECORDLEN - 4])
+		);
+		assert_eq!(PaymentsTransaction::from_wire(&record), Ok(tx));
+	}
+}
+
+#[test]
+fn record_layout_is_little_endian() {
+	let tx = PaymentsTransaction::from_str("withdrawal, 258, 16909060, 0.0001, EUR").unwrap();
+	let record = tx.to_wire().unwrap();
+	assert_eq!(
+		record[..21],
+		[0x82, 2, 1, 4, 3, 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, b'E', b'U', b'R', 0, 0, 0]
+	);
+}
+
+#[test]
+fn transaction_with_idempotency_key_cannot_be_encoded() {
+	let tx = PaymentsTransaction::from_str("deposit, 1, 1, 100.0, , , key").unwrap();
+	assert_eq!(tx.to_wire(), Err(WireError::KeyNotEncodable));
+}
+
+#[test]
+fn corrupt_records_are_err() {
+	let mut flipped = PaymentsTransaction::from_str("deposit, 1, 1, 100.0")
+		.unwrap()
+		.to_wire()
+		.unwrap();
+	flipped[9] ^= 0x10;
+	assert_eq!(
+		PaymentsTransaction::from_wire(&flipped),
+		Err(WireError::ChecksumMismatch)
+	);
+	for (record, err) in [
+		(
+			tampered("deposit, 1, 1, 100.0", 0, &[0x89]),
+			WireError::UnknownTransactionType,
+		),
+		(
+			tampered("deposit, 1, 1, 100.0", 0, &[0x00]),
+			WireError::UnknownTransactionType,
+		),
+		//deposits need an amount
+		(
+			tampered("deposit, 1, 1, 100.0", 0, &[0x01]),
+			WireError::InvalidRecord,
+		),
+		//an amount without the amount flag
+		(tampered("dispute, 1, 1", 7, &[1]), WireError::InvalidRecord),
+		(
+			tampered("representment, 1, 1", 0, &[0x86]),
+			WireError::InvalidRecord,
+		),
+		(
+			tampered("dispute, 1, 1", 15, b"EUR"),
+			WireError::InvalidRecord,
+		),
+		(
+			tampered("deposit, 1, 1, 100.0", 18, b"USD"),
+			WireError::InvalidRecord,
+		),
+		(
+			tampered("convert, 1, 1, 1.0, EUR, USD", 18, &[0, 0, 0]),
+			WireError::InvalidRecord,
+		),
+		(
+			tampered("deposit, 1, 1, 100.0, EUR", 15, b"eur"),
+			WireError::InvalidCurrency,
+		),
+		(
+			tampered("deposit, 1, 1, 100.0, EUR", 15, &[0xFF, 0, 0]),
+			WireError::InvalidCurrency,
+		),
+	] {
+		assert_eq!(PaymentsTransaction::from_wire(&record), Err(err));
+	}
+}
+
+#[test]
+fn stream_round_trips_and_skips_corrupt_records() {
+	let mut writer = WireWriter::new(Vec::new()).unwrap();
+	for tx in TRANSACTIONS {
+		writer
+			.write(&PaymentsTransaction::from_str(tx).unwrap())
+			.unwrap();
+	}
+	let mut stream = writer.into_inner().unwrap();
+	assert_eq!(stream[..4], WireMAGIC);
+	assert_eq!(stream[4], WireVERSION);
+	assert_eq!(stream.len(), 5 + TRANSACTIONS.len() * WireRECORDLEN);
+	//corrupting the 3rd record only loses that one
+	stream[5 + 2 * WireRECORDLEN + 1] ^= 0xFF;
+	let mut reader = WireReader::new(stream.as_slice()).unwrap();
+	for (i, tx) in TRANSACTIONS.iter().enumerate() {
+		let expected = match i {
+			2 => Err(WireError::ChecksumMismatch),
+			_ => Ok(PaymentsTransaction::from_str(tx).unwrap()),
+		};
+		assert_eq!(reader.get_next(), expected, "{}", tx);
+	}
+	assert_eq!(reader.get_next(), Err(WireError::EOFReached));
+	assert_eq!(reader.get_next(), Err(WireError::EOFReached));
+}
+
+#[test]
+fn truncated_stream_reports_the_partial_record_once() {
+	let mut writer = WireWriter::new(Vec::new()).unwrap();
+	let tx = PaymentsTransaction::from_str("deposit, 1, 1, 100.0").unwrap();
+	writer.write(&tx).unwrap();
+	writer.write(&tx).unwrap();
+	let stream = writer.into_inner().unwrap();
+	let mut reader = WireReader::new(&stream[..stream.len() - 3]).unwrap();
+	assert_eq!(reader.get_next(), Ok(tx));
+	assert_eq!(reader.get_next(), Err(WireError::TruncatedRecord));
+	assert_eq!(reader.get_next(), Err(WireError::EOFReached));
+}
+
+#[test]
+fn stream_header_is_checked() {
+	for (stream, err) in [
+		(&b""[..], WireError::BadMagic),
+		(&b"MPT"[..], WireError::BadMagic),
+		(&b"MPTY\x01"[..], WireError::BadMagic),
+		(&b"deposit, 1, 1, 100.0"[..], WireError::BadMagic),
+		(&b"MPTX\x02"[..], WireError::UnsupportedVersion(2)),
+	] {
+		assert_eq!(WireReader::new(stream).err(), Some(err));
+	}
+	let mut reader = WireReader::new(&b"MPTX\x01"[..]).unwrap();
+	assert_eq!(reader.get_next(), Err(WireError::EOFReached));
+}